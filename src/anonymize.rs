@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use ethers::types::{Bytes, H160, H256};
+use fil_actor_eam::EthAddress;
+use fvm_shared::crypto::hash::SupportedHashes;
+use serde::{Deserialize, Serialize};
+
+use crate::extractor::opcodes::{OP_CREATE, OP_CREATE2};
+use crate::extractor::precompiles::is_precompile;
+use crate::extractor::types::{
+    EthAccesses, EthAccountState, EthCallFrame, EthState, EthTransactionTestVector,
+};
+use crate::util::{compute_address_create, hash, hash_20, masked_id};
+
+/// Number of low storage slots tried when recognizing `keccak(address . slot)` mapping keys.
+const MAPPING_SLOT_SEARCH: u64 = 64;
+
+const OP_PUSH1: u8 = 0x60;
+const OP_PUSH32: u8 = 0x7f;
+
+/// An occurrence of an account address which could not be rewritten safely,
+/// the original address is left in place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnresolvedOccurrence {
+    pub address: H160,
+    pub location: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnonymizeReport {
+    /// number of accounts mapped to synthetic addresses
+    pub remapped_accounts: usize,
+    /// number of address occurrences rewritten in code, calldata, storage and return data
+    pub rewritten_occurrences: usize,
+    pub unresolved: Vec<UnresolvedOccurrence>,
+}
+
+/// Consistently remaps Ethereum account addresses to synthetic ones.
+///
+/// Synthetic addresses are derived from `keccak(seed . address)`, so the same seed maps
+/// an account to the same synthetic address across vectors. Contracts deployed by CREATE
+/// are remapped to the address computed from their remapped creator, otherwise FEVM would
+/// deploy them elsewhere.
+pub struct AddressRemapper {
+    seed: Vec<u8>,
+    mapping: BTreeMap<H160, H160>,
+    storage_keys: HashMap<H256, H256>,
+}
+
+impl AddressRemapper {
+    pub fn new(seed: &[u8]) -> Self {
+        Self {
+            seed: seed.to_vec(),
+            mapping: BTreeMap::new(),
+            storage_keys: HashMap::new(),
+        }
+    }
+
+    /// original address -> synthetic address
+    pub fn mapping(&self) -> &BTreeMap<H160, H160> {
+        &self.mapping
+    }
+
+    pub fn anonymize(
+        &mut self,
+        input: &EthTransactionTestVector,
+    ) -> (EthTransactionTestVector, AnonymizeReport) {
+        let mut report = AnonymizeReport::default();
+        self.build_mapping(input, &mut report);
+        self.build_storage_keys();
+        report.remapped_accounts = self.mapping.iter().filter(|(k, v)| k != v).count();
+
+        let mut output = input.clone();
+        output.from = self.map_address(&input.from);
+        if !input.create_contract() {
+            output.to = self.map_address(&input.to);
+        }
+        output.coinbase = self.map_address(&input.coinbase);
+        output.input = if input.create_contract() {
+            self.rewrite_code(&input.input, "input", &mut report)
+        } else {
+            self.rewrite_words(&input.input, "input", &mut report)
+        };
+        output.return_value = self.rewrite_words(&input.return_value, "return_value", &mut report);
+        output.prestate = self.rewrite_state(&input.prestate, "prestate", &mut report);
+        output.poststate = self.rewrite_state(&input.poststate, "poststate", &mut report);
+        output.accesses = input
+            .accesses
+            .as_ref()
+            .map(|accesses| self.rewrite_accesses(accesses));
+        output.call_tree = input
+            .call_tree
+            .as_ref()
+            .map(|frame| self.rewrite_call_frame(frame, "call_tree", &mut report));
+        for (i, call) in output.precompile_calls.iter_mut().enumerate() {
+            let location = format!("precompile_calls[{}]", i);
+            call.input =
+                self.rewrite_words(&call.input, &format!("{}.input", location), &mut report);
+            call.output =
+                self.rewrite_words(&call.output, &format!("{}.output", location), &mut report);
+        }
+        (output, report)
+    }
+
+    fn map_address(&self, addr: &H160) -> H160 {
+        self.mapping.get(addr).cloned().unwrap_or(*addr)
+    }
+
+    fn synthetic_address(&self, addr: &H160) -> H160 {
+        let mut preimage = self.seed.clone();
+        preimage.extend_from_slice(addr.as_bytes());
+        H160(hash_20(&preimage))
+    }
+
+    fn build_mapping(&mut self, input: &EthTransactionTestVector, report: &mut AnonymizeReport) {
+        let mut accounts: HashSet<H160> = HashSet::new();
+        accounts.insert(input.from);
+        accounts.insert(input.coinbase);
+        if !input.create_contract() {
+            accounts.insert(input.to);
+        }
+        accounts.extend(input.prestate.keys());
+        accounts.extend(input.poststate.keys());
+        accounts.retain(|addr| !is_reserved_address(addr));
+
+        // accounts deployed by this transaction, together with the creator and creator nonce
+        // when they can be recomputed through CREATE.
+        let mut created: BTreeMap<H160, Option<(H160, u64)>> = BTreeMap::new();
+        for (addr, state) in &input.poststate {
+            if !accounts.contains(addr) || state.code.is_empty() {
+                continue;
+            }
+            let pre_code_empty = input
+                .prestate
+                .get(addr)
+                .map(|s| s.code.is_empty())
+                .unwrap_or(true);
+            if pre_code_empty {
+                created.insert(*addr, find_creator(input, &accounts, addr));
+            }
+        }
+
+        for addr in &accounts {
+            if !created.contains_key(addr) {
+                self.mapping.insert(*addr, self.synthetic_address(addr));
+            }
+        }
+
+        // creators can be created by the same transaction as well, resolve until fixpoint.
+        loop {
+            let mut progress = false;
+            for (addr, creator) in &created {
+                if self.mapping.contains_key(addr) {
+                    continue;
+                }
+                if let Some((creator, nonce)) = creator {
+                    if let Some(mapped_creator) = self.mapping.get(creator) {
+                        let mapped = compute_address_create(&EthAddress(mapped_creator.0), *nonce);
+                        self.mapping.insert(*addr, H160(mapped.0));
+                        progress = true;
+                    }
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+        for addr in created.keys() {
+            if !self.mapping.contains_key(addr) {
+                self.mapping.insert(*addr, *addr);
+                report.unresolved.push(UnresolvedOccurrence {
+                    address: *addr,
+                    location: String::from("account"),
+                    reason: String::from(
+                        "deployed by CREATE2 or by an unknown creator, address cannot be recomputed",
+                    ),
+                });
+            }
+        }
+    }
+
+    /// Precompute storage keys of solidity mappings keyed by an account address, i.e.
+    /// `keccak(address . slot)` and `keccak(address . keccak(address . slot))`.
+    fn build_storage_keys(&mut self) {
+        let remapped: Vec<(H160, H160)> = self
+            .mapping
+            .iter()
+            .filter(|(k, v)| k != v)
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        let mut first_level = Vec::new();
+        for slot in 0..MAPPING_SLOT_SEARCH {
+            let slot = H256::from_low_u64_be(slot);
+            for (original, mapped) in &remapped {
+                let key = mapping_key(original, &slot);
+                let mapped_key = mapping_key(mapped, &slot);
+                self.storage_keys.insert(key, mapped_key);
+                first_level.push((key, mapped_key));
+            }
+        }
+        for (key, mapped_key) in &first_level {
+            for (original, mapped) in &remapped {
+                self.storage_keys
+                    .insert(mapping_key(original, key), mapping_key(mapped, mapped_key));
+            }
+        }
+    }
+
+    fn rewrite_state(
+        &self,
+        state: &EthState,
+        location: &str,
+        report: &mut AnonymizeReport,
+    ) -> EthState {
+        let mut output = EthState::new();
+        for (addr, account) in state {
+            let location = format!("{}.{:?}", location, addr);
+            let mut storage = BTreeMap::new();
+            for (k, v) in &account.storage {
+                let key = match self.storage_keys.get(k) {
+                    Some(key) => {
+                        report.rewritten_occurrences += 1;
+                        *key
+                    }
+                    None => H256::from_slice(&self.rewrite_word(
+                        &k.0,
+                        &format!("{}.storage_key", location),
+                        report,
+                    )),
+                };
+                let value = H256::from_slice(&self.rewrite_word(
+                    &v.0,
+                    &format!("{}.storage[{:?}]", location, k),
+                    report,
+                ));
+                storage.insert(key, value);
+            }
+            output.insert(
+                self.map_address(addr),
+                EthAccountState {
+                    nonce: account.nonce,
+                    balance: account.balance,
+                    code: self.rewrite_code(&account.code, &format!("{}.code", location), report),
+                    storage,
+                },
+            );
+        }
+        output
+    }
+
+    fn rewrite_storage_key(&self, key: &H256) -> H256 {
+        self.storage_keys.get(key).cloned().unwrap_or(*key)
+    }
+
+    fn rewrite_accesses(&self, accesses: &EthAccesses) -> EthAccesses {
+        let map_addresses = |addrs: &BTreeSet<H160>| -> BTreeSet<H160> {
+            addrs.iter().map(|addr| self.map_address(addr)).collect()
+        };
+        let map_slots = |slots: &BTreeMap<H160, BTreeSet<H256>>| -> BTreeMap<H160, BTreeSet<H256>> {
+            slots
+                .iter()
+                .map(|(addr, keys)| {
+                    (
+                        self.map_address(addr),
+                        keys.iter()
+                            .map(|key| self.rewrite_storage_key(key))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+        EthAccesses {
+            storage_reads: map_slots(&accesses.storage_reads),
+            storage_writes: map_slots(&accesses.storage_writes),
+            balances: map_addresses(&accesses.balances),
+            codes: map_addresses(&accesses.codes),
+            accounts: map_addresses(&accesses.accounts),
+        }
+    }
+
+    fn rewrite_call_frame(
+        &self,
+        frame: &EthCallFrame,
+        location: &str,
+        report: &mut AnonymizeReport,
+    ) -> EthCallFrame {
+        let create = frame.call_type == OP_CREATE || frame.call_type == OP_CREATE2;
+        let input_location = format!("{}.input", location);
+        let output_location = format!("{}.output", location);
+        let mut storage_writes = BTreeMap::new();
+        for (k, v) in &frame.storage_writes {
+            let value = self.rewrite_word(&v.0, &format!("{}.storage_writes", location), report);
+            storage_writes.insert(self.rewrite_storage_key(k), H256::from_slice(&value));
+        }
+        EthCallFrame {
+            call_type: frame.call_type.clone(),
+            caller: self.map_address(&frame.caller),
+            callee: self.map_address(&frame.callee),
+            value: frame.value,
+            input: if create {
+                self.rewrite_code(&frame.input, &input_location, report)
+            } else {
+                self.rewrite_words(&frame.input, &input_location, report)
+            },
+            output: if create {
+                self.rewrite_code(&frame.output, &output_location, report)
+            } else {
+                self.rewrite_words(&frame.output, &output_location, report)
+            },
+            gas: frame.gas,
+            gas_used: frame.gas_used,
+            reverted: frame.reverted,
+            storage_writes,
+            calls: frame
+                .calls
+                .iter()
+                .enumerate()
+                .map(|(i, call)| {
+                    self.rewrite_call_frame(call, &format!("{}.calls[{}]", location, i), report)
+                })
+                .collect(),
+            salt: frame.salt,
+        }
+    }
+
+    /// Storage words may pack an address together with other fields at any byte offset.
+    fn rewrite_word(&self, word: &[u8], location: &str, report: &mut AnonymizeReport) -> Vec<u8> {
+        let mut output = word.to_vec();
+        for (pos, original) in self.find_occurrences(word) {
+            let mapped = self.map_address(&original);
+            output[pos..pos + 20].copy_from_slice(mapped.as_bytes());
+            report.rewritten_occurrences += 1;
+            log::debug!("rewrite {:?} at {}[{}]", original, location, pos);
+        }
+        output
+    }
+
+    /// Calldata and return data, only ABI encoded (left padded) addresses are rewritten.
+    fn rewrite_words(&self, data: &Bytes, location: &str, report: &mut AnonymizeReport) -> Bytes {
+        let mut output = data.to_vec();
+        self.rewrite_padded(&mut output, &HashSet::new(), location, report);
+        output.into()
+    }
+
+    /// Bytecode, addresses pushed by PUSH20..PUSH32 (left padded) are rewritten, as well as left padded
+    /// words outside of instructions (e.g. constructor arguments appended to initcode).
+    fn rewrite_code(&self, code: &Bytes, location: &str, report: &mut AnonymizeReport) -> Bytes {
+        let mut output = code.to_vec();
+        let mut handled = HashSet::new();
+        let mut pc = 0;
+        while pc < code.len() {
+            let op = code[pc];
+            if (OP_PUSH1..=OP_PUSH32).contains(&op) {
+                let size = (op - OP_PUSH1 + 1) as usize;
+                let start = pc + 1;
+                if size >= 20 && start + size <= code.len() {
+                    let value = &code[start..start + size];
+                    let prefix = size - 20;
+                    if value[..prefix].iter().all(|b| *b == 0) {
+                        let candidate = H160::from_slice(&value[prefix..]);
+                        if self.mapping.contains_key(&candidate) {
+                            let mapped = self.map_address(&candidate);
+                            output[start + prefix..start + size].copy_from_slice(mapped.as_bytes());
+                            handled.insert(start + prefix);
+                            report.rewritten_occurrences += 1;
+                        }
+                    }
+                }
+                pc = start + size;
+            } else {
+                pc += 1;
+            }
+        }
+        self.rewrite_padded(&mut output, &handled, location, report);
+        output.into()
+    }
+
+    fn rewrite_padded(
+        &self,
+        data: &mut Vec<u8>,
+        handled: &HashSet<usize>,
+        location: &str,
+        report: &mut AnonymizeReport,
+    ) {
+        for (pos, original) in self.find_occurrences(data) {
+            if handled.contains(&pos) {
+                continue;
+            }
+            if pos >= 12 && data[pos - 12..pos].iter().all(|b| *b == 0) {
+                let mapped = self.map_address(&original);
+                data[pos..pos + 20].copy_from_slice(mapped.as_bytes());
+                report.rewritten_occurrences += 1;
+            } else {
+                report.unresolved.push(UnresolvedOccurrence {
+                    address: original,
+                    location: format!("{}[{}]", location, pos),
+                    reason: String::from("address is not an ABI encoded word or a push constant"),
+                });
+            }
+        }
+    }
+
+    fn find_occurrences(&self, data: &[u8]) -> Vec<(usize, H160)> {
+        let mut occurrences = Vec::new();
+        if data.len() < 20 {
+            return occurrences;
+        }
+        for pos in 0..=data.len() - 20 {
+            let candidate = H160::from_slice(&data[pos..pos + 20]);
+            if let Some(mapped) = self.mapping.get(&candidate) {
+                if mapped != &candidate {
+                    occurrences.push((pos, candidate));
+                }
+            }
+        }
+        occurrences
+    }
+}
+
+/// Remap account addresses of `input` with a synthetic address derived from `seed`.
+pub fn anonymize_test_vector(
+    input: &EthTransactionTestVector,
+    seed: &[u8],
+) -> (EthTransactionTestVector, AnonymizeReport) {
+    AddressRemapper::new(seed).anonymize(input)
+}
+
+/// Zero address, precompiles and masked ID addresses are kept as they are.
+pub fn is_reserved_address(addr: &H160) -> bool {
+    addr.0[..19].iter().all(|b| *b == 0) || is_precompile(addr) || masked_id(&addr.0).is_some()
+}
+
+fn find_creator(
+    input: &EthTransactionTestVector,
+    accounts: &HashSet<H160>,
+    created: &H160,
+) -> Option<(H160, u64)> {
+    if input.create_contract()
+        && H160(compute_address_create(&EthAddress(input.from.0), input.nonce).0) == *created
+    {
+        return Some((input.from, input.nonce));
+    }
+    for creator in accounts {
+        let pre_nonce = input.prestate.get(creator).map(|s| s.nonce).unwrap_or(0);
+        let post_nonce = input
+            .poststate
+            .get(creator)
+            .map(|s| s.nonce)
+            .unwrap_or(pre_nonce);
+        for nonce in pre_nonce..post_nonce {
+            if H160(compute_address_create(&EthAddress(creator.0), nonce).0) == *created {
+                return Some((*creator, nonce));
+            }
+        }
+    }
+    None
+}
+
+fn mapping_key(addr: &H160, slot: &H256) -> H256 {
+    let mut preimage = [0u8; 64];
+    preimage[12..32].copy_from_slice(addr.as_bytes());
+    preimage[32..].copy_from_slice(slot.as_bytes());
+    H256::from_slice(&hash(SupportedHashes::Keccak256, &preimage))
+}
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ethers::types::GethTrace;
+use serde::{Deserialize, Serialize};
+
+use crate::extractor::opcodes::*;
+use crate::extractor::precompiles::{precompile_name, ETH_PRECOMPILES};
+use crate::extractor::transaction::decode_address;
+
+/// EVM features exercised by a set of structLog traces.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Coverage {
+    pub transactions: u64,
+    pub failed_transactions: u64,
+    /// executed opcode -> number of executions
+    pub opcodes: BTreeMap<String, u64>,
+    /// called precompile -> number of calls
+    pub precompiles: BTreeMap<String, u64>,
+    /// call depth -> number of frames entered at that depth
+    pub call_depths: BTreeMap<u64, u64>,
+    pub max_call_depth: u64,
+    pub creates: u64,
+    pub create2s: u64,
+    /// call depth -> number of REVERT executed at that depth
+    pub reverts: BTreeMap<u64, u64>,
+    /// execution error -> number of occurrences, e.g. out of gas
+    pub errors: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoverageReport {
+    pub coverage: Coverage,
+    pub missing_opcodes: Vec<String>,
+    pub missing_precompiles: Vec<String>,
+    pub missing_features: Vec<String>,
+}
+
+impl Coverage {
+    pub fn record_trace(&mut self, trace: &GethTrace) {
+        self.transactions += 1;
+        if trace.failed {
+            self.failed_transactions += 1;
+        }
+        *self.call_depths.entry(1).or_default() += 1;
+        self.max_call_depth = self.max_call_depth.max(1);
+
+        for (i, log) in trace.struct_logs.iter().enumerate() {
+            let op = normalize_opcode(&log.op);
+            *self.opcodes.entry(op.to_string()).or_default() += 1;
+            if let Some(error) = &log.error {
+                *self.errors.entry(error.clone()).or_default() += 1;
+            }
+            match op {
+                // a faulted call, e.g. on a stack underflow, calls nothing
+                OP_CALL | OP_STATICCALL | OP_DELEGATECALL | OP_CALLCODE if log.error.is_none() => {
+                    let address = log.stack.as_ref().and_then(|stack| {
+                        let i = stack.len().checked_sub(2)?;
+                        Some(decode_address(stack[i]))
+                    });
+                    if let Some(name) = address.as_ref().and_then(precompile_name) {
+                        *self.precompiles.entry(name.to_string()).or_default() += 1;
+                    }
+                }
+                OP_CREATE => self.creates += 1,
+                OP_CREATE2 => self.create2s += 1,
+                OP_REVERT => *self.reverts.entry(log.depth).or_default() += 1,
+                _ => (),
+            }
+            // a new frame is entered if the next log is one level deeper
+            if let Some(next) = trace.struct_logs.get(i + 1) {
+                if next.depth > log.depth {
+                    *self.call_depths.entry(next.depth).or_default() += 1;
+                    self.max_call_depth = self.max_call_depth.max(next.depth);
+                }
+            }
+        }
+    }
+
+    pub fn report(&self) -> CoverageReport {
+        let missing_opcodes = OPCODES
+            .iter()
+            .filter(|(_, name)| !self.opcodes.contains_key(*name))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let missing_precompiles = ETH_PRECOMPILES
+            .iter()
+            .filter(|(_, name)| !self.precompiles.contains_key(*name))
+            .map(|(_, name)| name.to_string())
+            .collect();
+
+        let mut features = BTreeSet::new();
+        if self.max_call_depth < 2 {
+            features.insert("nested calls");
+        }
+        if self.creates == 0 {
+            features.insert("CREATE");
+        }
+        if self.create2s == 0 {
+            features.insert("CREATE2");
+        }
+        if !self.reverts.contains_key(&1) {
+            features.insert("top-level revert");
+        }
+        if !self.reverts.keys().any(|depth| *depth > 1) {
+            features.insert("nested revert");
+        }
+        if self.errors.is_empty() {
+            features.insert("execution error");
+        }
+        if self.failed_transactions == 0 {
+            features.insert("failed transaction");
+        }
+
+        CoverageReport {
+            coverage: self.clone(),
+            missing_opcodes,
+            missing_precompiles,
+            missing_features: features.into_iter().map(String::from).collect(),
+        }
+    }
+}
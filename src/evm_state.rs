@@ -1,12 +1,59 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use cid::multihash::Code;
 use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::tuple::*;
-use fvm_ipld_encoding::Cbor;
+use fvm_ipld_encoding::{from_slice, strict_bytes, Cbor, CborStore};
+use fvm_shared::crypto::hash::SupportedHashes;
+use fvm_shared::ActorID;
 use serde_tuple::{Deserialize_tuple, Serialize_tuple};
 
+/// Layout of the EVM actor state, named after the first builtin-actors release using it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StateVersion {
+    #[default]
+    V10,
+    /// adds the tombstone left by SELFDESTRUCT, the bytecode hash is a plain digest
+    V11,
+    /// adds the transient data of EIP-1153
+    V16,
+}
+
+impl StateVersion {
+    pub const ALL: &'static [StateVersion] =
+        &[StateVersion::V16, StateVersion::V11, StateVersion::V10];
+}
+
+impl Display for StateVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateVersion::V10 => write!(f, "v10"),
+            StateVersion::V11 => write!(f, "v11"),
+            StateVersion::V16 => write!(f, "v16"),
+        }
+    }
+}
+
+impl FromStr for StateVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches('v') {
+            "10" => Ok(StateVersion::V10),
+            "11" | "12" | "13" | "14" | "15" => Ok(StateVersion::V11),
+            "16" => Ok(StateVersion::V16),
+            _ => Err(anyhow!("unknown EVM state version {}", s)),
+        }
+    }
+}
+
 /// Data stored by an EVM contract.
 /// This runs on the fvm-evm-runtime actor code cid.
 #[derive(Debug, Serialize_tuple, Deserialize_tuple)]
-pub struct State {
+pub struct StateV10 {
     /// The EVM contract bytecode resulting from calling the
     /// initialization code by the constructor.
     pub bytecode: Cid,
@@ -24,4 +71,205 @@ pub struct State {
     pub nonce: u64,
 }
 
-impl Cbor for State {}
+impl Cbor for StateV10 {}
+
+#[derive(Debug, Serialize_tuple, Deserialize_tuple)]
+pub struct StateV11 {
+    pub bytecode: Cid,
+    #[serde(with = "strict_bytes")]
+    pub bytecode_hash: [u8; 32],
+    pub contract_state: Cid,
+    pub nonce: u64,
+    /// Set when the contract self-destructed, it's dead once the message which
+    /// destroyed it is done.
+    pub tombstone: Option<Tombstone>,
+}
+
+impl Cbor for StateV11 {}
+
+#[derive(Debug, Serialize_tuple, Deserialize_tuple)]
+pub struct StateV16 {
+    pub bytecode: Cid,
+    #[serde(with = "strict_bytes")]
+    pub bytecode_hash: [u8; 32],
+    pub contract_state: Cid,
+    /// Storage of TLOAD and TSTORE, valid for the message it was written by.
+    pub transient_data: Option<TransientData>,
+    pub nonce: u64,
+    pub tombstone: Option<Tombstone>,
+}
+
+impl Cbor for StateV16 {}
+
+/// Identifies a message by its origin and the origin's nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct Tombstone {
+    pub origin: ActorID,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct TransientDataLifespan {
+    pub origin: ActorID,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct TransientData {
+    /// KAMT<U256, U256>
+    pub transient_data_state: Cid,
+    pub transient_data_lifespan: TransientDataLifespan,
+}
+
+/// EVM actor state independent of its layout, fields a layout doesn't have are dropped
+/// when it's stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub version: StateVersion,
+    pub bytecode: Cid,
+    /// keccak256(bytecode)
+    pub bytecode_hash: [u8; 32],
+    pub contract_state: Cid,
+    pub nonce: u64,
+    pub tombstone: Option<Tombstone>,
+    pub transient_data: Option<TransientData>,
+}
+
+impl State {
+    pub fn new(
+        version: StateVersion,
+        bytecode: Cid,
+        bytecode_hash: [u8; 32],
+        contract_state: Cid,
+        nonce: u64,
+    ) -> Self {
+        Self {
+            version,
+            bytecode,
+            bytecode_hash,
+            contract_state,
+            nonce,
+            tombstone: None,
+            transient_data: None,
+        }
+    }
+
+    /// Load the EVM state at `head` whatever its layout, told apart by their numbers of
+    /// fields. Returns `None` if `head` isn't in the store or no layout has its number of
+    /// fields, so it isn't an EVM state.
+    pub fn load<BS: Blockstore>(store: &BS, head: &Cid) -> anyhow::Result<Option<Self>> {
+        let block = match store.get(head)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        // CBOR array headers of 4, 5 and 6 items
+        let version = match block.first() {
+            Some(0x84) => StateVersion::V10,
+            Some(0x85) => StateVersion::V11,
+            Some(0x86) => StateVersion::V16,
+            _ => return Ok(None),
+        };
+        Self::decode(&block, version).map(Some)
+    }
+
+    pub fn load_version<BS: Blockstore>(
+        store: &BS,
+        head: &Cid,
+        version: StateVersion,
+    ) -> anyhow::Result<Option<Self>> {
+        store
+            .get(head)?
+            .map(|block| Self::decode(&block, version))
+            .transpose()
+    }
+
+    fn decode(block: &[u8], version: StateVersion) -> anyhow::Result<Self> {
+        let invalid = || format!("invalid {} EVM state", version);
+        let state = match version {
+            StateVersion::V10 => {
+                let st: StateV10 = from_slice(block).with_context(invalid)?;
+                let bytecode_hash = st.bytecode_hash.digest().try_into().map_err(|_| {
+                    anyhow!(
+                        "bytecode hash of {} bytes in a v10 EVM state",
+                        st.bytecode_hash.digest().len()
+                    )
+                })?;
+                Self {
+                    version,
+                    bytecode: st.bytecode,
+                    bytecode_hash,
+                    contract_state: st.contract_state,
+                    nonce: st.nonce,
+                    tombstone: None,
+                    transient_data: None,
+                }
+            }
+            StateVersion::V11 => {
+                let st: StateV11 = from_slice(block).with_context(invalid)?;
+                Self {
+                    version,
+                    bytecode: st.bytecode,
+                    bytecode_hash: st.bytecode_hash,
+                    contract_state: st.contract_state,
+                    nonce: st.nonce,
+                    tombstone: st.tombstone,
+                    transient_data: None,
+                }
+            }
+            StateVersion::V16 => {
+                let st: StateV16 = from_slice(block).with_context(invalid)?;
+                Self {
+                    version,
+                    bytecode: st.bytecode,
+                    bytecode_hash: st.bytecode_hash,
+                    contract_state: st.contract_state,
+                    nonce: st.nonce,
+                    tombstone: st.tombstone,
+                    transient_data: st.transient_data,
+                }
+            }
+        };
+        Ok(state)
+    }
+
+    /// Write the state with the layout of its version.
+    pub fn store<BS: Blockstore>(&self, store: &BS) -> anyhow::Result<Cid> {
+        let cid = match self.version {
+            StateVersion::V10 => store.put_cbor(
+                &StateV10 {
+                    bytecode: self.bytecode,
+                    bytecode_hash: multihash::Multihash::wrap(
+                        SupportedHashes::Keccak256 as u64,
+                        &self.bytecode_hash,
+                    )
+                    .map_err(|e| anyhow!("failed to wrap bytecode hash: {:?}", e))?,
+                    contract_state: self.contract_state,
+                    nonce: self.nonce,
+                },
+                Code::Blake2b256,
+            )?,
+            StateVersion::V11 => store.put_cbor(
+                &StateV11 {
+                    bytecode: self.bytecode,
+                    bytecode_hash: self.bytecode_hash,
+                    contract_state: self.contract_state,
+                    nonce: self.nonce,
+                    tombstone: self.tombstone,
+                },
+                Code::Blake2b256,
+            )?,
+            StateVersion::V16 => store.put_cbor(
+                &StateV16 {
+                    bytecode: self.bytecode,
+                    bytecode_hash: self.bytecode_hash,
+                    contract_state: self.contract_state,
+                    transient_data: self.transient_data,
+                    nonce: self.nonce,
+                    tombstone: self.tombstone,
+                },
+                Code::Blake2b256,
+            )?,
+        };
+        Ok(cid)
+    }
+}
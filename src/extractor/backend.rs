@@ -0,0 +1,42 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// Tracing API of the node the states of a transaction are extracted from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// `debug_traceTransaction` tracers of geth
+    #[default]
+    Geth,
+    /// `vmTrace` of `trace_replayTransaction`, replayed like the structLogs of geth
+    Erigon,
+    /// `stateDiff` of `trace_replayTransaction` of Erigon, Nethermind and OpenEthereum
+    Parity,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Geth => write!(f, "geth"),
+            Backend::Erigon => write!(f, "erigon"),
+            Backend::Parity => write!(f, "parity"),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "geth" => Ok(Backend::Geth),
+            "erigon" => Ok(Backend::Erigon),
+            "parity" | "nethermind" => Ok(Backend::Parity),
+            _ => Err(anyhow!(
+                "unknown backend {}, expected geth, erigon or parity",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::providers::{Middleware, Provider};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::backend::Backend;
+use super::cache::RpcCache;
+use super::transaction::extract_eth_transaction_test_vector;
+use super::types::EthTransactionTestVector;
+
+/// Name of the file recording the progress of a batch extraction in its output dir.
+pub const EXTRACT_STATE_FILE: &str = ".extract-state.json";
+
+/// Criteria a transaction of a block range must match to be extracted.
+#[derive(Debug, Clone, Default)]
+pub struct TxFilter {
+    /// called contract, or deployer for creations
+    pub to: Option<H160>,
+    /// 4-byte function selector the input starts with
+    pub selector: Option<[u8; 4]>,
+    pub creations_only: bool,
+    /// only transactions whose receipt status is 0, exported with a reverted receipt
+    pub failed_only: bool,
+    pub min_gas_used: Option<u64>,
+}
+
+impl TxFilter {
+    /// Whether `tx` matches the criteria which don't need its receipt.
+    pub fn matches(&self, tx: &Transaction) -> bool {
+        if self.creations_only && tx.to.is_some() {
+            return false;
+        }
+        if let Some(to) = self.to {
+            if tx.to.unwrap_or(tx.from) != to {
+                return false;
+            }
+        }
+        if let Some(selector) = self.selector {
+            if !tx.input.starts_with(&selector) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether `matches_receipt` needs to be checked.
+    pub fn needs_receipt(&self) -> bool {
+        self.failed_only || self.min_gas_used.is_some()
+    }
+
+    pub fn matches_receipt(&self, receipt: &TransactionReceipt) -> bool {
+        if self.failed_only && receipt.status != Some(U64::zero()) {
+            return false;
+        }
+        if let Some(min_gas_used) = self.min_gas_used {
+            if receipt
+                .gas_used
+                .map_or(true, |gas| gas < min_gas_used.into())
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Read transaction hashes from `path`, one per line, blank lines and `#` comments are
+/// skipped.
+pub fn read_tx_hashes(path: &Path) -> anyhow::Result<Vec<H256>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut hashes = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hash = H256::from_str(line)
+            .with_context(|| format!("{:?}:{}: invalid transaction hash", path, i + 1))?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+/// Hashes of the transactions of `blocks` matching `filter`, in chain order.
+pub async fn select_transactions<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    blocks: RangeInclusive<u64>,
+    filter: &TxFilter,
+) -> anyhow::Result<Vec<H256>> {
+    let mut hashes = Vec::new();
+    for number in blocks {
+        let block = provider
+            .get_block_with_txs(U64::from(number))
+            .await?
+            .with_context(|| format!("block {} not found", number))?;
+        hashes.extend(filter_block_transactions(provider, &block, filter).await?);
+    }
+    Ok(hashes)
+}
+
+/// Hashes of the transactions of `block` matching `filter`.
+pub async fn filter_block_transactions<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    block: &Block<Transaction>,
+    filter: &TxFilter,
+) -> anyhow::Result<Vec<H256>> {
+    let mut hashes = Vec::new();
+    for tx in block.transactions.iter().filter(|tx| filter.matches(tx)) {
+        if filter.needs_receipt() {
+            let receipt = provider
+                .get_transaction_receipt(tx.hash)
+                .await?
+                .with_context(|| format!("receipt of {:?} not found", tx.hash))?;
+            if !filter.matches_receipt(&receipt) {
+                continue;
+            }
+        }
+        hashes.push(tx.hash);
+    }
+    Ok(hashes)
+}
+
+/// Progress of a batch extraction, saved after each transaction so an interrupted run
+/// resumes where it left off. Failed transactions are retried.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtractState {
+    pub done: BTreeSet<H256>,
+    /// transaction hash -> error of the last attempt
+    pub failed: BTreeMap<H256, String>,
+}
+
+impl ExtractState {
+    pub fn load(out_dir: &Path) -> anyhow::Result<Self> {
+        let path = out_dir.join(EXTRACT_STATE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("invalid extraction state {:?}", path))
+    }
+
+    pub fn save(&self, out_dir: &Path) -> anyhow::Result<()> {
+        // written aside then renamed, so an interruption never leaves a truncated state
+        let tmp = out_dir.join(format!("{}.tmp", EXTRACT_STATE_FILE));
+        serde_json::to_writer_pretty(File::create(&tmp)?, self)?;
+        std::fs::rename(tmp, out_dir.join(EXTRACT_STATE_FILE))?;
+        Ok(())
+    }
+}
+
+/// Extract the transactions of `hashes` not done yet according to the state file of
+/// `out_dir`, at most `concurrency` at a time, and hand each one to `write`.
+pub async fn extract_batch<P, F>(
+    provider: &Provider<P>,
+    hashes: Vec<H256>,
+    out_dir: &Path,
+    concurrency: usize,
+    cache: Option<&RpcCache>,
+    backend: Backend,
+    write: F,
+) -> anyhow::Result<ExtractState>
+where
+    P: JsonRpcClient,
+    F: Fn(H256, EthTransactionTestVector) -> anyhow::Result<()>,
+{
+    let mut state = ExtractState::load(out_dir)?;
+    let pending: Vec<H256> = hashes
+        .into_iter()
+        .filter(|hash| !state.done.contains(hash))
+        .collect();
+    let total = pending.len();
+
+    let mut extracted = stream::iter(pending)
+        .map(|hash| async move {
+            let res = extract_eth_transaction_test_vector(provider, hash, cache, backend).await;
+            (hash, res)
+        })
+        .buffer_unordered(concurrency.max(1));
+    let mut count = 0;
+    while let Some((hash, res)) = extracted.next().await {
+        count += 1;
+        match res.and_then(|evm_input| write(hash, evm_input)) {
+            Ok(()) => {
+                log::info!("[{}/{}] extracted {:?}", count, total, hash);
+                state.failed.remove(&hash);
+                state.done.insert(hash);
+            }
+            Err(e) => {
+                log::error!("[{}/{}] {:?} failed: {:#}", count, total, hash, e);
+                state.failed.insert(hash, format!("{:#}", e));
+            }
+        }
+        state.save(out_dir)?;
+    }
+    Ok(state)
+}
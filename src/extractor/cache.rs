@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use ethers::prelude::*;
+use ethers::providers::Provider;
+use ethers::utils::keccak256;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+/// Local cache of the raw RPC responses fetched to extract transactions.
+///
+/// Responses are stored once under `blobs/<keccak256 of the response>`, and
+/// `index/<tx hash>.json` maps each request made for a transaction to its response, so
+/// the entries of a transaction can be invalidated on their own.
+#[derive(Debug, Clone)]
+pub struct RpcCache {
+    dir: PathBuf,
+    /// total size of the blobs above which the oldest transactions are evicted
+    max_size: Option<u64>,
+    /// total size of the blobs, scanned on first use then kept up to date
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+/// Request key -> blob name.
+type TxIndex = BTreeMap<String, String>;
+
+/// Usage of a cache directory.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheInfo {
+    pub transactions: usize,
+    pub blobs: usize,
+    /// total size of the blobs, in bytes
+    pub size: u64,
+}
+
+impl RpcCache {
+    pub fn open(dir: impl Into<PathBuf>, max_size: Option<u64>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("index"))?;
+        fs::create_dir_all(dir.join("blobs"))?;
+        Ok(Self {
+            dir,
+            max_size,
+            size: Default::default(),
+        })
+    }
+
+    fn index_path(&self, tx_hash: &H256) -> PathBuf {
+        self.dir.join("index").join(format!("{:?}.json", tx_hash))
+    }
+
+    fn blob_path(&self, name: &str) -> PathBuf {
+        self.dir.join("blobs").join(name)
+    }
+
+    fn load_index(&self, tx_hash: &H256) -> anyhow::Result<TxIndex> {
+        let path = self.index_path(tx_hash);
+        if !path.exists() {
+            return Ok(TxIndex::default());
+        }
+        serde_json::from_reader(BufReader::new(File::open(&path)?))
+            .with_context(|| format!("invalid cache index {:?}", path))
+    }
+
+    /// Index files of the cached transactions, leaving out the ones being written.
+    fn index_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(self.dir.join("index"))? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Cached response of `key` requested for `tx_hash`.
+    pub fn get(&self, tx_hash: &H256, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut blob = Vec::new();
+        match self.open(tx_hash, key)? {
+            Some(mut file) => {
+                file.read_to_end(&mut blob)?;
+                Ok(Some(blob))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Open the cached response of `key` requested for `tx_hash`.
+    pub fn open(&self, tx_hash: &H256, key: &str) -> anyhow::Result<Option<File>> {
+        let index = self.load_index(tx_hash)?;
+        match index.get(key) {
+            Some(name) => match File::open(self.blob_path(name)) {
+                Ok(file) => Ok(Some(file)),
+                // the blob was pruned, refetch it
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, tx_hash: &H256, key: &str, response: &[u8]) -> anyhow::Result<()> {
+        let name = hex::encode(keccak256(response));
+        let blob_path = self.blob_path(&name);
+        let mut written = 0;
+        if !blob_path.exists() {
+            write_atomic(&blob_path, response)?;
+            written = response.len() as u64;
+        }
+        let mut index = self.load_index(tx_hash)?;
+        index.insert(key.to_owned(), name);
+        write_atomic(&self.index_path(tx_hash), &serde_json::to_vec(&index)?)?;
+        if let Some(max_size) = self.max_size {
+            let size = {
+                let mut size = self.size.lock().unwrap();
+                let total = match *size {
+                    Some(total) => total + written,
+                    None => self.info()?.size,
+                };
+                *size = Some(total);
+                total
+            };
+            if size > max_size {
+                self.prune(max_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the entries of `tx_hash`, returns whether there were any.
+    pub fn invalidate(&self, tx_hash: &H256) -> anyhow::Result<bool> {
+        let path = self.index_path(tx_hash);
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(path)?;
+        let freed = self.collect_garbage()?;
+        if let Some(size) = self.size.lock().unwrap().as_mut() {
+            *size = size.saturating_sub(freed);
+        }
+        Ok(true)
+    }
+
+    pub fn clear(&self) -> anyhow::Result<()> {
+        for sub in ["index", "blobs"] {
+            let dir = self.dir.join(sub);
+            fs::remove_dir_all(&dir)?;
+            fs::create_dir_all(&dir)?;
+        }
+        *self.size.lock().unwrap() = Some(0);
+        Ok(())
+    }
+
+    /// Evict the transactions cached first until the blobs take at most `max_size` bytes.
+    pub fn prune(&self, max_size: u64) -> anyhow::Result<()> {
+        let mut size = self.info()?.size;
+        if size <= max_size {
+            *self.size.lock().unwrap() = Some(size);
+            return Ok(());
+        }
+        let mut indexes = Vec::new();
+        for path in self.index_files()? {
+            let modified = path
+                .metadata()?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            indexes.push((modified, path));
+        }
+        indexes.sort();
+        for (_, path) in indexes {
+            if size <= max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            size -= self.collect_garbage()?;
+        }
+        *self.size.lock().unwrap() = Some(size);
+        Ok(())
+    }
+
+    /// Remove the blobs no transaction refers to, returns the number of bytes freed.
+    fn collect_garbage(&self) -> anyhow::Result<u64> {
+        let mut live = BTreeSet::new();
+        for path in self.index_files()? {
+            let index: TxIndex = serde_json::from_reader(BufReader::new(File::open(&path)?))
+                .with_context(|| format!("invalid cache index {:?}", path))?;
+            live.extend(index.into_values());
+        }
+        let mut freed = 0;
+        for entry in fs::read_dir(self.dir.join("blobs"))? {
+            let entry = entry?;
+            if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+                freed += entry.metadata()?.len();
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(freed)
+    }
+
+    pub fn info(&self) -> anyhow::Result<CacheInfo> {
+        let mut info = CacheInfo {
+            transactions: self.index_files()?.len(),
+            ..Default::default()
+        };
+        for entry in fs::read_dir(self.dir.join("blobs"))? {
+            info.blobs += 1;
+            info.size += entry?.metadata()?.len();
+        }
+        Ok(info)
+    }
+}
+
+/// Written aside then renamed, so concurrent extractions never read a partial file.
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Sends the RPC requests made for one transaction, answering from the cache when it can.
+pub(crate) struct CachedRpc<'a, P> {
+    pub provider: &'a Provider<P>,
+    pub cache: Option<&'a RpcCache>,
+    pub tx_hash: H256,
+}
+
+impl<'a, P: JsonRpcClient> CachedRpc<'a, P> {
+    pub async fn request<T, R>(&self, method: &str, params: T) -> anyhow::Result<R>
+    where
+        T: std::fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => return Ok(self.provider.request(method, params).await?),
+        };
+        let key = format!("{}{}", method, serde_json::to_string(&params)?);
+        if let Some(response) = cache.get(&self.tx_hash, &key)? {
+            log::debug!("{:?}: cache hit for {}", self.tx_hash, method);
+            return Ok(serde_json::from_slice(&response)?);
+        }
+        let response: Box<RawValue> = self.provider.request(method, params).await?;
+        // null answers a request about something not on chain yet, don't remember it
+        if response.get() != "null" {
+            cache.put(&self.tx_hash, &key, response.get().as_bytes())?;
+        }
+        Ok(serde_json::from_str(response.get())?)
+    }
+
+    /// Like `request`, but returns the raw response to be parsed incrementally, it's read
+    /// from the cache file when there's one.
+    ///
+    /// On a cache miss the response isn't streamed: ethers' `JsonRpcClient` only hands
+    /// out complete responses, its HTTP transport reads the whole body and the WebSocket
+    /// and IPC ones whole messages. The raw JSON text is held in memory until it's
+    /// written to a file, only the parsed logs are never all in memory at once.
+    pub async fn request_reader<T>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> anyhow::Result<Box<dyn Read + Send>>
+    where
+        T: std::fmt::Debug + Serialize + Send + Sync,
+    {
+        let key = format!("{}{}", method, serde_json::to_string(&params)?);
+        if let Some(cache) = self.cache {
+            if let Some(file) = cache.open(&self.tx_hash, &key)? {
+                log::debug!("{:?}: cache hit for {}", self.tx_hash, method);
+                return Ok(Box::new(file));
+            }
+        }
+        let response: Box<RawValue> = self.provider.request(method, params).await?;
+        // the text is dropped once written to a file, before the parser starts
+        if let Some(cache) = self.cache.filter(|_| response.get() != "null") {
+            cache.put(&self.tx_hash, &key, response.get().as_bytes())?;
+            // pruning may have evicted the entry right away, spill it then
+            if let Some(file) = cache.open(&self.tx_hash, &key)? {
+                return Ok(Box::new(file));
+            }
+        }
+        Ok(Box::new(spill(&self.tx_hash, response.get().as_bytes())?))
+    }
+}
+
+/// Write `data` to an unlinked temporary file and return it rewound.
+fn spill(tx_hash: &H256, data: &[u8]) -> anyhow::Result<File> {
+    let path = std::env::temp_dir().join(format!(
+        "fevm-response-{:?}-{}",
+        tx_hash,
+        std::process::id()
+    ));
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .with_context(|| format!("cannot create {:?}", path))?;
+    // the open handle keeps the data readable once the name is gone
+    let removed = fs::remove_file(&path);
+    file.write_all(data)?;
+    file.seek(SeekFrom::Start(0))?;
+    if removed.is_err() {
+        log::debug!("cannot unlink {:?} while it's open", path);
+    }
+    Ok(file)
+}
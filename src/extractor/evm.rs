@@ -0,0 +1,1491 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use ethers::types::{Block, Bytes, StructLog, Transaction, H160, H256, U256, U512, U64};
+use ethers::utils::{get_contract_address, get_create2_address_from_hash, keccak256};
+use serde::{Deserialize, Serialize};
+
+use super::journal::JournaledState;
+use super::opcodes::*;
+use super::precompiles::{precompile, PrecompileKind, ETH_PRECOMPILES};
+use super::replay::extract_test_vector;
+use super::source::EthTraceSource;
+use super::trace_stream::{StructLogStream, TraceSummary};
+use super::transaction::{decode_address, U256_to_H256};
+use super::types::{EthCallFrame, EthState, EthTransactionTestVector};
+
+/// Transaction executed by the embedded EVM.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvmTransaction {
+    pub from: H160,
+    /// `None` to create a contract from `input`
+    #[serde(default)]
+    pub to: Option<H160>,
+    #[serde(default)]
+    pub value: U256,
+    #[serde(default)]
+    pub input: Bytes,
+    /// gas limit
+    pub gas: U256,
+    #[serde(default)]
+    pub gas_price: U256,
+}
+
+/// Block the transaction is executed in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EvmBlock {
+    pub number: u64,
+    pub coinbase: H160,
+    pub timestamp: U256,
+    pub gas_limit: U256,
+    pub base_fee_per_gas: Option<U256>,
+    pub difficulty: U256,
+    /// PREVRANDAO, used when the difficulty is zero
+    pub random: U256,
+    pub chain_id: U256,
+    /// hashes returned by BLOCKHASH
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+impl Default for EvmBlock {
+    fn default() -> Self {
+        Self {
+            number: 1,
+            coinbase: H160::zero(),
+            timestamp: U256::zero(),
+            gas_limit: 30_000_000.into(),
+            base_fee_per_gas: None,
+            difficulty: U256::zero(),
+            random: U256::zero(),
+            chain_id: 1.into(),
+            block_hashes: BTreeMap::new(),
+        }
+    }
+}
+
+/// Prestate and environment of a transaction executed without a node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvmInput {
+    pub prestate: EthState,
+    pub transaction: EvmTransaction,
+    #[serde(default)]
+    pub block: EvmBlock,
+}
+
+/// Execute `input` with the embedded EVM and extract its test vector.
+pub async fn synthesize_test_vector(input: &EvmInput) -> anyhow::Result<EthTransactionTestVector> {
+    let source = EvmSource::execute(input)?;
+    extract_test_vector(&source, source.transaction.hash).await
+}
+
+const MAX_CALL_DEPTH: u64 = 1024;
+const MAX_STACK_SIZE: usize = 1024;
+const MAX_CODE_SIZE: usize = 0x6000;
+const COLD_ACCOUNT_ACCESS_COST: u64 = 2600;
+const COLD_SLOAD_COST: u64 = 2100;
+const WARM_STORAGE_READ_COST: u64 = 100;
+const CALL_STIPEND: u64 = 2300;
+
+type Fault = &'static str;
+
+const OUT_OF_GAS: Fault = "out of gas";
+const WRITE_PROTECTION: Fault = "write protection";
+
+/// Accounts and slots accessed so far as of EIP-2929, and the gas refund, which are all
+/// restored when a frame reverts.
+#[derive(Debug, Clone, Default)]
+struct Substate {
+    accounts: BTreeSet<H160>,
+    slots: BTreeSet<(H160, H256)>,
+    refund: i64,
+    destroyed: BTreeSet<H160>,
+}
+
+/// A call or a creation.
+#[derive(Debug, Clone)]
+struct Message {
+    call_type: &'static str,
+    caller: H160,
+    /// account whose storage and balance the code runs with
+    address: H160,
+    /// account whose code runs, the created account for creations
+    code_address: H160,
+    /// value moved from the caller to `address`
+    transfer: U256,
+    /// value returned by CALLVALUE
+    value: U256,
+    input: Bytes,
+    code: Bytes,
+    is_static: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FrameResult {
+    success: bool,
+    gas_left: u64,
+    output: Bytes,
+}
+
+impl FrameResult {
+    /// Failure consuming all the gas.
+    fn fault() -> Self {
+        Self::default()
+    }
+}
+
+/// Stack, memory and gas of a running frame.
+#[derive(Debug, Default)]
+struct Machine {
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+    gas: u64,
+    return_data: Bytes,
+}
+
+impl Machine {
+    fn pop(&mut self) -> Result<U256, Fault> {
+        self.stack.pop().ok_or("stack underflow")
+    }
+
+    fn push(&mut self, value: U256) -> Result<(), Fault> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err("stack limit reached 1024");
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn charge(&mut self, cost: u64) -> Result<(), Fault> {
+        self.gas = self.gas.checked_sub(cost).ok_or(OUT_OF_GAS)?;
+        Ok(())
+    }
+
+    /// Charge the expansion of the memory to the `size` bytes from `offset`, returns them
+    /// as indexes.
+    fn expand(&mut self, offset: U256, size: U256) -> Result<(usize, usize), Fault> {
+        if size.is_zero() {
+            return Ok((0, 0));
+        }
+        if offset > U256::from(u32::MAX) || size > U256::from(u32::MAX) {
+            return Err(OUT_OF_GAS);
+        }
+        let (offset, size) = (offset.as_usize(), size.as_usize());
+        let words = words(offset + size);
+        let current = words_of(self.memory.len());
+        if words > current {
+            self.charge(memory_cost(words) - memory_cost(current))?;
+            self.memory.resize(words as usize * 32, 0);
+        }
+        Ok((offset, size))
+    }
+
+    fn read(&self, offset: usize, size: usize) -> Bytes {
+        Bytes::from(self.memory[offset..offset + size].to_vec())
+    }
+
+    /// Copy `size` bytes of `data` from `data_offset` to the memory at `offset`, the bytes
+    /// past the end of `data` are zeros.
+    fn write(&mut self, offset: usize, size: usize, data: &[u8], data_offset: U256) {
+        let data_offset = saturating_usize(data_offset);
+        for i in 0..size {
+            self.memory[offset + i] = data_offset
+                .checked_add(i)
+                .and_then(|j| data.get(j))
+                .copied()
+                .unwrap_or(0);
+        }
+    }
+}
+
+fn words(size: usize) -> u64 {
+    (size as u64 + 31) / 32
+}
+
+fn words_of(memory_size: usize) -> u64 {
+    memory_size as u64 / 32
+}
+
+fn memory_cost(words: u64) -> u64 {
+    3 * words + words * words / 512
+}
+
+fn copy_cost(size: usize) -> u64 {
+    3 * words(size)
+}
+
+fn saturating_usize(value: U256) -> usize {
+    if value.bits() > 63 {
+        usize::MAX
+    } else {
+        value.as_usize()
+    }
+}
+
+fn address_to_u256(address: H160) -> U256 {
+    U256::from_big_endian(address.as_bytes())
+}
+
+fn is_negative(value: U256) -> bool {
+    value.bit(255)
+}
+
+fn negate(value: U256) -> U256 {
+    (!value).overflowing_add(U256::one()).0
+}
+
+fn abs(value: U256) -> U256 {
+    if is_negative(value) {
+        negate(value)
+    } else {
+        value
+    }
+}
+
+fn signed_lt(a: U256, b: U256) -> bool {
+    match (is_negative(a), is_negative(b)) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => a < b,
+    }
+}
+
+fn low_u256(value: U512) -> U256 {
+    let mut bytes = [0; 64];
+    value.to_big_endian(&mut bytes);
+    U256::from_big_endian(&bytes[32..])
+}
+
+/// Offsets of the JUMPDESTs of `code` which are not in PUSH data.
+fn jump_destinations(code: &[u8]) -> Vec<bool> {
+    let mut destinations = vec![false; code.len()];
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        if opcode == 0x5b {
+            destinations[pc] = true;
+        }
+        if (0x60..=0x7f).contains(&opcode) {
+            pc += (opcode - 0x5f) as usize;
+        }
+        pc += 1;
+    }
+    destinations
+}
+
+fn intrinsic_gas(transaction: &EvmTransaction) -> u64 {
+    let data: u64 = transaction
+        .input
+        .iter()
+        .map(|byte| if *byte == 0 { 4 } else { 16 })
+        .sum();
+    let creation = if transaction.to.is_none() { 32000 } else { 0 };
+    21000 + data + creation
+}
+
+/// Interpreter of the transaction of an [`EvmInput`], recording the structLogs geth would.
+struct Evm<'a> {
+    input: &'a EvmInput,
+    state: JournaledState,
+    substate: Substate,
+    /// accounts the transaction reads or writes, which are in its prestate
+    touched: BTreeSet<H160>,
+    logs: Vec<StructLog>,
+    /// frames being executed, the outermost first
+    frames: Vec<EthCallFrame>,
+    call_tree: Option<EthCallFrame>,
+    /// first feature met which isn't implemented
+    unsupported: Option<String>,
+}
+
+impl<'a> Evm<'a> {
+    fn balance(&self, address: &H160) -> U256 {
+        self.state
+            .get(address)
+            .map(|account| account.balance)
+            .unwrap_or_default()
+    }
+
+    fn code(&self, address: &H160) -> Bytes {
+        self.state
+            .get(address)
+            .map(|account| account.code.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether `address` is empty as of EIP-161.
+    fn is_empty(&self, address: &H160) -> bool {
+        self.state.get(address).map_or(true, |account| {
+            account.nonce == 0 && account.balance.is_zero() && account.code.is_empty()
+        })
+    }
+
+    fn storage(&self, address: &H160, key: &H256) -> H256 {
+        self.state
+            .get(address)
+            .and_then(|account| account.storage.get(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Value of the slot before the transaction.
+    fn original_storage(&self, address: &H160, key: &H256) -> H256 {
+        self.input
+            .prestate
+            .get(address)
+            .and_then(|account| account.storage.get(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn touch(&mut self, address: &H160) {
+        self.touched.insert(*address);
+        self.state.create_account(address);
+    }
+
+    /// Gas of an access to `address`, which is warm from now on.
+    fn access_account(&mut self, address: H160) -> u64 {
+        self.touched.insert(address);
+        if self.substate.accounts.insert(address) {
+            COLD_ACCOUNT_ACCESS_COST
+        } else {
+            WARM_STORAGE_READ_COST
+        }
+    }
+
+    fn access_slot(&mut self, address: H160, key: H256) -> u64 {
+        if self.substate.slots.insert((address, key)) {
+            COLD_SLOAD_COST
+        } else {
+            WARM_STORAGE_READ_COST
+        }
+    }
+
+    /// Gas of SSTORE and its refund as of EIP-2200 and EIP-3529, besides the cold access.
+    fn sstore_cost(&mut self, address: &H160, key: &H256, new: H256) -> u64 {
+        let current = self.storage(address, key);
+        let original = self.original_storage(address, key);
+        if new == current {
+            return WARM_STORAGE_READ_COST;
+        }
+        if original == current {
+            if original.is_zero() {
+                return 20000;
+            }
+            if new.is_zero() {
+                self.substate.refund += 4800;
+            }
+            return 5000 - COLD_SLOAD_COST;
+        }
+        if !original.is_zero() {
+            if current.is_zero() {
+                self.substate.refund -= 4800;
+            } else if new.is_zero() {
+                self.substate.refund += 4800;
+            }
+        }
+        if new == original {
+            self.substate.refund += if original.is_zero() {
+                20000 - WARM_STORAGE_READ_COST as i64
+            } else {
+                5000 - COLD_SLOAD_COST as i64 - WARM_STORAGE_READ_COST as i64
+            };
+        }
+        WARM_STORAGE_READ_COST
+    }
+
+    fn enter_frame(&mut self, message: &Message, gas: u64) {
+        self.frames.push(EthCallFrame {
+            call_type: message.call_type.to_owned(),
+            caller: message.caller,
+            callee: message.code_address,
+            value: message.value,
+            input: if message.call_type == OP_CREATE || message.call_type == OP_CREATE2 {
+                message.code.clone()
+            } else {
+                message.input.clone()
+            },
+            gas,
+            ..Default::default()
+        });
+    }
+
+    /// Keep or revert the changes of the frame at `depth`, and attach it to its caller.
+    fn exit_frame(&mut self, result: FrameResult, snapshot: Substate, depth: u64) -> FrameResult {
+        if result.success {
+            self.state.discard_checkpoints(depth as usize - 1);
+        } else {
+            self.state.revert();
+            self.substate = snapshot;
+        }
+        let mut frame = self.frames.pop().unwrap();
+        frame.output = result.output.clone();
+        frame.gas_used = frame.gas - result.gas_left;
+        frame.reverted = !result.success;
+        match self.frames.last_mut() {
+            Some(caller) => caller.calls.push(frame),
+            None => self.call_tree = Some(frame),
+        }
+        result
+    }
+
+    fn call(&mut self, message: Message, gas: u64, depth: u64) -> FrameResult {
+        let snapshot = self.substate.clone();
+        self.state.checkpoint();
+        self.touch(&message.address);
+        if !message.transfer.is_zero() {
+            self.state.sub_balance(&message.caller, message.transfer);
+            self.state.add_balance(&message.address, message.transfer);
+        }
+        self.enter_frame(&message, gas);
+        let result = match precompile(&message.code_address) {
+            Some((PrecompileKind::Ethereum, "identity")) => {
+                let cost = 15 + copy_cost(message.input.len());
+                match gas.checked_sub(cost) {
+                    Some(gas_left) => FrameResult {
+                        success: true,
+                        gas_left,
+                        output: message.input.clone(),
+                    },
+                    None => FrameResult::fault(),
+                }
+            }
+            Some((PrecompileKind::Ethereum, name)) => {
+                self.unsupported
+                    .get_or_insert_with(|| format!("the {} precompile", name));
+                FrameResult::fault()
+            }
+            // FEVM precompiles are plain accounts on Ethereum
+            _ => self.run(&message, gas, depth),
+        };
+        self.exit_frame(result, snapshot, depth)
+    }
+
+    fn create(&mut self, message: Message, gas: u64, depth: u64) -> FrameResult {
+        // the created address stays warm even if the creation fails
+        self.substate.accounts.insert(message.address);
+        self.touched.insert(message.address);
+        let collision = self.state.get(&message.address).map_or(false, |account| {
+            account.nonce != 0 || !account.code.is_empty()
+        });
+        if collision {
+            return FrameResult::fault();
+        }
+
+        let snapshot = self.substate.clone();
+        self.state.checkpoint();
+        self.touch(&message.address);
+        self.state.set_nonce(&message.address, 1);
+        if !message.transfer.is_zero() {
+            self.state.sub_balance(&message.caller, message.transfer);
+            self.state.add_balance(&message.address, message.transfer);
+        }
+        self.enter_frame(&message, gas);
+        let mut result = self.run(&message, gas, depth);
+        if result.success {
+            let code = &result.output;
+            let deposit = 200 * code.len() as u64;
+            let fault = if code.len() > MAX_CODE_SIZE {
+                Some("max code size exceeded")
+            } else if code.first() == Some(&0xef) {
+                Some("invalid code: must not begin with 0xef")
+            } else if result.gas_left < deposit {
+                Some("contract creation code storage out of gas")
+            } else {
+                None
+            };
+            match fault {
+                Some(fault) => {
+                    if let Some(log) = self.logs.last_mut().filter(|log| log.depth == depth) {
+                        log.error = Some(fault.to_owned());
+                    }
+                    result = FrameResult::fault();
+                }
+                None => {
+                    result.gas_left -= deposit;
+                    self.state.set_code(&message.address, code.clone());
+                }
+            }
+        }
+        self.exit_frame(result, snapshot, depth)
+    }
+
+    /// Run the code of `message` at `depth` with `gas`.
+    fn run(&mut self, message: &Message, gas: u64, depth: u64) -> FrameResult {
+        if message.code.is_empty() {
+            return FrameResult {
+                success: true,
+                gas_left: gas,
+                output: Bytes::default(),
+            };
+        }
+        let jump_destinations = jump_destinations(&message.code);
+        let mut machine = Machine {
+            gas,
+            ..Default::default()
+        };
+        let mut pc = 0;
+        loop {
+            // running past the end of the code is a STOP
+            let opcode = message.code.get(pc).copied().unwrap_or(0);
+            let log = self.logs.len();
+            self.logs.push(StructLog {
+                depth,
+                gas: machine.gas,
+                op: struct_log_op(opcode),
+                pc: pc as u64,
+                stack: Some(machine.stack.clone()),
+                ..Default::default()
+            });
+            let gas_before = machine.gas;
+            let step = self.step(
+                message,
+                &mut machine,
+                &jump_destinations,
+                &mut pc,
+                depth,
+                log,
+            );
+            // the cost of calls and creations is set before running them
+            if !matches!(opcode, 0xf0 | 0xf1 | 0xf2 | 0xf4 | 0xf5 | 0xfa) {
+                self.logs[log].gas_cost = gas_before.saturating_sub(machine.gas);
+            }
+            match step {
+                Ok(None) => (),
+                Ok(Some(result)) => return result,
+                Err(fault) => {
+                    // the replay reverts on INVALID by itself
+                    if opcode != 0xfe {
+                        self.logs[log].error = Some(fault.to_owned());
+                    }
+                    return FrameResult::fault();
+                }
+            }
+        }
+    }
+
+    /// Execute the operation at `pc`, returns the result of the frame if it halts.
+    fn step(
+        &mut self,
+        message: &Message,
+        m: &mut Machine,
+        jump_destinations: &[bool],
+        pc: &mut usize,
+        depth: u64,
+        log: usize,
+    ) -> Result<Option<FrameResult>, Fault> {
+        let input = self.input;
+        let code = &message.code;
+        let opcode = code.get(*pc).copied().unwrap_or(0);
+        match opcode {
+            // STOP
+            0x00 => {
+                return Ok(Some(FrameResult {
+                    success: true,
+                    gas_left: m.gas,
+                    output: Bytes::default(),
+                }))
+            }
+            // ADD
+            0x01 => {
+                m.charge(3)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(a.overflowing_add(b).0)?;
+            }
+            // MUL
+            0x02 => {
+                m.charge(5)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(a.overflowing_mul(b).0)?;
+            }
+            // SUB
+            0x03 => {
+                m.charge(3)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(a.overflowing_sub(b).0)?;
+            }
+            // DIV
+            0x04 => {
+                m.charge(5)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(if b.is_zero() { b } else { a / b })?;
+            }
+            // SDIV
+            0x05 => {
+                m.charge(5)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                let quotient = if b.is_zero() {
+                    b
+                } else {
+                    let quotient = abs(a) / abs(b);
+                    if is_negative(a) != is_negative(b) {
+                        negate(quotient)
+                    } else {
+                        quotient
+                    }
+                };
+                m.push(quotient)?;
+            }
+            // MOD
+            0x06 => {
+                m.charge(5)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(if b.is_zero() { b } else { a % b })?;
+            }
+            // SMOD
+            0x07 => {
+                m.charge(5)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                let remainder = if b.is_zero() {
+                    b
+                } else {
+                    let remainder = abs(a) % abs(b);
+                    if is_negative(a) {
+                        negate(remainder)
+                    } else {
+                        remainder
+                    }
+                };
+                m.push(remainder)?;
+            }
+            // ADDMOD
+            0x08 => {
+                m.charge(8)?;
+                let (a, b, n) = (m.pop()?, m.pop()?, m.pop()?);
+                m.push(if n.is_zero() {
+                    n
+                } else {
+                    low_u256((U512::from(a) + U512::from(b)) % U512::from(n))
+                })?;
+            }
+            // MULMOD
+            0x09 => {
+                m.charge(8)?;
+                let (a, b, n) = (m.pop()?, m.pop()?, m.pop()?);
+                m.push(if n.is_zero() {
+                    n
+                } else {
+                    low_u256(a.full_mul(b) % U512::from(n))
+                })?;
+            }
+            // EXP
+            0x0a => {
+                let (base, exponent) = (m.pop()?, m.pop()?);
+                m.charge(10 + 50 * ((exponent.bits() as u64 + 7) / 8))?;
+                m.push(base.overflowing_pow(exponent).0)?;
+            }
+            // SIGNEXTEND
+            0x0b => {
+                m.charge(5)?;
+                let (size, value) = (m.pop()?, m.pop()?);
+                m.push(if size < U256::from(31) {
+                    let sign_bit = size.as_usize() * 8 + 7;
+                    let mask = (U256::one() << sign_bit) - 1;
+                    if value.bit(sign_bit) {
+                        value | !mask
+                    } else {
+                        value & mask
+                    }
+                } else {
+                    value
+                })?;
+            }
+            // LT, GT, SLT, SGT, EQ
+            0x10..=0x14 => {
+                m.charge(3)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                let result = match opcode {
+                    0x10 => a < b,
+                    0x11 => a > b,
+                    0x12 => signed_lt(a, b),
+                    0x13 => signed_lt(b, a),
+                    _ => a == b,
+                };
+                m.push(U256::from(result as u8))?;
+            }
+            // ISZERO
+            0x15 => {
+                m.charge(3)?;
+                let a = m.pop()?;
+                m.push(U256::from(a.is_zero() as u8))?;
+            }
+            // AND, OR, XOR
+            0x16..=0x18 => {
+                m.charge(3)?;
+                let (a, b) = (m.pop()?, m.pop()?);
+                m.push(match opcode {
+                    0x16 => a & b,
+                    0x17 => a | b,
+                    _ => a ^ b,
+                })?;
+            }
+            // NOT
+            0x19 => {
+                m.charge(3)?;
+                let a = m.pop()?;
+                m.push(!a)?;
+            }
+            // BYTE
+            0x1a => {
+                m.charge(3)?;
+                let (i, value) = (m.pop()?, m.pop()?);
+                m.push(if i < U256::from(32) {
+                    (value >> (8 * (31 - i.as_usize()))) & U256::from(0xff)
+                } else {
+                    U256::zero()
+                })?;
+            }
+            // SHL, SHR
+            0x1b | 0x1c => {
+                m.charge(3)?;
+                let (shift, value) = (m.pop()?, m.pop()?);
+                m.push(if shift >= U256::from(256) {
+                    U256::zero()
+                } else if opcode == 0x1b {
+                    value << shift.as_usize()
+                } else {
+                    value >> shift.as_usize()
+                })?;
+            }
+            // SAR
+            0x1d => {
+                m.charge(3)?;
+                let (shift, value) = (m.pop()?, m.pop()?);
+                let negative = is_negative(value);
+                m.push(if shift >= U256::from(256) {
+                    if negative {
+                        U256::MAX
+                    } else {
+                        U256::zero()
+                    }
+                } else if negative {
+                    !(!value >> shift.as_usize())
+                } else {
+                    value >> shift.as_usize()
+                })?;
+            }
+            // KECCAK256
+            0x20 => {
+                let (offset, size) = (m.pop()?, m.pop()?);
+                let (offset, size) = m.expand(offset, size)?;
+                m.charge(30 + 6 * words(size))?;
+                let hash = keccak256(&m.memory[offset..offset + size]);
+                m.push(U256::from_big_endian(&hash))?;
+            }
+            // ADDRESS
+            0x30 => {
+                m.charge(2)?;
+                m.push(address_to_u256(message.address))?;
+            }
+            // BALANCE
+            0x31 => {
+                let address = decode_address(m.pop()?);
+                m.charge(self.access_account(address))?;
+                m.push(self.balance(&address))?;
+            }
+            // ORIGIN
+            0x32 => {
+                m.charge(2)?;
+                m.push(address_to_u256(input.transaction.from))?;
+            }
+            // CALLER
+            0x33 => {
+                m.charge(2)?;
+                m.push(address_to_u256(message.caller))?;
+            }
+            // CALLVALUE
+            0x34 => {
+                m.charge(2)?;
+                m.push(message.value)?;
+            }
+            // CALLDATALOAD
+            0x35 => {
+                m.charge(3)?;
+                let offset = saturating_usize(m.pop()?);
+                let mut word = [0; 32];
+                for (i, byte) in word.iter_mut().enumerate() {
+                    if let Some(value) = offset.checked_add(i).and_then(|j| message.input.get(j)) {
+                        *byte = *value;
+                    }
+                }
+                m.push(U256::from_big_endian(&word))?;
+            }
+            // CALLDATASIZE
+            0x36 => {
+                m.charge(2)?;
+                m.push(message.input.len().into())?;
+            }
+            // CALLDATACOPY, CODECOPY
+            0x37 | 0x39 => {
+                let (memory_offset, data_offset, size) = (m.pop()?, m.pop()?, m.pop()?);
+                let (memory_offset, size) = m.expand(memory_offset, size)?;
+                m.charge(3 + copy_cost(size))?;
+                let data = if opcode == 0x37 { &message.input } else { code };
+                m.write(memory_offset, size, data, data_offset);
+            }
+            // CODESIZE
+            0x38 => {
+                m.charge(2)?;
+                m.push(code.len().into())?;
+            }
+            // GASPRICE
+            0x3a => {
+                m.charge(2)?;
+                m.push(input.transaction.gas_price)?;
+            }
+            // EXTCODESIZE
+            0x3b => {
+                let address = decode_address(m.pop()?);
+                m.charge(self.access_account(address))?;
+                m.push(self.code(&address).len().into())?;
+            }
+            // EXTCODECOPY
+            0x3c => {
+                let address = decode_address(m.pop()?);
+                let (memory_offset, code_offset, size) = (m.pop()?, m.pop()?, m.pop()?);
+                let (memory_offset, size) = m.expand(memory_offset, size)?;
+                m.charge(self.access_account(address) + copy_cost(size))?;
+                m.write(memory_offset, size, &self.code(&address), code_offset);
+            }
+            // RETURNDATASIZE
+            0x3d => {
+                m.charge(2)?;
+                m.push(m.return_data.len().into())?;
+            }
+            // RETURNDATACOPY
+            0x3e => {
+                let (memory_offset, data_offset, size) = (m.pop()?, m.pop()?, m.pop()?);
+                let (end, overflow) = data_offset.overflowing_add(size);
+                if overflow || end > U256::from(m.return_data.len()) {
+                    return Err("return data out of bounds");
+                }
+                let (memory_offset, size) = m.expand(memory_offset, size)?;
+                m.charge(3 + copy_cost(size))?;
+                let data = m.return_data.clone();
+                m.write(memory_offset, size, &data, data_offset);
+            }
+            // EXTCODEHASH
+            0x3f => {
+                let address = decode_address(m.pop()?);
+                m.charge(self.access_account(address))?;
+                m.push(if self.is_empty(&address) {
+                    U256::zero()
+                } else {
+                    U256::from_big_endian(&keccak256(self.code(&address)))
+                })?;
+            }
+            // BLOCKHASH
+            0x40 => {
+                m.charge(20)?;
+                let number = m.pop()?;
+                let current = input.block.number;
+                let hash = if number < U256::from(current)
+                    && U256::from(current) - number <= U256::from(256)
+                {
+                    input
+                        .block
+                        .block_hashes
+                        .get(&number.as_u64())
+                        .copied()
+                        .unwrap_or_default()
+                } else {
+                    H256::zero()
+                };
+                m.push(U256::from_big_endian(hash.as_bytes()))?;
+            }
+            // COINBASE
+            0x41 => {
+                m.charge(2)?;
+                m.push(address_to_u256(input.block.coinbase))?;
+            }
+            // TIMESTAMP
+            0x42 => {
+                m.charge(2)?;
+                m.push(input.block.timestamp)?;
+            }
+            // NUMBER
+            0x43 => {
+                m.charge(2)?;
+                m.push(input.block.number.into())?;
+            }
+            // DIFFICULTY, PREVRANDAO after the merge
+            0x44 => {
+                m.charge(2)?;
+                m.push(if input.block.difficulty.is_zero() {
+                    input.block.random
+                } else {
+                    input.block.difficulty
+                })?;
+            }
+            // GASLIMIT
+            0x45 => {
+                m.charge(2)?;
+                m.push(input.block.gas_limit)?;
+            }
+            // CHAINID
+            0x46 => {
+                m.charge(2)?;
+                m.push(input.block.chain_id)?;
+            }
+            // SELFBALANCE
+            0x47 => {
+                m.charge(5)?;
+                m.push(self.balance(&message.address))?;
+            }
+            // BASEFEE
+            0x48 => {
+                m.charge(2)?;
+                m.push(input.block.base_fee_per_gas.unwrap_or_default())?;
+            }
+            // POP
+            0x50 => {
+                m.charge(2)?;
+                m.pop()?;
+            }
+            // MLOAD
+            0x51 => {
+                let offset = m.pop()?;
+                let (offset, _) = m.expand(offset, U256::from(32))?;
+                m.charge(3)?;
+                let value = U256::from_big_endian(&m.memory[offset..offset + 32]);
+                m.push(value)?;
+            }
+            // MSTORE
+            0x52 => {
+                let (offset, value) = (m.pop()?, m.pop()?);
+                let (offset, _) = m.expand(offset, U256::from(32))?;
+                m.charge(3)?;
+                value.to_big_endian(&mut m.memory[offset..offset + 32]);
+            }
+            // MSTORE8
+            0x53 => {
+                let (offset, value) = (m.pop()?, m.pop()?);
+                let (offset, _) = m.expand(offset, U256::one())?;
+                m.charge(3)?;
+                m.memory[offset] = value.byte(0);
+            }
+            // SLOAD
+            0x54 => {
+                let key = U256_to_H256(m.pop()?);
+                m.charge(self.access_slot(message.address, key))?;
+                let value = self.storage(&message.address, &key);
+                m.push(U256::from_big_endian(value.as_bytes()))?;
+            }
+            // SSTORE
+            0x55 => {
+                if message.is_static {
+                    return Err(WRITE_PROTECTION);
+                }
+                // EIP-2200 sentry
+                if m.gas <= CALL_STIPEND {
+                    return Err(OUT_OF_GAS);
+                }
+                let (key, value) = (U256_to_H256(m.pop()?), U256_to_H256(m.pop()?));
+                let cold = match self.access_slot(message.address, key) {
+                    COLD_SLOAD_COST => COLD_SLOAD_COST,
+                    _ => 0,
+                };
+                let cost = cold + self.sstore_cost(&message.address, &key, value);
+                m.charge(cost)?;
+                self.state.set_storage(&message.address, key, value);
+            }
+            // JUMP, JUMPI
+            0x56 | 0x57 => {
+                m.charge(if opcode == 0x56 { 8 } else { 10 })?;
+                let destination = m.pop()?;
+                let jump = opcode == 0x56 || !m.pop()?.is_zero();
+                if jump {
+                    let destination = saturating_usize(destination);
+                    if !jump_destinations.get(destination).copied().unwrap_or(false) {
+                        return Err("invalid jump destination");
+                    }
+                    *pc = destination;
+                    return Ok(None);
+                }
+            }
+            // PC
+            0x58 => {
+                m.charge(2)?;
+                m.push((*pc).into())?;
+            }
+            // MSIZE
+            0x59 => {
+                m.charge(2)?;
+                m.push(m.memory.len().into())?;
+            }
+            // GAS
+            0x5a => {
+                m.charge(2)?;
+                m.push(m.gas.into())?;
+            }
+            // JUMPDEST
+            0x5b => m.charge(1)?,
+            // PUSH1 to PUSH32
+            0x60..=0x7f => {
+                m.charge(3)?;
+                let size = (opcode - 0x5f) as usize;
+                let mut word = [0; 32];
+                for i in 0..size {
+                    word[32 - size + i] = code.get(*pc + 1 + i).copied().unwrap_or(0);
+                }
+                m.push(U256::from_big_endian(&word))?;
+                *pc += size;
+            }
+            // DUP1 to DUP16
+            0x80..=0x8f => {
+                m.charge(3)?;
+                let n = (opcode - 0x7f) as usize;
+                if m.stack.len() < n {
+                    return Err("stack underflow");
+                }
+                let value = m.stack[m.stack.len() - n];
+                m.push(value)?;
+            }
+            // SWAP1 to SWAP16
+            0x90..=0x9f => {
+                m.charge(3)?;
+                let n = (opcode - 0x8f) as usize;
+                let top = m.stack.len().checked_sub(1).ok_or("stack underflow")?;
+                let other = top.checked_sub(n).ok_or("stack underflow")?;
+                m.stack.swap(top, other);
+            }
+            // LOG0 to LOG4
+            0xa0..=0xa4 => {
+                if message.is_static {
+                    return Err(WRITE_PROTECTION);
+                }
+                let topics = (opcode - 0xa0) as u64;
+                let (offset, size) = (m.pop()?, m.pop()?);
+                for _ in 0..topics {
+                    m.pop()?;
+                }
+                let (_, size) = m.expand(offset, size)?;
+                m.charge(375 + 375 * topics + 8 * size as u64)?;
+            }
+            // CREATE, CREATE2
+            0xf0 | 0xf5 => {
+                if message.is_static {
+                    return Err(WRITE_PROTECTION);
+                }
+                let (value, offset, size) = (m.pop()?, m.pop()?, m.pop()?);
+                let salt = if opcode == 0xf5 { Some(m.pop()?) } else { None };
+                let (offset, size) = m.expand(offset, size)?;
+                let hashing = if salt.is_some() { 6 * words(size) } else { 0 };
+                m.charge(32000 + hashing)?;
+                let init_code = m.read(offset, size);
+                m.return_data = Bytes::default();
+
+                let nonce = self
+                    .state
+                    .get(&message.address)
+                    .map(|account| account.nonce)
+                    .unwrap_or_default();
+                if depth > MAX_CALL_DEPTH
+                    || self.balance(&message.address) < value
+                    || nonce == u64::MAX
+                {
+                    self.logs[log].gas_cost = 32000 + hashing;
+                    m.push(U256::zero())?;
+                    *pc += 1;
+                    return Ok(None);
+                }
+                let address = match salt {
+                    Some(salt) => get_create2_address_from_hash(
+                        message.address,
+                        U256_to_H256(salt).as_bytes().to_vec(),
+                        keccak256(&init_code),
+                    ),
+                    None => get_contract_address(message.address, nonce),
+                };
+                self.state.increment_nonce(&message.address);
+
+                let gas = m.gas - m.gas / 64;
+                m.charge(gas)?;
+                self.logs[log].gas_cost = 32000 + hashing + gas;
+                let result = self.create(
+                    Message {
+                        call_type: if salt.is_some() {
+                            OP_CREATE2
+                        } else {
+                            OP_CREATE
+                        },
+                        caller: message.address,
+                        address,
+                        code_address: address,
+                        transfer: value,
+                        value,
+                        input: Bytes::default(),
+                        code: init_code,
+                        is_static: false,
+                    },
+                    gas,
+                    depth + 1,
+                );
+                m.gas += result.gas_left;
+                if result.success {
+                    m.push(address_to_u256(address))?;
+                } else {
+                    m.return_data = result.output;
+                    m.push(U256::zero())?;
+                }
+            }
+            // CALL, CALLCODE, DELEGATECALL, STATICCALL
+            0xf1 | 0xf2 | 0xf4 | 0xfa => {
+                let requested_gas = m.pop()?;
+                let to = decode_address(m.pop()?);
+                let value = if opcode == 0xf1 || opcode == 0xf2 {
+                    m.pop()?
+                } else {
+                    U256::zero()
+                };
+                let (in_offset, in_size) = (m.pop()?, m.pop()?);
+                let (out_offset, out_size) = (m.pop()?, m.pop()?);
+                if opcode == 0xf1 && message.is_static && !value.is_zero() {
+                    return Err(WRITE_PROTECTION);
+                }
+                let (in_offset, in_size) = m.expand(in_offset, in_size)?;
+                let (out_offset, out_size) = m.expand(out_offset, out_size)?;
+                let mut cost = self.access_account(to);
+                if !value.is_zero() {
+                    cost += 9000;
+                    if opcode == 0xf1 && self.is_empty(&to) {
+                        cost += 25000;
+                    }
+                }
+                m.charge(cost)?;
+                let available = m.gas - m.gas / 64;
+                let mut gas = if requested_gas > U256::from(available) {
+                    available
+                } else {
+                    requested_gas.as_u64()
+                };
+                m.charge(gas)?;
+                self.logs[log].gas_cost = cost + gas;
+                if !value.is_zero() {
+                    gas += CALL_STIPEND;
+                }
+                let call_input = m.read(in_offset, in_size);
+                m.return_data = Bytes::default();
+
+                if depth > MAX_CALL_DEPTH || self.balance(&message.address) < value {
+                    m.gas += gas;
+                    m.push(U256::zero())?;
+                    *pc += 1;
+                    return Ok(None);
+                }
+                let child = match opcode {
+                    0xf1 => Message {
+                        call_type: OP_CALL,
+                        caller: message.address,
+                        address: to,
+                        code_address: to,
+                        transfer: value,
+                        value,
+                        input: call_input,
+                        code: self.code(&to),
+                        is_static: message.is_static,
+                    },
+                    0xf2 => Message {
+                        call_type: OP_CALLCODE,
+                        caller: message.address,
+                        address: message.address,
+                        code_address: to,
+                        transfer: U256::zero(),
+                        value,
+                        input: call_input,
+                        code: self.code(&to),
+                        is_static: message.is_static,
+                    },
+                    0xf4 => Message {
+                        call_type: OP_DELEGATECALL,
+                        caller: message.caller,
+                        address: message.address,
+                        code_address: to,
+                        transfer: U256::zero(),
+                        value: message.value,
+                        input: call_input,
+                        code: self.code(&to),
+                        is_static: message.is_static,
+                    },
+                    _ => Message {
+                        call_type: OP_STATICCALL,
+                        caller: message.address,
+                        address: to,
+                        code_address: to,
+                        transfer: U256::zero(),
+                        value: U256::zero(),
+                        input: call_input,
+                        code: self.code(&to),
+                        is_static: true,
+                    },
+                };
+                let result = self.call(child, gas, depth + 1);
+                m.gas += result.gas_left;
+                let size = out_size.min(result.output.len());
+                m.memory[out_offset..out_offset + size].copy_from_slice(&result.output[..size]);
+                m.return_data = result.output;
+                m.push(U256::from(result.success as u8))?;
+            }
+            // RETURN, REVERT
+            0xf3 | 0xfd => {
+                let (offset, size) = (m.pop()?, m.pop()?);
+                let (offset, size) = m.expand(offset, size)?;
+                return Ok(Some(FrameResult {
+                    success: opcode == 0xf3,
+                    gas_left: m.gas,
+                    output: m.read(offset, size),
+                }));
+            }
+            // SELFDESTRUCT
+            0xff => {
+                if message.is_static {
+                    return Err(WRITE_PROTECTION);
+                }
+                let beneficiary = decode_address(m.pop()?);
+                let balance = self.balance(&message.address);
+                let mut cost = 5000;
+                if self.access_account(beneficiary) == COLD_ACCOUNT_ACCESS_COST {
+                    cost += COLD_ACCOUNT_ACCESS_COST;
+                }
+                if !balance.is_zero() && self.is_empty(&beneficiary) {
+                    cost += 25000;
+                }
+                m.charge(cost)?;
+                self.touch(&beneficiary);
+                if beneficiary != message.address {
+                    self.state.add_balance(&beneficiary, balance);
+                }
+                self.state.set_balance(&message.address, U256::zero());
+                self.substate.destroyed.insert(message.address);
+                return Ok(Some(FrameResult {
+                    success: true,
+                    gas_left: m.gas,
+                    output: Bytes::default(),
+                }));
+            }
+            // defined by later forks than the one implemented
+            0x5c..=0x5f => {
+                let name = match opcode {
+                    0x5c => "TLOAD",
+                    0x5d => "TSTORE",
+                    0x5e => "MCOPY",
+                    _ => "PUSH0",
+                };
+                self.unsupported
+                    .get_or_insert_with(|| format!("the {} opcode", name));
+                return Err("invalid opcode");
+            }
+            0xfe => return Err("invalid opcode: INVALID"),
+            _ => return Err("invalid opcode"),
+        }
+        *pc += 1;
+        Ok(None)
+    }
+}
+
+/// A transaction executed by the embedded EVM, replayed like the ones traced by a node.
+pub struct EvmSource {
+    pub transaction: Transaction,
+    pub block: Block<Transaction>,
+    /// prestate of the input, plus the accounts the transaction touches
+    pub prestate: EthState,
+    pub poststate: EthState,
+    pub logs: Vec<StructLog>,
+    pub summary: TraceSummary,
+    pub call_tree: Option<EthCallFrame>,
+}
+
+impl EvmSource {
+    pub fn execute(input: &EvmInput) -> anyhow::Result<Self> {
+        let tx = &input.transaction;
+        let block = &input.block;
+        let sender = input
+            .prestate
+            .get(&tx.from)
+            .with_context(|| format!("sender {:?} is not in the prestate", tx.from))?;
+        ensure!(tx.gas.bits() <= 64, "gas limit {} too high", tx.gas);
+        let gas_limit = tx.gas.as_u64();
+        let intrinsic_gas = intrinsic_gas(tx);
+        ensure!(
+            gas_limit >= intrinsic_gas,
+            "gas limit {} below the intrinsic gas {}",
+            gas_limit,
+            intrinsic_gas
+        );
+        let gas_fee = tx.gas.checked_mul(tx.gas_price);
+        let cost = gas_fee.and_then(|gas_fee| gas_fee.checked_add(tx.value));
+        ensure!(
+            cost.is_some(),
+            "gas fee and value of {} gas at {} overflow",
+            tx.gas,
+            tx.gas_price
+        );
+        let (gas_fee, cost) = (gas_fee.unwrap(), cost.unwrap());
+        ensure!(
+            sender.balance >= cost,
+            "sender balance {} below the gas fee and value {}",
+            sender.balance,
+            cost
+        );
+
+        let mut evm = Evm {
+            input,
+            state: JournaledState::new(input.prestate.clone()),
+            substate: Substate::default(),
+            touched: BTreeSet::new(),
+            logs: Vec::new(),
+            frames: Vec::new(),
+            call_tree: None,
+            unsupported: None,
+        };
+        evm.state.sub_balance(&tx.from, gas_fee);
+        evm.state.increment_nonce(&tx.from);
+        evm.touched.insert(tx.from);
+        evm.substate.accounts.insert(tx.from);
+        for (address, _) in ETH_PRECOMPILES {
+            evm.substate
+                .accounts
+                .insert(H160::from_low_u64_be(*address));
+        }
+
+        let gas = gas_limit - intrinsic_gas;
+        let result = match tx.to {
+            Some(to) => {
+                evm.substate.accounts.insert(to);
+                let message = Message {
+                    call_type: OP_CALL,
+                    caller: tx.from,
+                    address: to,
+                    code_address: to,
+                    transfer: tx.value,
+                    value: tx.value,
+                    input: tx.input.clone(),
+                    code: evm.code(&to),
+                    is_static: false,
+                };
+                evm.call(message, gas, 1)
+            }
+            None => {
+                let address = get_contract_address(tx.from, sender.nonce);
+                let message = Message {
+                    call_type: OP_CREATE,
+                    caller: tx.from,
+                    address,
+                    code_address: address,
+                    transfer: tx.value,
+                    value: tx.value,
+                    input: Bytes::default(),
+                    code: tx.input.clone(),
+                    is_static: false,
+                };
+                evm.create(message, gas, 1)
+            }
+        };
+        if let Some(feature) = evm.unsupported {
+            bail!("{} is not supported by the embedded EVM", feature);
+        }
+
+        // EIP-3529 caps the refund to a fifth of the gas used
+        let gas_used = gas_limit - result.gas_left;
+        let refund = (evm.substate.refund.max(0) as u64).min(gas_used / 5);
+        let gas_used = gas_used - refund;
+        evm.state
+            .add_balance(&tx.from, U256::from(gas_limit - gas_used) * tx.gas_price);
+        let mut poststate = evm.state.into_state();
+        for address in &evm.substate.destroyed {
+            poststate.remove(address);
+        }
+        let mut prestate = input.prestate.clone();
+        for address in evm.touched {
+            prestate.entry(address).or_default();
+        }
+
+        let tx_hash = H256(keccak256(serde_json::to_vec(input)?));
+        let block_hash = block
+            .block_hashes
+            .get(&block.number)
+            .copied()
+            .unwrap_or_else(|| H256(keccak256(block.number.to_be_bytes())));
+        let transaction = Transaction {
+            hash: tx_hash,
+            nonce: sender.nonce.into(),
+            block_hash: Some(block_hash),
+            block_number: Some(U64::from(block.number)),
+            transaction_index: Some(U64::zero()),
+            from: tx.from,
+            to: tx.to,
+            value: tx.value,
+            gas_price: Some(tx.gas_price),
+            gas: tx.gas,
+            input: tx.input.clone(),
+            chain_id: Some(block.chain_id),
+            ..Default::default()
+        };
+        let block = Block {
+            hash: Some(block_hash),
+            number: Some(U64::from(block.number)),
+            author: Some(block.coinbase),
+            timestamp: block.timestamp,
+            difficulty: block.difficulty,
+            mix_hash: Some(U256_to_H256(block.random)),
+            gas_limit: block.gas_limit,
+            base_fee_per_gas: block.base_fee_per_gas,
+            transactions: vec![transaction.clone()],
+            ..Default::default()
+        };
+        Ok(Self {
+            transaction,
+            block,
+            prestate,
+            poststate,
+            logs: evm.logs,
+            summary: TraceSummary {
+                failed: !result.success,
+                gas: gas_used,
+                return_value: result.output,
+            },
+            call_tree: evm.call_tree,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl EthTraceSource for EvmSource {
+    async fn transaction(&self, tx_hash: H256) -> anyhow::Result<Transaction> {
+        ensure!(
+            self.transaction.hash == tx_hash,
+            "transaction {:?} not executed",
+            tx_hash
+        );
+        Ok(self.transaction.clone())
+    }
+
+    async fn block(&self, _block_hash: H256) -> anyhow::Result<Block<Transaction>> {
+        Ok(self.block.clone())
+    }
+
+    async fn prestate(&self, _tx_hash: H256) -> anyhow::Result<EthState> {
+        Ok(self.prestate.clone())
+    }
+
+    async fn execution_trace(&self, _tx_hash: H256) -> anyhow::Result<StructLogStream> {
+        Ok(StructLogStream::from_logs(
+            self.logs.clone(),
+            self.summary.clone(),
+        ))
+    }
+
+    async fn code_at(&self, address: H160, _block_number: u64) -> anyhow::Result<Bytes> {
+        Ok(self
+            .poststate
+            .get(&address)
+            .map(|account| account.code.clone())
+            .unwrap_or_default())
+    }
+
+    async fn call_trace(&self, _tx_hash: H256) -> anyhow::Result<Option<EthCallFrame>> {
+        Ok(self.call_tree.clone())
+    }
+}
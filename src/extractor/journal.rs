@@ -0,0 +1,128 @@
+use ethers::types::{Bytes, H160, H256, U256};
+
+use super::types::{EthAccountState, EthState};
+
+/// Previous value of a field changed in the state.
+#[derive(Debug, Clone)]
+enum Change {
+    Balance(H160, U256),
+    Nonce(H160, u64),
+    Code(H160, Bytes),
+    Storage(H160, H256, Option<H256>),
+    /// account created by the change
+    Account(H160),
+}
+
+/// State replayed from a trace, with a journal of its changes so the changes of a
+/// reverted call frame can be undone without keeping a copy of the state per frame.
+#[derive(Debug, Clone, Default)]
+pub struct JournaledState {
+    state: EthState,
+    journal: Vec<Change>,
+    /// journal length when each checkpoint was taken
+    checkpoints: Vec<usize>,
+}
+
+impl JournaledState {
+    pub fn new(state: EthState) -> Self {
+        Self {
+            state,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, address: &H160) -> Option<&EthAccountState> {
+        self.state.get(address)
+    }
+
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.journal.len());
+    }
+
+    /// Keep the changes made since the checkpoints above `depth`, an outer revert still
+    /// undoes them.
+    pub fn discard_checkpoints(&mut self, depth: usize) {
+        self.checkpoints.truncate(depth);
+        if self.checkpoints.is_empty() {
+            // nothing can be reverted anymore
+            self.journal.clear();
+        }
+    }
+
+    /// Undo the changes made since the last checkpoint and drop it.
+    pub fn revert(&mut self) {
+        let checkpoint = self.checkpoints.pop().expect("no checkpoint to revert to");
+        while self.journal.len() > checkpoint {
+            match self.journal.pop().unwrap() {
+                Change::Balance(address, balance) => self.account(&address).balance = balance,
+                Change::Nonce(address, nonce) => self.account(&address).nonce = nonce,
+                Change::Code(address, code) => self.account(&address).code = code,
+                Change::Storage(address, key, Some(val)) => {
+                    self.account(&address).storage.insert(key, val);
+                }
+                Change::Storage(address, key, None) => {
+                    self.account(&address).storage.remove(&key);
+                }
+                Change::Account(address) => {
+                    self.state.remove(&address);
+                }
+            }
+        }
+    }
+
+    fn account(&mut self, address: &H160) -> &mut EthAccountState {
+        self.state
+            .get_mut(address)
+            .unwrap_or_else(|| panic!("{:?} is not in the state", address))
+    }
+
+    /// Add an empty account at `address` if there's none.
+    pub fn create_account(&mut self, address: &H160) {
+        if !self.state.contains_key(address) {
+            self.state.insert(*address, EthAccountState::default());
+            self.journal.push(Change::Account(*address));
+        }
+    }
+
+    pub fn set_balance(&mut self, address: &H160, balance: U256) {
+        let account = self.account(address);
+        let previous = std::mem::replace(&mut account.balance, balance);
+        self.journal.push(Change::Balance(*address, previous));
+    }
+
+    pub fn add_balance(&mut self, address: &H160, value: U256) {
+        let balance = self.account(address).balance + value;
+        self.set_balance(address, balance);
+    }
+
+    pub fn sub_balance(&mut self, address: &H160, value: U256) {
+        let balance = self.account(address).balance - value;
+        self.set_balance(address, balance);
+    }
+
+    pub fn set_nonce(&mut self, address: &H160, nonce: u64) {
+        let account = self.account(address);
+        let previous = std::mem::replace(&mut account.nonce, nonce);
+        self.journal.push(Change::Nonce(*address, previous));
+    }
+
+    pub fn increment_nonce(&mut self, address: &H160) {
+        let nonce = self.account(address).nonce + 1;
+        self.set_nonce(address, nonce);
+    }
+
+    pub fn set_code(&mut self, address: &H160, code: Bytes) {
+        let account = self.account(address);
+        let previous = std::mem::replace(&mut account.code, code);
+        self.journal.push(Change::Code(*address, previous));
+    }
+
+    pub fn set_storage(&mut self, address: &H160, key: H256, val: H256) {
+        let previous = self.account(address).storage.insert(key, val);
+        self.journal.push(Change::Storage(*address, key, previous));
+    }
+
+    pub fn into_state(self) -> EthState {
+        self.state
+    }
+}
@@ -0,0 +1,671 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use ethers::abi::AbiEncode;
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::H256;
+use fil_actor_init::{ExecReturn, Method as InitMethod, State as InitState};
+use fil_actors_runtime::{
+    BURNT_FUNDS_ACTOR_ADDR, INIT_ACTOR_ADDR, REWARD_ACTOR_ADDR, SYSTEM_ACTOR_ADDR,
+};
+use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
+use fvm_ipld_encoding::{Cbor, CborStore, RawBytes, DAG_CBOR};
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::crypto::signature::Signature;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::receipt::Receipt;
+use fvm_shared::state::StateRoot;
+use fvm_shared::{HAMT_BIT_WIDTH, IDENTITY_HASH, IPLD_RAW};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::runtime::Handle;
+
+use crate::cidjson::CidJson;
+use crate::export_car;
+use crate::mock::Actor;
+use crate::tracing_blockstore::TracingBlockStore;
+use crate::vector::{
+    ApplyMessage, GenerationData, MetaData, PostConditions, PreConditions, RandomnessKind,
+    RandomnessMatch, RandomnessRule, StateTreeVector, TestVector, Variant,
+};
+
+/// Messages of the tipset executed before the extracted one which are applied to compute
+/// its pre-state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precursors {
+    /// every message before it, the pre-state is the one the chain executed it on
+    #[default]
+    All,
+    /// only the messages of its sender, enough when the others touch different actors
+    Sender,
+}
+
+impl FromStr for Precursors {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Precursors::All),
+            "sender" => Ok(Precursors::Sender),
+            _ => Err(anyhow!("unknown precursors {}, expected all or sender", s)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TipSet {
+    #[serde(with = "crate::cidjson::vec")]
+    cids: Vec<Cid>,
+    blocks: Vec<BlockHeader>,
+    height: ChainEpoch,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BlockHeader {
+    #[serde(with = "crate::cidjson::vec")]
+    parents: Vec<Cid>,
+    parent_base_fee: String,
+    timestamp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MsgLookup {
+    #[serde(with = "crate::cidjson")]
+    message: Cid,
+    receipt: LotusReceipt,
+    /// tipset whose parent state includes the message execution
+    #[serde(with = "crate::cidjson::vec")]
+    tip_set: Vec<Cid>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LotusReceipt {
+    exit_code: u32,
+    /// base64 return data
+    #[serde(rename = "Return")]
+    return_data: Option<String>,
+    gas_used: i64,
+    #[serde(default, with = "crate::cidjson::opt")]
+    events_root: Option<Cid>,
+}
+
+impl LotusReceipt {
+    fn to_receipt(&self) -> anyhow::Result<Receipt> {
+        let return_data = match &self.return_data {
+            Some(data) => base64::decode(data)?,
+            None => Vec::new(),
+        };
+        Ok(Receipt {
+            exit_code: ExitCode::new(self.exit_code),
+            return_data: RawBytes::new(return_data),
+            gas_used: self.gas_used,
+            events_root: self.events_root,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ParentMessage {
+    #[serde(with = "crate::cidjson")]
+    cid: Cid,
+    /// passed back to `Filecoin.StateCompute` as is
+    message: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ComputeStateOutput {
+    #[serde(with = "crate::cidjson")]
+    root: Cid,
+    trace: Vec<InvocResult>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InvocResult {
+    msg_rct: Option<LotusReceipt>,
+    execution_trace: ExecutionTrace,
+    #[serde(default)]
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ExecutionTrace {
+    msg: MessageTrace,
+    #[serde(default)]
+    msg_rct: Option<LotusReceipt>,
+    #[serde(default)]
+    subcalls: Option<Vec<ExecutionTrace>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageTrace {
+    from: String,
+    to: String,
+    #[serde(default)]
+    method: u64,
+}
+
+impl ExecutionTrace {
+    /// Senders and receivers of the message and of all its subcalls, and the robust
+    /// addresses of the actors they create.
+    fn addresses(&self, addresses: &mut Vec<Address>) -> anyhow::Result<()> {
+        let mut found = vec![
+            Address::from_str(&self.msg.from)?,
+            Address::from_str(&self.msg.to)?,
+        ];
+        found.extend(self.created_robust_address()?);
+        for addr in found {
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+        for call in self.subcalls.iter().flatten() {
+            call.addresses(addresses)?;
+        }
+        Ok(())
+    }
+
+    /// Robust address assigned by the Init actor if this is a successful `Exec` or `Exec4`.
+    fn created_robust_address(&self) -> anyhow::Result<Option<Address>> {
+        let exec = [InitMethod::Exec as u64, InitMethod::Exec4 as u64];
+        if Address::from_str(&self.msg.to)? != INIT_ACTOR_ADDR || !exec.contains(&self.msg.method) {
+            return Ok(None);
+        }
+        let receipt = match &self.msg_rct {
+            Some(receipt) if receipt.exit_code == 0 => receipt.to_receipt()?,
+            _ => return Ok(None),
+        };
+        let ret: ExecReturn = receipt.return_data.deserialize()?;
+        Ok(Some(ret.robust_address))
+    }
+}
+
+fn cid_json(cid: &Cid) -> Value {
+    json!({ "/": cid.to_string() })
+}
+
+fn tipset_key_json(cids: &[Cid]) -> Value {
+    Value::Array(cids.iter().map(cid_json).collect())
+}
+
+/// Raw block of `cid`, checked against its hash.
+async fn read_obj<P: JsonRpcClient>(provider: &Provider<P>, cid: &Cid) -> anyhow::Result<Vec<u8>> {
+    let data: String = provider
+        .request("Filecoin.ChainReadObj", [cid_json(cid)])
+        .await
+        .with_context(|| format!("failed to read block {}", cid))?;
+    let block = base64::decode(data)?;
+    let code = Code::try_from(cid.hash().code())?;
+    ensure!(
+        code.digest(&block) == *cid.hash(),
+        "block {} doesn't match its CID",
+        cid
+    );
+    Ok(block)
+}
+
+async fn chain_get_tipset<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    key: &[Cid],
+) -> anyhow::Result<TipSet> {
+    let tipset: TipSet = provider
+        .request("Filecoin.ChainGetTipSet", [tipset_key_json(key)])
+        .await?;
+    ensure!(!tipset.blocks.is_empty(), "tipset {:?} has no blocks", key);
+    Ok(tipset)
+}
+
+/// State root after applying `messages` at `height` on the state computed by `parent`.
+async fn state_compute<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    height: ChainEpoch,
+    messages: Vec<Value>,
+    parent: &[Cid],
+) -> anyhow::Result<ComputeStateOutput> {
+    let output = provider
+        .request(
+            "Filecoin.StateCompute",
+            json!([height, messages, tipset_key_json(parent)]),
+        )
+        .await?;
+    Ok(output)
+}
+
+/// Unsigned message of `cid`, secp256k1 and delegated messages are stored signed.
+async fn read_message<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    cid: &Cid,
+) -> anyhow::Result<Message> {
+    let bytes = read_obj(provider, cid).await?;
+    fvm_ipld_encoding::from_slice::<(Message, Signature)>(&bytes)
+        .map(|(message, _)| message)
+        .or_else(|_| fvm_ipld_encoding::from_slice::<Message>(&bytes))
+        .with_context(|| format!("{} is not a message", cid))
+}
+
+/// Blocks of a Lotus node, fetched through `Filecoin.ChainReadObj` when first read and kept
+/// in memory. The blockstore is synchronous so it must be used from a multi-threaded tokio
+/// runtime.
+pub struct LotusBlockstore<'a, P> {
+    provider: &'a Provider<P>,
+    runtime: Handle,
+    blocks: MemoryBlockstore,
+}
+
+impl<'a, P: JsonRpcClient> LotusBlockstore<'a, P> {
+    pub fn new(provider: &'a Provider<P>) -> Self {
+        Self {
+            provider,
+            runtime: Handle::current(),
+            blocks: MemoryBlockstore::new(),
+        }
+    }
+}
+
+impl<'a, P: JsonRpcClient> Blockstore for LotusBlockstore<'a, P> {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        if k.hash().code() == IDENTITY_HASH {
+            return Ok(Some(k.hash().digest().to_vec()));
+        }
+        if let Some(block) = self.blocks.get(k)? {
+            return Ok(Some(block));
+        }
+        let block =
+            tokio::task::block_in_place(|| self.runtime.block_on(read_obj(self.provider, k)))?;
+        self.blocks.put_keyed(k, &block)?;
+        Ok(Some(block))
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.blocks.put_keyed(k, block)
+    }
+}
+
+/// CIDs a DAG-CBOR block links to, i.e. its byte strings tagged 42.
+pub fn dag_links(mut data: &[u8]) -> anyhow::Result<Vec<Cid>> {
+    fn read_head(data: &mut &[u8]) -> anyhow::Result<(u8, u64)> {
+        let (&first, rest) = data.split_first().context("truncated CBOR")?;
+        let len = match first & 0x1f {
+            info @ 0..=23 => {
+                *data = rest;
+                return Ok((first >> 5, info as u64));
+            }
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => bail!("indefinite length items are not DAG-CBOR"),
+        };
+        ensure!(rest.len() >= len, "truncated CBOR");
+        let arg = rest[..len]
+            .iter()
+            .fold(0u64, |arg, byte| arg << 8 | *byte as u64);
+        *data = &rest[len..];
+        Ok((first >> 5, arg))
+    }
+
+    let mut links = Vec::new();
+    let mut items = 1u64;
+    let mut tagged_link = false;
+    while items > 0 {
+        items -= 1;
+        let (major, arg) = read_head(&mut data)?;
+        match major {
+            2 | 3 => {
+                let len = usize::try_from(arg)?;
+                ensure!(data.len() >= len, "truncated CBOR");
+                let (bytes, rest) = data.split_at(len);
+                if tagged_link {
+                    // CIDs are prefixed by the multibase identity prefix
+                    ensure!(major == 2 && bytes.first() == Some(&0), "invalid CID link");
+                    links.push(Cid::try_from(&bytes[1..])?);
+                }
+                data = rest;
+            }
+            4 => items += arg,
+            5 => items += 2 * arg,
+            6 => {
+                items += 1;
+                tagged_link = arg == 42;
+                continue;
+            }
+            _ => (),
+        }
+        tagged_link = false;
+    }
+    Ok(links)
+}
+
+/// Read every block reachable from `root` which isn't in `seen` yet.
+pub fn read_dag<BS: Blockstore>(
+    store: &BS,
+    root: Cid,
+    seen: &mut HashSet<Cid>,
+) -> anyhow::Result<()> {
+    walk_dag(store, root, seen, true)
+}
+
+/// Like `read_dag`, but leaves out the raw blocks, e.g. the actor code the manifest of
+/// the System actor links to.
+pub fn read_cbor_dag<BS: Blockstore>(
+    store: &BS,
+    root: Cid,
+    seen: &mut HashSet<Cid>,
+) -> anyhow::Result<()> {
+    walk_dag(store, root, seen, false)
+}
+
+fn walk_dag<BS: Blockstore>(
+    store: &BS,
+    root: Cid,
+    seen: &mut HashSet<Cid>,
+    raw: bool,
+) -> anyhow::Result<()> {
+    let mut pending = vec![root];
+    while let Some(cid) = pending.pop() {
+        if cid.hash().code() == IDENTITY_HASH
+            || (!raw && cid.codec() == IPLD_RAW)
+            || !seen.insert(cid)
+        {
+            continue;
+        }
+        let block = store
+            .get(&cid)?
+            .with_context(|| format!("block {} not found", cid))?;
+        if cid.codec() == DAG_CBOR {
+            pending.extend(dag_links(&block)?);
+        }
+    }
+    Ok(())
+}
+
+/// Read the blocks needed to execute a message touching `addresses` on the state `root`:
+/// the path to each actor in the state tree and its whole state. Only the address lookups
+/// of the Init actor are read, its whole address map would be far too large, and the
+/// System actor's manifest is read without the actor code.
+fn read_state<BS: Blockstore>(
+    store: &BS,
+    root: &Cid,
+    addresses: &[Address],
+    seen: &mut HashSet<Cid>,
+) -> anyhow::Result<()> {
+    let state_root: StateRoot = store
+        .get_cbor(root)?
+        .with_context(|| format!("state root {} not found", root))?;
+    store.get(&state_root.info)?;
+    let actors =
+        Hamt::<&BS, Actor>::load_with_bit_width(&state_root.actors, store, HAMT_BIT_WIDTH)?;
+    let init_actor = actors
+        .get(&INIT_ACTOR_ADDR.to_bytes())?
+        .context("Init actor not found")?;
+    let init: InitState = store
+        .get_cbor(&init_actor.head)?
+        .context("Init actor state not found")?;
+    for addr in addresses {
+        let id_addr = match addr.id() {
+            Ok(_) => Some(*addr),
+            // also reads the path of the addresses the message assigns
+            Err(_) => init.resolve_address(store, addr)?,
+        };
+        let actor = match id_addr {
+            Some(id_addr) => actors.get(&id_addr.to_bytes())?,
+            None => None,
+        };
+        match actor {
+            Some(_) if id_addr == Some(INIT_ACTOR_ADDR) => (),
+            // the code of the actors is in the bundle the vector runs with
+            Some(actor) if id_addr == Some(SYSTEM_ACTOR_ADDR) => {
+                read_cbor_dag(store, actor.head, seen)?
+            }
+            Some(actor) => read_dag(store, actor.head, seen)?,
+            None => (),
+        }
+    }
+    Ok(())
+}
+
+fn load_actors<'bs, BS: Blockstore>(
+    store: &'bs BS,
+    root: &Cid,
+) -> anyhow::Result<Hamt<&'bs BS, Actor>> {
+    let state_root: StateRoot = store
+        .get_cbor(root)?
+        .with_context(|| format!("state root {} not found", root))?;
+    Ok(Hamt::load_with_bit_width(
+        &state_root.actors,
+        store,
+        HAMT_BIT_WIDTH,
+    )?)
+}
+
+/// Delegated addresses of the actors of `addresses` the message creates, which are only
+/// in the post-state.
+fn created_delegated_addresses<BS: Blockstore>(
+    store: &BS,
+    pre_root: &Cid,
+    post_root: &Cid,
+    addresses: &[Address],
+) -> anyhow::Result<Vec<Address>> {
+    let pre_actors = load_actors(store, pre_root)?;
+    let post_actors = load_actors(store, post_root)?;
+    let mut delegated = Vec::new();
+    for addr in addresses.iter().filter(|addr| addr.id().is_ok()) {
+        if pre_actors.get(&addr.to_bytes())?.is_some() {
+            continue;
+        }
+        if let Some(actor) = post_actors.get(&addr.to_bytes())? {
+            delegated.extend(actor.predictable_address);
+        }
+    }
+    Ok(delegated)
+}
+
+/// Extract the message of the FEVM transaction `tx_hash` executed by a Lotus node into the
+/// test vector file at `path`. The pre and post state roots are computed by the node from
+/// the parent state of the tipset including the message and the `precursors` applied
+/// before it, and the vector carries the blocks of the actors the message touches. Nothing
+/// is replayed, so unlike the Ethereum backends it isn't an
+/// [`EthTraceSource`](super::source::EthTraceSource).
+pub async fn export_lotus_test_vector_file<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: H256,
+    path: &Path,
+    precursors: Precursors,
+) -> anyhow::Result<()> {
+    let msg_cid: Option<CidJson> = provider
+        .request("Filecoin.EthGetMessageCidByTransactionHash", [tx_hash])
+        .await?;
+    let msg_cid = msg_cid
+        .with_context(|| format!("transaction {:?} not found", tx_hash))?
+        .0;
+    let lookup: Option<MsgLookup> = provider
+        .request(
+            "Filecoin.StateSearchMsg",
+            json!([[], cid_json(&msg_cid), -1, true]),
+        )
+        .await?;
+    let lookup = lookup.with_context(|| format!("message {} not executed yet", msg_cid))?;
+
+    let execution = chain_get_tipset(provider, &lookup.tip_set).await?;
+    let included = chain_get_tipset(provider, &execution.blocks[0].parents).await?;
+    let parent_key = &included.blocks[0].parents;
+    let parent = chain_get_tipset(provider, parent_key).await?;
+
+    // the parent messages of a block are the messages of its parent tipset in execution order
+    let messages: Vec<ParentMessage> = provider
+        .request(
+            "Filecoin.ChainGetParentMessages",
+            [cid_json(&execution.cids[0])],
+        )
+        .await?;
+    let index = messages
+        .iter()
+        .position(|m| m.cid == lookup.message)
+        .with_context(|| format!("message {} not found in its tipset", lookup.message))?;
+    let sender = &messages[index].message["From"];
+    let mut applied: Vec<Value> = messages[..index]
+        .iter()
+        .filter(|m| precursors == Precursors::All || m.message["From"] == *sender)
+        .map(|m| m.message.clone())
+        .collect();
+    log::info!(
+        "{:?}: message {} at epoch {}, {} precursors",
+        tx_hash,
+        lookup.message,
+        included.height,
+        applied.len()
+    );
+    let pre = state_compute(provider, included.height, applied.clone(), parent_key).await?;
+    applied.push(messages[index].message.clone());
+    let post = state_compute(provider, included.height, applied, parent_key).await?;
+    let invoc = post.trace.last().context("no execution trace")?;
+    ensure!(
+        invoc.error.is_empty(),
+        "{} failed to apply: {}",
+        lookup.message,
+        invoc.error
+    );
+
+    // the post-state is the one computed by the node, so is the expected receipt, it only
+    // matches the chain when every precursor is applied.
+    let on_chain = lookup.receipt.to_receipt()?;
+    let receipt = invoc
+        .msg_rct
+        .as_ref()
+        .with_context(|| format!("no receipt computed for {}", lookup.message))?
+        .to_receipt()?;
+    if receipt != on_chain {
+        let mismatch = format!(
+            "{:?}: receipt {:?} on the computed pre-state, {:?} on chain",
+            tx_hash, receipt, on_chain
+        );
+        // the messages left out by `Precursors::Sender` may change the execution
+        ensure!(precursors == Precursors::Sender, "{}", mismatch);
+        log::warn!("{}", mismatch);
+    }
+    let message = read_message(provider, &lookup.message).await?;
+
+    let mut addresses = vec![
+        SYSTEM_ACTOR_ADDR,
+        INIT_ACTOR_ADDR,
+        REWARD_ACTOR_ADDR,
+        BURNT_FUNDS_ACTOR_ADDR,
+        message.from,
+    ];
+    invoc.execution_trace.addresses(&mut addresses)?;
+    let store = TracingBlockStore::new(LotusBlockstore::new(provider));
+    // the lookups of the addresses a creation assigns are read on the pre-state as well,
+    // where they aren't found yet
+    for addr in created_delegated_addresses(&store, &pre.root, &post.root, &addresses)? {
+        if !addresses.contains(&addr) {
+            addresses.push(addr);
+        }
+    }
+    let mut seen = HashSet::new();
+    read_state(&store, &pre.root, &addresses, &mut seen)?;
+    read_state(&store, &post.root, &addresses, &mut seen)?;
+    if let Some(events_root) = receipt.events_root {
+        read_dag(&store, events_root, &mut seen)?;
+    }
+    log::info!(
+        "{:?}: {} actors, {} blocks",
+        tx_hash,
+        addresses.len(),
+        store.traced.borrow().len()
+    );
+    let car = export_car(&store, vec![pre.root, post.root]).await?;
+
+    // the node computes the state with the base fee, timestamp and randomness of the parent
+    let basefee = parent.blocks[0].parent_base_fee.parse::<u128>()?;
+    let timestamp = parent.blocks.iter().map(|block| block.timestamp).min();
+    const ENTROPY: &[u8] = b"prevrandao";
+    let randomness: String = provider
+        .request(
+            "Filecoin.StateGetRandomnessFromBeacon",
+            json!([
+                10, // fil_actors_runtime::runtime::randomness::DomainSeparationTag::EvmPrevRandao
+                included.height,
+                base64::encode(ENTROPY),
+                tipset_key_json(parent_key)
+            ]),
+        )
+        .await?;
+    let nv: u32 = provider
+        .request(
+            "Filecoin.StateNetworkVersion",
+            [tipset_key_json(&included.cids)],
+        )
+        .await?;
+    let chain_id = provider.get_chainid().await?.as_u64();
+
+    let test_vector = TestVector {
+        class: String::from("message"),
+        chain_id: Some(chain_id),
+        selector: None,
+        meta: Some(MetaData {
+            id: tx_hash.encode_hex(),
+            version: String::from(""),
+            description: String::from(""),
+            comment: format!("message {} at epoch {}", lookup.message, included.height),
+            gen: vec![GenerationData {
+                source: env!("CARGO_PKG_REPOSITORY").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            }],
+        }),
+        car,
+        preconditions: PreConditions {
+            state_tree: StateTreeVector { root_cid: pre.root },
+            basefee: Some(basefee),
+            circ_supply: None,
+            variants: vec![Variant {
+                id: String::from("lotus"),
+                epoch: included.height,
+                timestamp,
+                nv,
+            }],
+        },
+        apply_messages: vec![ApplyMessage {
+            bytes: message.marshal_cbor()?,
+            epoch_offset: None,
+        }],
+        postconditions: PostConditions {
+            state_tree: StateTreeVector {
+                root_cid: post.root,
+            },
+            receipts: vec![receipt],
+        },
+        randomness: vec![RandomnessMatch {
+            on: RandomnessRule {
+                kind: RandomnessKind::Beacon,
+                dst: 10,
+                epoch: included.height,
+                entropy: Vec::from(ENTROPY),
+            },
+            ret: base64::decode(randomness)?,
+        }],
+        skip_compare_gas_used: false,
+        skip_compare_addresses: None,
+        skip_compare_actor_ids: None,
+        additional_compare_addresses: None,
+        tipset_cids: None,
+    };
+    let output = File::create(path)?;
+    serde_json::to_writer_pretty(output, &test_vector)?;
+    Ok(())
+}
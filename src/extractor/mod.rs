@@ -1,3 +1,17 @@
+pub mod backend;
+pub mod batch;
+pub mod cache;
+pub mod evm;
+pub mod journal;
+pub mod lotus;
 pub mod opcodes;
+pub mod policy;
+pub mod precompiles;
+pub mod replay;
+pub mod source;
+pub mod state_diff;
+pub mod trace_stream;
 pub mod transaction;
+pub mod transport;
 pub mod types;
+pub mod watch;
@@ -0,0 +1,227 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+#[cfg(unix)]
+use ethers::providers::IpcError;
+use ethers::providers::{
+    HttpClientError, JsonRpcClient, ProviderError, PubsubClient, WsClientError,
+};
+use ethers::types::U256;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::time::Instant;
+
+/// How RPC requests are sent: failed requests are retried with an exponential backoff,
+/// each attempt can time out, and the request rate can be capped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcPolicy {
+    pub retries: u32,
+    /// delay before the first retry, doubled at each retry
+    pub backoff: Duration,
+    pub timeout: Option<Duration>,
+    /// must be positive
+    pub requests_per_second: Option<f64>,
+}
+
+impl Default for RpcPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            timeout: None,
+            requests_per_second: None,
+        }
+    }
+}
+
+/// Errors worth retrying, e.g. connection failures or rate limiting, as opposed to errors
+/// the node answers the same way every time.
+pub trait TransientError {
+    fn is_transient(&self) -> bool;
+}
+
+/// Whether the JSON-RPC error answered by the node is about the request rate.
+fn is_rate_limited(code: i64, message: &str) -> bool {
+    code == 429 || code == -32005 || message.to_lowercase().contains("rate limit")
+}
+
+impl TransientError for HttpClientError {
+    fn is_transient(&self) -> bool {
+        match self {
+            HttpClientError::JsonRpcError(e) => is_rate_limited(e.code, &e.message),
+            HttpClientError::ReqwestError(_) => true,
+            // error pages of proxies in front of the node, a JSON answer that doesn't
+            // parse would be the same on every attempt
+            HttpClientError::SerdeJson { text, .. } => {
+                serde_json::from_str::<serde::de::IgnoredAny>(text).is_err()
+            }
+        }
+    }
+}
+
+impl TransientError for WsClientError {
+    fn is_transient(&self) -> bool {
+        match self {
+            WsClientError::JsonRpcError(e) => is_rate_limited(e.code, &e.message),
+            WsClientError::JsonError(_) => false,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl TransientError for IpcError {
+    fn is_transient(&self) -> bool {
+        match self {
+            IpcError::JsonRpcError(e) => is_rate_limited(e.code, &e.message),
+            IpcError::JsonError(_) => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PolicyError<E> {
+    #[error(transparent)]
+    Client(E),
+    #[error("{method} timed out after {timeout:?}")]
+    Timeout { method: String, timeout: Duration },
+}
+
+impl<E: TransientError> TransientError for PolicyError<E> {
+    fn is_transient(&self) -> bool {
+        match self {
+            PolicyError::Client(e) => e.is_transient(),
+            PolicyError::Timeout { .. } => true,
+        }
+    }
+}
+
+impl<E> From<PolicyError<E>> for ProviderError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn from(e: PolicyError<E>) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+/// Hands out request slots at most `1 / interval` per second.
+#[derive(Debug, Default)]
+struct RateLimiter {
+    interval: Option<Duration>,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    async fn acquire(&self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next| next.max(now));
+            *next_slot = Some(slot + interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Client sending the requests of `inner` according to an [`RpcPolicy`]. Clones share the
+/// rate limit, so it applies to all the extractions using the same provider.
+#[derive(Debug, Clone)]
+pub struct PolicyClient<C> {
+    inner: C,
+    policy: RpcPolicy,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<C> PolicyClient<C> {
+    pub fn new(inner: C, policy: RpcPolicy) -> Self {
+        let limiter = RateLimiter {
+            interval: policy
+                .requests_per_second
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            ..Default::default()
+        };
+        Self {
+            inner,
+            policy,
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<C: JsonRpcClient> PolicyClient<C> {
+    async fn attempt<T, R>(&self, method: &str, params: &T) -> Result<R, PolicyError<C::Error>>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        self.limiter.acquire().await;
+        let request = self.inner.request(method, params);
+        match self.policy.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, request).await {
+                Ok(res) => res.map_err(PolicyError::Client),
+                Err(_) => Err(PolicyError::Timeout {
+                    method: method.to_owned(),
+                    timeout,
+                }),
+            },
+            None => request.await.map_err(PolicyError::Client),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> JsonRpcClient for PolicyClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: TransientError + Send + Sync + 'static,
+{
+    type Error = PolicyError<C::Error>;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let mut backoff = self.policy.backoff;
+        let mut retries = 0;
+        loop {
+            let e = match self.attempt(method, &params).await {
+                Ok(res) => return Ok(res),
+                Err(e) => e,
+            };
+            if retries >= self.policy.retries || !e.is_transient() {
+                return Err(e);
+            }
+            log::warn!("{} failed, retrying in {:?}: {}", method, backoff, e);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            retries += 1;
+        }
+    }
+}
+
+impl<C> PubsubClient for PolicyClient<C>
+where
+    C: PubsubClient,
+    C::Error: TransientError + Send + Sync + 'static,
+{
+    type NotificationStream = C::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        self.inner.subscribe(id).map_err(PolicyError::Client)
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        self.inner.unsubscribe(id).map_err(PolicyError::Client)
+    }
+}
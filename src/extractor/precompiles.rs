@@ -0,0 +1,78 @@
+use ethers::types::H160;
+
+use super::opcodes::{OP_CALLCODE, OP_DELEGATECALL};
+use crate::extractor::types::EthPrecompileCall;
+
+/// Ethereum precompiles, by address. Like PUSH0, Cancun's point evaluation
+/// at 0x0a belongs to a later fork, so 0x0a is an ordinary account.
+pub const ETH_PRECOMPILES: &[(u64, &str)] = &[
+    (0x01, "ecrecover"),
+    (0x02, "sha256"),
+    (0x03, "ripemd160"),
+    (0x04, "identity"),
+    (0x05, "modexp"),
+    (0x06, "ecadd"),
+    (0x07, "ecmul"),
+    (0x08, "ecpairing"),
+    (0x09, "blake2f"),
+];
+
+/// FEVM specific precompiles, by address `0xfe00..00<id>`.
+pub const FEVM_PRECOMPILES: &[(u64, &str)] = &[
+    (0x01, "resolve_address"),
+    (0x02, "lookup_delegated_address"),
+    (0x03, "call_actor"),
+    (0x04, "get_actor_type"),
+    (0x05, "call_actor_id"),
+];
+
+const FEVM_PRECOMPILE_PREFIX: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrecompileKind {
+    Ethereum,
+    Fevm,
+}
+
+pub fn precompile(addr: &H160) -> Option<(PrecompileKind, &'static str)> {
+    if !addr.0[1..12].iter().all(|b| *b == 0) {
+        return None;
+    }
+    let (kind, precompiles) = match addr.0[0] {
+        0 => (PrecompileKind::Ethereum, ETH_PRECOMPILES),
+        FEVM_PRECOMPILE_PREFIX => (PrecompileKind::Fevm, FEVM_PRECOMPILES),
+        _ => return None,
+    };
+    let id = addr.to_low_u64_be();
+    precompiles
+        .iter()
+        .find(|(addr, _)| *addr == id)
+        .map(|(_, name)| (kind, *name))
+}
+
+pub fn precompile_name(addr: &H160) -> Option<&'static str> {
+    precompile(addr).map(|(_, name)| name)
+}
+
+pub fn is_precompile(addr: &H160) -> bool {
+    precompile(addr).is_some()
+}
+
+/// Explain why FEVM would execute the precompile call differently than Geth did,
+/// gas aside since it's never compared.
+pub fn fevm_difference(call: &EthPrecompileCall) -> Option<String> {
+    let (kind, name) = precompile(&call.address)?;
+    if kind == PrecompileKind::Fevm {
+        return Some(format!(
+            "{} is a FEVM precompile, Ethereum executed it as an empty account",
+            name
+        ));
+    }
+    if !call.value.is_zero() {
+        return Some(format!("value transferred to {} precompile", name));
+    }
+    if call.call_type == OP_DELEGATECALL || call.call_type == OP_CALLCODE {
+        return Some(format!("{} precompile called by {}", name, call.call_type));
+    }
+    None
+}
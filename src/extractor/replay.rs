@@ -0,0 +1,577 @@
+use anyhow::{bail, ensure, Context};
+use ethers::prelude::*;
+use ethers::utils::{get_contract_address, keccak256};
+use fil_actor_eam::EthAddress;
+
+use super::journal::JournaledState;
+use super::opcodes::*;
+use super::precompiles::is_precompile;
+use super::source::EthTraceSource;
+use super::transaction::{
+    decode_address, finish_test_vector, transaction_test_vector, U256_to_H256,
+};
+use super::types::{EthAccesses, EthCallFrame, EthTransactionTestVector};
+use crate::util::compute_address_create2;
+
+/// Extract the test vector of the transaction `tx_hash` from `source`.
+pub async fn extract_test_vector<S: EthTraceSource + ?Sized>(
+    source: &S,
+    tx_hash: H256,
+) -> anyhow::Result<EthTransactionTestVector> {
+    let transaction = source.transaction(tx_hash).await?;
+    let block_hash = transaction
+        .block_hash
+        .with_context(|| format!("transaction {:?} is pending", tx_hash))?;
+    let block = source.block(block_hash).await?;
+
+    let mut vector = transaction_test_vector(&transaction, &block);
+    replay_transaction(source, &transaction, &mut vector).await?;
+    finish_test_vector(&mut vector);
+    Ok(vector)
+}
+
+/// Replay the structLogs of `transaction` on top of its prestate, filling the states,
+/// the receipt fields and the observations of `vector`.
+pub async fn replay_transaction<S: EthTraceSource + ?Sized>(
+    source: &S,
+    transaction: &Transaction,
+    vector: &mut EthTransactionTestVector,
+) -> anyhow::Result<()> {
+    let tx_hash = transaction.hash;
+    let next_block_number = vector.block_number + 1;
+
+    let tx_from = transaction.from;
+    let tx_to = transaction
+        .to
+        .unwrap_or_else(|| get_contract_address(tx_from, transaction.nonce));
+
+    // accounts involved in the transaction, with the slots it accesses only
+    let prestate = source.prestate(tx_hash).await?;
+
+    // all state modification made by this transaction will apply to poststate,
+    // it's based on prestate.
+    let mut poststate = prestate.clone();
+
+    // trace the state-change made by this transaction through structLogger tracer,
+    // which is the default tracer of Geth traceTransaction RPC.
+    // Note: there seems be a "diff mode" of prestate tracer, but it's not available
+    // currently on latest Geth release(v1.10.26)
+    let mut struct_logs = source.execution_trace(tx_hash).await?;
+
+    // input and output of inner calls live in memory, which is not captured by
+    // the structLogger, take them from the call trace of the source instead.
+    let traced_calls = match source.call_trace(tx_hash).await {
+        Ok(traced) => traced,
+        Err(e) => {
+            log::warn!("failed to trace calls of {:?}: {}", tx_hash, e);
+            None
+        }
+    };
+
+    let sender_account = poststate.get_mut(&tx_from).unwrap();
+
+    // calculate gas fee(including leftover gas)
+    let gas_price = transaction.gas_price.unwrap();
+    let gas_fee = gas_price * transaction.gas;
+    sender_account.balance -= gas_fee;
+
+    // increase sender nonce
+    sender_account.nonce += 1;
+
+    // used to track real execution context(e.g. which contract's storage is read, written)
+    let mut execution_contexts = vec![tx_to];
+    // used to handle reverting and other errors, the first checkpoint should
+    // be taken after gas fee deduction but tx value transfer
+    let mut poststate = JournaledState::new(poststate);
+    poststate.checkpoint();
+    // accounts and slots the execution depends on, used to minimize the prestate
+    let mut accesses = EthAccesses::default();
+    accesses.accounts.insert(tx_from);
+    accesses.accounts.insert(tx_to);
+    accesses.codes.insert(tx_to);
+    // call frames being executed, one per depth
+    let mut frames = vec![PendingFrame::new(
+        if transaction.to.is_none() {
+            OP_CREATE
+        } else {
+            OP_CALL
+        },
+        tx_from,
+        tx_to,
+        transaction.value,
+        transaction.gas,
+    )];
+    frames[0].frame.input = transaction.input.clone();
+
+    if transaction.to.is_none() {
+        // FIXME The contract may have self-destructed.
+        // We can get runtime code of the created contract(ether created by
+        // topmost transaction or created by CREATE,CREATE2 opcode) from
+        // memory in structLog(created by structLogger tracer), this require
+        // us to enable memory trace option, but this would result in
+        // huge response size on some transactions.
+        let code = source.code_at(tx_to, next_block_number).await?;
+        poststate.create_account(&tx_to);
+        poststate.set_nonce(&tx_to, 1);
+        poststate.set_code(&tx_to, code);
+    }
+
+    // transaction value transfer
+    if !transaction.value.is_zero() {
+        poststate.sub_balance(&tx_from, transaction.value);
+        poststate.add_balance(&tx_to, transaction.value);
+    }
+
+    // start to apply changes made by tx on poststate
+    let mut depth = 1u64;
+    while let Some(log) = struct_logs.next().await {
+        let log = &log;
+
+        if depth > log.depth {
+            depth = log.depth;
+            execution_contexts.truncate(depth.try_into().unwrap());
+
+            // the first log back in the caller has the call result on top of the stack
+            let success = log
+                .stack
+                .as_ref()
+                .and_then(|stack| stack.last())
+                .map(|result| !result.is_zero())
+                .unwrap_or(false);
+            // a creation failing on the code deposit leaves no error in its logs
+            let callee = frames.get(usize::try_from(depth).unwrap());
+            if !success && callee.map_or(false, |callee| !callee.frame.reverted) {
+                poststate.revert();
+            }
+            poststate.discard_checkpoints(depth.try_into().unwrap());
+            // the address of a created contract is on the stack once the creation returns
+            if let Some(callee) = callee.filter(|callee| success && callee.create_pc.is_some()) {
+                let created = decode_address(*log.stack.as_ref().unwrap().last().unwrap());
+                ensure!(
+                    created == callee.frame.callee,
+                    "{} at pc {} created {:?} instead of {:?}",
+                    callee.frame.call_type,
+                    callee.create_pc.unwrap(),
+                    created,
+                    callee.frame.callee
+                );
+                // FIXME
+                let code = source.code_at(created, next_block_number).await?;
+                poststate.set_code(&created, code);
+            }
+            close_frames(&mut frames, depth.try_into().unwrap(), success);
+        }
+        frames.last_mut().unwrap().enter(log);
+
+        // handle opcodes that might change the state
+        match log.op.as_str() {
+            OP_SSTORE => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let key = U256_to_H256(stack[stack.len() - 1]);
+                let val = U256_to_H256(stack[stack.len() - 2]);
+
+                poststate.set_storage(execution_contexts.last().unwrap(), key, val);
+
+                accesses
+                    .storage_writes
+                    .entry(*execution_contexts.last().unwrap())
+                    .or_default()
+                    .insert(key);
+                frames
+                    .last_mut()
+                    .unwrap()
+                    .frame
+                    .storage_writes
+                    .insert(key, val);
+            }
+            OP_SLOAD => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let key = U256_to_H256(stack[stack.len() - 1]);
+
+                accesses
+                    .storage_reads
+                    .entry(*execution_contexts.last().unwrap())
+                    .or_default()
+                    .insert(key);
+            }
+            OP_BALANCE => {
+                let stack = log.stack.as_ref().unwrap();
+
+                accesses
+                    .balances
+                    .insert(decode_address(stack[stack.len() - 1]));
+            }
+            OP_SELFBALANCE => {
+                accesses
+                    .balances
+                    .insert(*execution_contexts.last().unwrap());
+            }
+            OP_EXTCODESIZE | OP_EXTCODECOPY | OP_EXTCODEHASH => {
+                let stack = log.stack.as_ref().unwrap();
+
+                accesses
+                    .codes
+                    .insert(decode_address(stack[stack.len() - 1]));
+            }
+            // a call failing before it starts, e.g. out of gas or a stack underflow, is only
+            // an error, it reverts the frame running it
+            OP_CALL if log.error.is_none() => {
+                poststate.checkpoint();
+
+                let stack = log.stack.as_ref().unwrap();
+
+                let address = decode_address(stack[stack.len() - 2]);
+
+                let value = stack[stack.len() - 3];
+
+                if !value.is_zero() {
+                    let caller = execution_contexts.last().unwrap();
+
+                    // In some cases, the "CALL" will fail without any error and there's no "revert".
+                    if depth <= 1024 && poststate.get(caller).unwrap().balance >= value {
+                        poststate.sub_balance(caller, value);
+
+                        poststate.add_balance(&address, value);
+                    }
+                }
+
+                accesses.accounts.insert(address);
+                accesses.codes.insert(address);
+                frames.push(PendingFrame::new(
+                    OP_CALL,
+                    *execution_contexts.last().unwrap(),
+                    address,
+                    value,
+                    stack[stack.len() - 1],
+                ));
+                execution_contexts.push(address);
+
+                depth += 1;
+            }
+            OP_STATICCALL if log.error.is_none() => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let address = decode_address(stack[stack.len() - 2]);
+
+                accesses.accounts.insert(address);
+                accesses.codes.insert(address);
+                frames.push(PendingFrame::new(
+                    OP_STATICCALL,
+                    *execution_contexts.last().unwrap(),
+                    address,
+                    U256::zero(),
+                    stack[stack.len() - 1],
+                ));
+                execution_contexts.push(address);
+                poststate.checkpoint();
+
+                depth += 1;
+            }
+            OP_DELEGATECALL if log.error.is_none() => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let address = decode_address(stack[stack.len() - 2]);
+
+                accesses.codes.insert(address);
+                frames.push(PendingFrame::new(
+                    OP_DELEGATECALL,
+                    *execution_contexts.last().unwrap(),
+                    address,
+                    U256::zero(),
+                    stack[stack.len() - 1],
+                ));
+                execution_contexts.push(*execution_contexts.last().unwrap());
+                poststate.checkpoint();
+
+                depth += 1;
+            }
+            OP_CALLCODE if log.error.is_none() => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let address = decode_address(stack[stack.len() - 2]);
+
+                accesses.codes.insert(address);
+                frames.push(PendingFrame::new(
+                    OP_CALLCODE,
+                    *execution_contexts.last().unwrap(),
+                    address,
+                    stack[stack.len() - 3],
+                    stack[stack.len() - 1],
+                ));
+                execution_contexts.push(*execution_contexts.last().unwrap());
+                poststate.checkpoint();
+
+                depth += 1;
+            }
+            // a creation failing before it starts, e.g. out of gas, is only an error
+            OP_CREATE | OP_CREATE2 if log.error.is_none() => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let value = stack[stack.len() - 1];
+
+                let caller = *execution_contexts.last().unwrap();
+
+                // In some cases, the creation will fail without any error and there's no "revert",
+                // the nonce is still increased unless the creation couldn't start.
+                let starts = depth <= 1024 && poststate.get(&caller).unwrap().balance >= value;
+
+                // the address is checked against the one on the stack when the creation
+                // returns, the code of a failed creation runs at the address it would have had.
+                let salt = (log.op == OP_CREATE2).then(|| U256_to_H256(stack[stack.len() - 4]));
+                let address = match salt {
+                    None => Some(get_contract_address(
+                        caller,
+                        poststate.get(&caller).unwrap().nonce,
+                    )),
+                    // the creation code is only known if the tracer captured the memory,
+                    // otherwise the address is taken from the call trace.
+                    Some(salt) => memory_slice(log, stack[stack.len() - 2], stack[stack.len() - 3])
+                        .map(|init_code| create2_address(caller, salt, &init_code))
+                        .or_else(|| {
+                            traced_callee(traced_calls.as_ref(), &frames)
+                                .filter(|traced| traced.call_type == OP_CREATE2)
+                                .map(|traced| traced.callee)
+                        }),
+                };
+                let address = match address {
+                    Some(address) => address,
+                    None if starts => bail!(
+                        "{} at pc {} needs the memory or a call trace to find its address",
+                        log.op,
+                        log.pc
+                    ),
+                    None => H160::zero(),
+                };
+
+                if starts {
+                    poststate.increment_nonce(&caller);
+                }
+                poststate.checkpoint();
+
+                // undone with the changes of the frame if the creation fails
+                if starts {
+                    poststate.create_account(&address);
+                    poststate.set_nonce(&address, 1);
+
+                    if !value.is_zero() {
+                        poststate.sub_balance(&caller, value);
+
+                        poststate.add_balance(&address, value);
+                    }
+                }
+
+                if !address.is_zero() {
+                    accesses.accounts.insert(address);
+                }
+                let mut frame = PendingFrame::new(&log.op, caller, address, value, U256::zero());
+                frame.frame.salt = salt;
+                frame.create_pc = Some(log.pc);
+                frames.push(frame);
+                execution_contexts.push(address);
+
+                depth += 1;
+            }
+            OP_SELFDESTRUCT => {
+                let stack = log.stack.as_ref().unwrap();
+                let beneficiary = decode_address(stack[stack.len() - 1]);
+                accesses.accounts.insert(beneficiary);
+
+                let caller = execution_contexts.last().unwrap();
+
+                let caller_balance = poststate.get(caller).unwrap().balance;
+                if caller_balance != 0.into() {
+                    poststate.add_balance(&beneficiary, caller_balance);
+                }
+
+                // consider delete the account?
+                poststate.set_balance(caller, 0.into());
+                poststate.set_nonce(caller, 0);
+                poststate.set_code(caller, Bytes::default());
+            }
+            OP_BLOCKHASH => {
+                let stack = log.stack.as_ref().unwrap();
+
+                let stack_after = struct_logs.peek().await.unwrap().stack.clone().unwrap();
+
+                let num = stack[stack.len() - 1].as_u64();
+                let hash = stack_after[stack_after.len() - 1];
+                let mut bytes = [0; 32];
+                hash.to_big_endian(&mut bytes);
+                vector.block_hashes.insert(num, bytes.into());
+            }
+            OP_REVERT => {
+                poststate.revert();
+                frames.last_mut().unwrap().frame.reverted = true;
+            }
+            OP_INVALID => {
+                poststate.revert();
+                frames.last_mut().unwrap().frame.reverted = true;
+            }
+            _ => (),
+        }
+
+        if log.error.is_some() {
+            poststate.revert();
+            frames.last_mut().unwrap().frame.reverted = true;
+        }
+    }
+    let transaction_trace = struct_logs.finish()?;
+    if transaction_trace.failed && !frames[0].frame.reverted {
+        // the creation of the transaction failed on the code deposit
+        poststate.revert();
+    }
+
+    // refund unused gas to tx sender
+    // Note: Some opcodes(e.g. SSTORE) have additional gas refund. But it seems that
+    // we don't need further handling it, because there's no opcode gas refund on FEVM?
+    let leftover_gas = transaction.gas - transaction_trace.gas;
+    poststate.add_balance(&tx_from, leftover_gas * gas_price);
+    let poststate = poststate.into_state();
+
+    close_frames(&mut frames, 1, !transaction_trace.failed);
+    let mut call_tree = frames.pop().unwrap().frame;
+    call_tree.reverted = transaction_trace.failed;
+    call_tree.output = transaction_trace.return_value.clone();
+    call_tree.gas_used = transaction_trace.gas;
+    if let Some(traced) = &traced_calls {
+        merge_call_frame(&mut call_tree, traced)?;
+    }
+
+    vector.prestate = prestate;
+    vector.poststate = poststate;
+    vector.status = if transaction_trace.failed { 0 } else { 1 };
+    vector.gas_used = transaction_trace.gas.into();
+    vector.return_value = transaction_trace.return_value;
+    vector.accesses = Some(accesses);
+    vector.call_tree = Some(call_tree);
+    Ok(())
+}
+
+/// Copy the input and output of the `traced` frames, and check the addresses CREATE2
+/// computed from the creation code.
+fn merge_call_frame(frame: &mut EthCallFrame, traced: &EthCallFrame) -> anyhow::Result<()> {
+    if frame.call_type != traced.call_type {
+        log::warn!(
+            "call tree mismatch, replayed {} but traced {}",
+            frame.call_type,
+            traced.call_type
+        );
+        return Ok(());
+    }
+    frame.input = traced.input.clone();
+    frame.output = traced.output.clone();
+    frame.gas_used = traced.gas_used;
+    if let Some(salt) = frame.salt.filter(|_| !frame.reverted) {
+        let expected = create2_address(frame.caller, salt, &frame.input);
+        ensure!(
+            frame.callee == expected,
+            "CREATE2 created {:?} instead of {:?}",
+            frame.callee,
+            expected
+        );
+    }
+    if frame.calls.len() != traced.calls.len() {
+        log::warn!(
+            "call tree mismatch in {} to {:?}, replayed {} calls but traced {}",
+            frame.call_type,
+            frame.callee,
+            frame.calls.len(),
+            traced.calls.len()
+        );
+        return Ok(());
+    }
+    for (call, traced_call) in frame.calls.iter_mut().zip(traced.calls.iter()) {
+        merge_call_frame(call, traced_call)?;
+    }
+    Ok(())
+}
+
+/// Address CREATE2 deploys `init_code` at from `deployer` with `salt`.
+fn create2_address(deployer: H160, salt: H256, init_code: &[u8]) -> H160 {
+    let address = compute_address_create2(&EthAddress(deployer.0), &salt.0, &keccak256(init_code));
+    H160(address.0)
+}
+
+/// The `size` bytes from `offset` of the memory before the operation of `log`, `None` if
+/// the tracer didn't capture the memory.
+fn memory_slice(log: &StructLog, offset: U256, size: U256) -> Option<Vec<u8>> {
+    let memory = hex::decode(log.memory.as_ref()?.concat()).ok()?;
+    let end = offset.checked_add(size)?;
+    if end.bits() > 32 {
+        return None;
+    }
+    let (offset, end) = (offset.as_usize(), end.as_usize());
+    // the operation expands the memory with zeros
+    let mut bytes = vec![0; end - offset];
+    if offset < memory.len() {
+        let available = end.min(memory.len());
+        bytes[..available - offset].copy_from_slice(&memory[offset..available]);
+    }
+    Some(bytes)
+}
+
+/// The frame of `traced` for the call about to be opened by the innermost of `frames`,
+/// the replayed and traced trees have the same shape.
+fn traced_callee<'a>(
+    traced: Option<&'a EthCallFrame>,
+    frames: &[PendingFrame],
+) -> Option<&'a EthCallFrame> {
+    let mut traced = traced?;
+    // the index of an open frame among the calls of its caller is the number of calls
+    // the caller has closed
+    for caller in &frames[..frames.len() - 1] {
+        traced = traced.calls.get(caller.frame.calls.len())?;
+    }
+    traced.calls.get(frames.last()?.frame.calls.len())
+}
+
+/// A call frame being replayed, `entered` tells whether any opcode ran in it.
+struct PendingFrame {
+    frame: EthCallFrame,
+    entered: bool,
+    /// pc of the CREATE or CREATE2 opening the frame
+    create_pc: Option<u64>,
+}
+
+impl PendingFrame {
+    fn new(call_type: &str, caller: H160, callee: H160, value: U256, gas: U256) -> Self {
+        Self {
+            frame: EthCallFrame {
+                call_type: call_type.to_owned(),
+                caller,
+                callee,
+                value,
+                gas: gas.low_u64(),
+                ..Default::default()
+            },
+            entered: false,
+            create_pc: None,
+        }
+    }
+
+    fn enter(&mut self, log: &StructLog) {
+        if !self.entered {
+            self.entered = true;
+            self.frame.gas = log.gas;
+        }
+    }
+}
+
+/// Close the frames deeper than `depth`, attaching them to their caller.
+fn close_frames(frames: &mut Vec<PendingFrame>, depth: usize, success: bool) {
+    while frames.len() > depth.max(1) {
+        let returning = frames.len() == depth + 1;
+        let mut frame = frames.pop().unwrap();
+        if returning && !success {
+            frame.frame.reverted = true;
+            // calls failing before entering the callee, e.g. insufficient balance,
+            // don't open a frame.
+            if !frame.entered && !is_precompile(&frame.frame.callee) {
+                continue;
+            }
+        }
+        frames.last_mut().unwrap().frame.calls.push(frame.frame);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::providers::Provider;
+use ethers::utils;
+use serde::{Deserialize, Serialize};
+
+use super::cache::{CachedRpc, RpcCache};
+use super::replay::extract_test_vector;
+use super::trace_stream::StructLogStream;
+use super::transaction::struct_log_tracing_options;
+use super::types::{EthCallFrame, EthState, EthTransactionTestVector};
+
+/// Where the data a transaction is replayed from comes from, see
+/// [`replay_transaction`](super::replay::replay_transaction).
+///
+/// Only the backends with a structLog-like trace implement it. The `stateDiff` of the
+/// parity backend already holds both states, so
+/// [`StateDiffSource`](super::state_diff::StateDiffSource) is a [`TestVectorSource`] with
+/// nothing to replay. Lotus messages aren't replayed either: their vectors carry the FVM
+/// state of the node instead of Ethereum states, see
+/// [`export_lotus_test_vector_file`](super::lotus::export_lotus_test_vector_file).
+#[async_trait(?Send)]
+pub trait EthTraceSource {
+    async fn transaction(&self, tx_hash: H256) -> anyhow::Result<Transaction>;
+
+    /// block with its transactions
+    async fn block(&self, block_hash: H256) -> anyhow::Result<Block<Transaction>>;
+
+    /// Accounts involved in the transaction before it runs, with the slots it accesses.
+    async fn prestate(&self, tx_hash: H256) -> anyhow::Result<EthState>;
+
+    /// structLogs of the transaction, with the stack of each log.
+    async fn execution_trace(&self, tx_hash: H256) -> anyhow::Result<StructLogStream>;
+
+    /// Code of `address` at the end of block `block_number`.
+    async fn code_at(&self, address: H160, block_number: u64) -> anyhow::Result<Bytes>;
+
+    /// Call tree with the input and output of the inner calls, which the structLogs
+    /// don't have, `None` if the source can't tell.
+    async fn call_trace(&self, _tx_hash: H256) -> anyhow::Result<Option<EthCallFrame>> {
+        Ok(None)
+    }
+}
+
+/// Where a whole test vector comes from, either a transaction replayed from an
+/// [`EthTraceSource`] or the `stateDiff` of the node, see
+/// [`StateDiffSource`](super::state_diff::StateDiffSource).
+#[async_trait(?Send)]
+pub trait TestVectorSource {
+    async fn test_vector(&self, tx_hash: H256) -> anyhow::Result<EthTransactionTestVector>;
+}
+
+#[async_trait(?Send)]
+impl<S: EthTraceSource + ?Sized> TestVectorSource for S {
+    async fn test_vector(&self, tx_hash: H256) -> anyhow::Result<EthTransactionTestVector> {
+        extract_test_vector(self, tx_hash).await
+    }
+}
+
+pub(crate) async fn get_transaction<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    tx_hash: H256,
+) -> anyhow::Result<Transaction> {
+    rpc.request::<_, Option<_>>("eth_getTransactionByHash", [tx_hash])
+        .await?
+        .with_context(|| format!("transaction {:?} not found", tx_hash))
+}
+
+pub(crate) async fn get_block<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    block_hash: H256,
+) -> anyhow::Result<Block<Transaction>> {
+    rpc.request::<_, Option<_>>("eth_getBlockByHash", (block_hash, true))
+        .await?
+        .with_context(|| format!("block {:?} not found", block_hash))
+}
+
+pub(crate) async fn get_code<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    address: H160,
+    block_number: u64,
+) -> anyhow::Result<Bytes> {
+    let block: BlockId = block_number.into();
+    rpc.request("eth_getCode", (address, block)).await
+}
+
+/// Call frame as reported by the callTracer of Geth.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallTracerFrame {
+    #[serde(rename = "type")]
+    pub call_type: String,
+    #[serde(default)]
+    pub from: H160,
+    /// callee, or the created contract address
+    #[serde(default)]
+    pub to: Option<H160>,
+    #[serde(default)]
+    pub gas_used: U256,
+    #[serde(default)]
+    pub input: Bytes,
+    #[serde(default)]
+    pub output: Bytes,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallTracerFrame>,
+}
+
+impl From<&CallTracerFrame> for EthCallFrame {
+    fn from(traced: &CallTracerFrame) -> Self {
+        EthCallFrame {
+            call_type: traced.call_type.clone(),
+            caller: traced.from,
+            callee: traced.to.unwrap_or_default(),
+            input: traced.input.clone(),
+            output: traced.output.clone(),
+            gas_used: traced.gas_used.low_u64(),
+            calls: traced.calls.iter().map(EthCallFrame::from).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// The `debug_traceTransaction` tracers of a geth node, also served by Erigon and Anvil.
+pub struct GethSource<'a, P> {
+    rpc: CachedRpc<'a, P>,
+}
+
+impl<'a, P: JsonRpcClient> GethSource<'a, P> {
+    /// Source of the transaction `tx_hash`, the RPC responses are taken from `cache` when
+    /// they're there, and added to it otherwise.
+    pub fn new(provider: &'a Provider<P>, tx_hash: H256, cache: Option<&'a RpcCache>) -> Self {
+        Self {
+            rpc: CachedRpc {
+                provider,
+                cache,
+                tx_hash,
+            },
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a, P: JsonRpcClient> EthTraceSource for GethSource<'a, P> {
+    async fn transaction(&self, tx_hash: H256) -> anyhow::Result<Transaction> {
+        get_transaction(&self.rpc, tx_hash).await
+    }
+
+    async fn block(&self, block_hash: H256) -> anyhow::Result<Block<Transaction>> {
+        get_block(&self.rpc, block_hash).await
+    }
+
+    // Get pre-transaction state simply by built-in prestate tracer of Geth,
+    // all accounts involved in the transaction will be traced, (accounts accessed by
+    // BALANCE, EXTCODE* opcode are also included), each account state consists of
+    // nonce, balance, code and storage(accessed slots only).
+    // see https://geth.ethereum.org/docs/developers/evm-tracing/built-in-tracers#prestate-tracer
+    // for more info about prestate trace.
+    async fn prestate(&self, tx_hash: H256) -> anyhow::Result<EthState> {
+        let prestate_tracing_options: GethDebugTracingOptions = GethDebugTracingOptions {
+            tracer: Some("prestateTracer".to_owned()),
+            ..Default::default()
+        };
+        self.rpc
+            .request(
+                "debug_traceTransaction",
+                [
+                    utils::serialize(&tx_hash),
+                    utils::serialize(&prestate_tracing_options),
+                ],
+            )
+            .await
+    }
+
+    // The trace can take gigabytes, its logs are parsed while they're replayed.
+    async fn execution_trace(&self, tx_hash: H256) -> anyhow::Result<StructLogStream> {
+        Ok(StructLogStream::new(
+            self.rpc
+                .request_reader(
+                    "debug_traceTransaction",
+                    [
+                        utils::serialize(&tx_hash),
+                        utils::serialize(&struct_log_tracing_options()),
+                    ],
+                )
+                .await?,
+        ))
+    }
+
+    async fn code_at(&self, address: H160, block_number: u64) -> anyhow::Result<Bytes> {
+        get_code(&self.rpc, address, block_number).await
+    }
+
+    async fn call_trace(&self, tx_hash: H256) -> anyhow::Result<Option<EthCallFrame>> {
+        let call_tracing_options: GethDebugTracingOptions = GethDebugTracingOptions {
+            tracer: Some("callTracer".to_owned()),
+            ..Default::default()
+        };
+        let traced: CallTracerFrame = self
+            .rpc
+            .request(
+                "debug_traceTransaction",
+                [
+                    utils::serialize(&tx_hash),
+                    utils::serialize(&call_tracing_options),
+                ],
+            )
+            .await?;
+        Ok(Some(EthCallFrame::from(&traced)))
+    }
+}
+
+/// Everything the replay of a transaction needs, recorded in a single JSON file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceFixture {
+    pub transaction: Transaction,
+    pub block: Block<Transaction>,
+    pub prestate: EthState,
+    /// result of the structLogger tracer
+    pub trace: serde_json::Value,
+    /// result of the callTracer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calls: Option<CallTracerFrame>,
+    /// code of the contracts created by the transaction
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub codes: BTreeMap<H160, Bytes>,
+}
+
+/// A transaction recorded in a [`TraceFixture`], replayed without a node.
+pub struct FixtureSource {
+    pub fixture: TraceFixture,
+}
+
+impl FixtureSource {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let fixture = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("invalid trace fixture {:?}", path))?;
+        Ok(Self { fixture })
+    }
+}
+
+#[async_trait(?Send)]
+impl EthTraceSource for FixtureSource {
+    async fn transaction(&self, tx_hash: H256) -> anyhow::Result<Transaction> {
+        anyhow::ensure!(
+            self.fixture.transaction.hash == tx_hash,
+            "transaction {:?} not recorded",
+            tx_hash
+        );
+        Ok(self.fixture.transaction.clone())
+    }
+
+    async fn block(&self, block_hash: H256) -> anyhow::Result<Block<Transaction>> {
+        anyhow::ensure!(
+            self.fixture.block.hash == Some(block_hash),
+            "block {:?} not recorded",
+            block_hash
+        );
+        Ok(self.fixture.block.clone())
+    }
+
+    async fn prestate(&self, _tx_hash: H256) -> anyhow::Result<EthState> {
+        Ok(self.fixture.prestate.clone())
+    }
+
+    async fn execution_trace(&self, _tx_hash: H256) -> anyhow::Result<StructLogStream> {
+        let trace = serde_json::to_vec(&self.fixture.trace)?;
+        Ok(StructLogStream::new(Cursor::new(trace)))
+    }
+
+    async fn code_at(&self, address: H160, _block_number: u64) -> anyhow::Result<Bytes> {
+        self.fixture
+            .codes
+            .get(&address)
+            .cloned()
+            .with_context(|| format!("code of {:?} not recorded", address))
+    }
+
+    async fn call_trace(&self, _tx_hash: H256) -> anyhow::Result<Option<EthCallFrame>> {
+        Ok(self.fixture.calls.as_ref().map(EthCallFrame::from))
+    }
+}
@@ -0,0 +1,728 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use anyhow::{ensure, Context};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{
+    Block, BlockId, Bytes, StructLog, Transaction, TransactionReceipt, H160, H256, U256, U64,
+};
+use ethers::utils::get_contract_address;
+use serde::Deserialize;
+
+use super::cache::{CachedRpc, RpcCache};
+use super::opcodes::*;
+use super::precompiles::is_precompile;
+use super::source::{get_block, get_code, get_transaction, EthTraceSource, TestVectorSource};
+use super::trace_stream::{StructLogStream, TraceSummary};
+use super::transaction::{decode_address, finish_test_vector, transaction_test_vector};
+use super::types::{
+    EthAccesses, EthAccountState, EthCallFrame, EthState, EthTransactionTestVector,
+};
+
+/// Change of an account field in a `stateDiff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub enum Diff<T> {
+    /// unchanged, the value isn't given
+    #[default]
+    #[serde(rename = "=")]
+    Same,
+    /// set on an account or a slot which didn't exist
+    #[serde(rename = "+")]
+    Born(T),
+    /// cleared by the deletion of the account or of the slot
+    #[serde(rename = "-")]
+    Died(T),
+    #[serde(rename = "*")]
+    Changed { from: T, to: T },
+}
+
+impl<T: Clone + Default> Diff<T> {
+    /// Values before and after the transaction, `None` if unchanged.
+    pub fn values(&self) -> Option<(T, T)> {
+        match self {
+            Diff::Same => None,
+            Diff::Born(value) => Some((T::default(), value.clone())),
+            Diff::Died(value) => Some((value.clone(), T::default())),
+            Diff::Changed { from, to } => Some((from.clone(), to.clone())),
+        }
+    }
+}
+
+/// Changes of an account in a `stateDiff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct AccountDiff {
+    #[serde(default)]
+    pub balance: Diff<U256>,
+    #[serde(default)]
+    pub nonce: Diff<U256>,
+    #[serde(default)]
+    pub code: Diff<Bytes>,
+    #[serde(default)]
+    pub storage: BTreeMap<H256, Diff<H256>>,
+}
+
+pub type StateDiff = BTreeMap<H160, AccountDiff>;
+
+/// Result of `trace_replayTransaction` with the `trace`, `stateDiff` and `vmTrace` traces.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayTrace {
+    #[serde(default)]
+    pub output: Bytes,
+    #[serde(default)]
+    pub trace: Vec<TraceEntry>,
+    pub state_diff: Option<StateDiff>,
+    pub vm_trace: Option<VmTrace>,
+}
+
+/// One call, creation or self-destruct of the flat `trace`, in depth-first order.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    #[serde(rename = "type")]
+    pub trace_type: String,
+    pub action: TraceAction,
+    pub result: Option<TraceResult>,
+    pub error: Option<String>,
+    /// path from the top-level call
+    pub trace_address: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceAction {
+    /// call, staticcall, delegatecall or callcode
+    pub call_type: Option<String>,
+    /// create or create2, only reported by some clients
+    pub creation_method: Option<String>,
+    pub from: Option<H160>,
+    pub to: Option<H160>,
+    #[serde(default)]
+    pub value: U256,
+    #[serde(default)]
+    pub gas: U256,
+    #[serde(default)]
+    pub input: Bytes,
+    /// init code of creations
+    #[serde(default)]
+    pub init: Bytes,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceResult {
+    #[serde(default)]
+    pub gas_used: U256,
+    #[serde(default)]
+    pub output: Bytes,
+    /// address of the created contract
+    pub address: Option<H160>,
+    /// code of the created contract
+    #[serde(default)]
+    pub code: Bytes,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VmTrace {
+    pub code: Bytes,
+    pub ops: Vec<VmOperation>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VmOperation {
+    pub pc: usize,
+    #[serde(default)]
+    pub cost: u64,
+    /// `None` if the operation failed
+    pub ex: Option<VmExecuted>,
+    /// trace of the frame entered by a call or a creation
+    pub sub: Option<VmTrace>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VmExecuted {
+    /// gas left after the operation
+    #[serde(default)]
+    pub used: u64,
+    /// stack items written by the operation
+    #[serde(default)]
+    pub push: Vec<U256>,
+}
+
+fn u256_to_h256(value: U256) -> H256 {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    H256(bytes)
+}
+
+/// Accounts and slots a `vmTrace` depends on, found by replaying the stack of its frames.
+#[derive(Debug, Clone, Default)]
+pub struct VmAccesses {
+    pub accesses: EthAccesses,
+    /// values of the slots read before the transaction writes them, per storage context
+    pub reads: BTreeMap<H160, BTreeMap<H256, H256>>,
+    /// hashes returned by BLOCKHASH
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+impl VmAccesses {
+    /// Record the accesses of the frame `trace` executed with the storage of `context`.
+    pub fn record(&mut self, trace: &VmTrace, context: H160) {
+        let mut stack: Vec<U256> = Vec::new();
+        for op in &trace.ops {
+            // running past the end of the code is a STOP
+            let opcode = trace.code.get(op.pc).copied().unwrap_or(0);
+            let inputs = stack.split_off(stack.len().saturating_sub(stack_inputs(opcode)));
+            // `n`th input from the top of the stack
+            let input = |n: usize| {
+                inputs
+                    .len()
+                    .checked_sub(n + 1)
+                    .map(|i| inputs[i])
+                    .unwrap_or_default()
+            };
+            let pushed = op.ex.as_ref().map(|ex| ex.push.as_slice()).unwrap_or(&[]);
+            let output = pushed.first().copied().unwrap_or_default();
+
+            let callee = match opcode_name(opcode) {
+                Some(OP_SLOAD) => {
+                    let key = u256_to_h256(input(0));
+                    let written = self
+                        .accesses
+                        .storage_writes
+                        .get(&context)
+                        .map_or(false, |writes| writes.contains(&key));
+                    if op.ex.is_some() && !written {
+                        self.reads
+                            .entry(context)
+                            .or_default()
+                            .entry(key)
+                            .or_insert_with(|| u256_to_h256(output));
+                    }
+                    self.accesses
+                        .storage_reads
+                        .entry(context)
+                        .or_default()
+                        .insert(key);
+                    None
+                }
+                Some(OP_SSTORE) => {
+                    self.accesses
+                        .storage_writes
+                        .entry(context)
+                        .or_default()
+                        .insert(u256_to_h256(input(0)));
+                    None
+                }
+                Some(OP_BALANCE) => {
+                    self.accesses.balances.insert(decode_address(input(0)));
+                    None
+                }
+                Some(OP_SELFBALANCE) => {
+                    self.accesses.balances.insert(context);
+                    None
+                }
+                Some(OP_EXTCODESIZE | OP_EXTCODECOPY | OP_EXTCODEHASH) => {
+                    self.accesses.codes.insert(decode_address(input(0)));
+                    None
+                }
+                Some(OP_CALL | OP_STATICCALL) => {
+                    let address = decode_address(input(1));
+                    self.accesses.accounts.insert(address);
+                    self.accesses.codes.insert(address);
+                    Some(address)
+                }
+                Some(OP_DELEGATECALL | OP_CALLCODE) => {
+                    self.accesses.codes.insert(decode_address(input(1)));
+                    Some(context)
+                }
+                Some(OP_CREATE | OP_CREATE2) => {
+                    // a failed creation pushes zero
+                    let address = decode_address(output);
+                    if !address.is_zero() {
+                        self.accesses.accounts.insert(address);
+                    }
+                    Some(address)
+                }
+                Some(OP_SELFDESTRUCT) => {
+                    self.accesses.accounts.insert(decode_address(input(0)));
+                    None
+                }
+                Some(OP_BLOCKHASH) => {
+                    if op.ex.is_some() {
+                        self.block_hashes
+                            .insert(input(0).low_u64(), u256_to_h256(output));
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let (Some(callee), Some(sub)) = (callee, &op.sub) {
+                self.record(sub, callee);
+            }
+            if op.ex.is_none() {
+                break;
+            }
+            stack.extend_from_slice(pushed);
+        }
+    }
+}
+
+/// Call frame of a `trace` entry, `None` for self-destructs and rewards.
+fn call_frame(entry: &TraceEntry) -> Option<EthCallFrame> {
+    let action = &entry.action;
+    let result = entry.result.clone().unwrap_or_default();
+    let call_type = match entry.trace_type.as_str() {
+        "call" => match action.call_type.as_deref() {
+            Some("staticcall") => OP_STATICCALL,
+            Some("delegatecall") => OP_DELEGATECALL,
+            Some("callcode") => OP_CALLCODE,
+            _ => OP_CALL,
+        },
+        "create" => match action.creation_method.as_deref() {
+            Some("create2") => OP_CREATE2,
+            _ => OP_CREATE,
+        },
+        _ => return None,
+    };
+    let creation = entry.trace_type == "create";
+    Some(EthCallFrame {
+        call_type: call_type.to_owned(),
+        caller: action.from.unwrap_or_default(),
+        callee: if creation {
+            result.address.unwrap_or_default()
+        } else {
+            action.to.unwrap_or_default()
+        },
+        value: action.value,
+        input: if creation {
+            action.init.clone()
+        } else {
+            action.input.clone()
+        },
+        output: if creation { result.code } else { result.output },
+        gas: action.gas.low_u64(),
+        gas_used: result.gas_used.low_u64(),
+        reverted: entry.error.is_some(),
+        ..Default::default()
+    })
+}
+
+/// Call tree of the flat `trace` of a transaction.
+pub fn call_tree(trace: &[TraceEntry]) -> Option<EthCallFrame> {
+    fn close(frames: &mut Vec<EthCallFrame>, depth: usize) {
+        while frames.len() > depth.max(1) {
+            let frame = frames.pop().unwrap();
+            frames.last_mut().unwrap().calls.push(frame);
+        }
+    }
+
+    // the entries are in depth-first order, so a frame is complete once an entry which
+    // isn't deeper comes
+    let mut frames = Vec::new();
+    for entry in trace {
+        close(&mut frames, entry.trace_address.len());
+        if let Some(frame) = call_frame(entry) {
+            frames.push(frame);
+        }
+    }
+    close(&mut frames, 1);
+    frames.pop()
+}
+
+/// Account fields last set by the transactions of the block before the extracted one.
+#[derive(Debug, Clone, Default)]
+struct PriorChanges {
+    balance: Option<U256>,
+    nonce: Option<U256>,
+    code: Option<Bytes>,
+}
+
+/// One transaction of `trace_replayBlockTransactions`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockTransactionReplay {
+    transaction_hash: Option<H256>,
+    state_diff: Option<StateDiff>,
+}
+
+/// Fields changed by the transactions of the block of `transaction` which run before it,
+/// from their `stateDiff`. Replaying the whole block is only needed when it isn't the
+/// first transaction.
+async fn prior_changes<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    transaction: &Transaction,
+    block_number: u64,
+) -> anyhow::Result<BTreeMap<H160, PriorChanges>> {
+    let mut changes: BTreeMap<H160, PriorChanges> = BTreeMap::new();
+    let index = transaction
+        .transaction_index
+        .map_or(0, |index| index.as_usize());
+    if index == 0 {
+        return Ok(changes);
+    }
+    let replays: Vec<BlockTransactionReplay> = rpc
+        .request(
+            "trace_replayBlockTransactions",
+            (U64::from(block_number), ["stateDiff"]),
+        )
+        .await?;
+    ensure!(
+        replays.get(index).map_or(false, |replay| replay
+            .transaction_hash
+            .map_or(true, |hash| hash == transaction.hash)),
+        "{:?} is not transaction {} of the replay of block {}",
+        transaction.hash,
+        index,
+        block_number
+    );
+    for replay in &replays[..index] {
+        let state_diff = replay
+            .state_diff
+            .as_ref()
+            .context("trace_replayBlockTransactions returned no stateDiff")?;
+        for (address, diff) in state_diff {
+            let account = changes.entry(*address).or_default();
+            if let Some((_, balance)) = diff.balance.values() {
+                account.balance = Some(balance);
+            }
+            if let Some((_, nonce)) = diff.nonce.values() {
+                account.nonce = Some(nonce);
+            }
+            if let Some((_, code)) = diff.code.values() {
+                account.code = Some(code);
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Pre and post states of `address`, the fields unchanged by the transaction are the ones
+/// `prior` transactions of the block set or taken from `block`, and the slots it only
+/// reads from `reads`.
+async fn account_states<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    address: H160,
+    diff: &AccountDiff,
+    reads: Option<&BTreeMap<H256, H256>>,
+    prior: Option<&PriorChanges>,
+    block: BlockId,
+) -> anyhow::Result<(EthAccountState, EthAccountState)> {
+    let (pre_balance, post_balance) = match (diff.balance.values(), prior.and_then(|p| p.balance)) {
+        (Some(values), _) => values,
+        (None, Some(balance)) => (balance, balance),
+        (None, None) => {
+            let balance: U256 = rpc.request("eth_getBalance", (address, block)).await?;
+            (balance, balance)
+        }
+    };
+    let (pre_nonce, post_nonce) = match (diff.nonce.values(), prior.and_then(|p| p.nonce)) {
+        (Some(values), _) => values,
+        (None, Some(nonce)) => (nonce, nonce),
+        (None, None) => {
+            let nonce: U256 = rpc
+                .request("eth_getTransactionCount", (address, block))
+                .await?;
+            (nonce, nonce)
+        }
+    };
+    let (pre_code, post_code) = match (diff.code.values(), prior.and_then(|p| p.code.clone())) {
+        (Some(values), _) => values,
+        (None, Some(code)) => (code.clone(), code),
+        (None, None) => {
+            let code: Bytes = rpc.request("eth_getCode", (address, block)).await?;
+            (code.clone(), code)
+        }
+    };
+    let mut pre = EthAccountState {
+        nonce: pre_nonce.as_u64(),
+        balance: pre_balance,
+        code: pre_code,
+        storage: reads.cloned().unwrap_or_default(),
+    };
+    let mut post = EthAccountState {
+        nonce: post_nonce.as_u64(),
+        balance: post_balance,
+        code: post_code,
+        storage: pre.storage.clone(),
+    };
+    for (key, slot) in &diff.storage {
+        if let Some((pre_value, post_value)) = slot.values() {
+            pre.storage.insert(*key, pre_value);
+            post.storage.insert(*key, post_value);
+        }
+    }
+    Ok((pre, post))
+}
+
+/// Pre and post states of the accounts of the `stateDiff` of `replay`, and of the accounts
+/// and slots its `vmTrace` accesses. The values of the slots only read are found in the
+/// `vmTrace`, the other values the transaction doesn't change are the ones at the end of
+/// the previous block, updated with the changes of the transactions before it in the block.
+async fn diff_states<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    transaction: &Transaction,
+    replay: &ReplayTrace,
+) -> anyhow::Result<(EthState, EthState, VmAccesses)> {
+    let state_diff = replay
+        .state_diff
+        .as_ref()
+        .context("trace_replayTransaction returned no stateDiff")?;
+
+    let tx_from = transaction.from;
+    let tx_to = transaction
+        .to
+        .unwrap_or_else(|| get_contract_address(tx_from, transaction.nonce));
+    let mut vm = VmAccesses::default();
+    vm.accesses.accounts.insert(tx_from);
+    vm.accesses.accounts.insert(tx_to);
+    vm.accesses.codes.insert(tx_to);
+    match &replay.vm_trace {
+        Some(vm_trace) => vm.record(vm_trace, tx_to),
+        None => log::warn!(
+            "{:?}: no vmTrace, the slots only read are missing",
+            transaction.hash
+        ),
+    }
+
+    let mut addresses: BTreeSet<H160> = state_diff.keys().copied().collect();
+    addresses.extend(vm.accesses.accounts.iter().copied());
+    addresses.extend(vm.accesses.balances.iter().copied());
+    addresses.extend(vm.accesses.codes.iter().copied());
+    addresses.extend(vm.reads.keys().copied());
+    let block_number = transaction
+        .block_number
+        .with_context(|| format!("transaction {:?} is pending", transaction.hash))?
+        .as_u64();
+    let parent_block: BlockId = block_number.saturating_sub(1).into();
+    let prior = prior_changes(rpc, transaction, block_number).await?;
+    let mut prestate = EthState::new();
+    let mut poststate = EthState::new();
+    for address in addresses {
+        if is_precompile(&address) {
+            continue;
+        }
+        let diff = state_diff.get(&address).cloned().unwrap_or_default();
+        let (pre, post) = account_states(
+            rpc,
+            address,
+            &diff,
+            vm.reads.get(&address),
+            prior.get(&address),
+            parent_block,
+        )
+        .await?;
+        prestate.insert(address, pre);
+        poststate.insert(address, post);
+    }
+    Ok((prestate, poststate, vm))
+}
+
+async fn get_receipt<P: JsonRpcClient>(
+    rpc: &CachedRpc<'_, P>,
+    tx_hash: H256,
+) -> anyhow::Result<TransactionReceipt> {
+    rpc.request::<_, Option<_>>("eth_getTransactionReceipt", [tx_hash])
+        .await?
+        .with_context(|| format!("no receipt for {:?}", tx_hash))
+}
+
+/// `stateDiff` of `trace_replayTransaction` of Erigon, Nethermind and OpenEthereum, the
+/// states are taken from it instead of replayed, see [`diff_states`].
+pub struct StateDiffSource<'a, P> {
+    rpc: CachedRpc<'a, P>,
+}
+
+impl<'a, P: JsonRpcClient> StateDiffSource<'a, P> {
+    pub fn new(provider: &'a Provider<P>, tx_hash: H256, cache: Option<&'a RpcCache>) -> Self {
+        Self {
+            rpc: CachedRpc {
+                provider,
+                cache,
+                tx_hash,
+            },
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a, P: JsonRpcClient> TestVectorSource for StateDiffSource<'a, P> {
+    async fn test_vector(&self, tx_hash: H256) -> anyhow::Result<EthTransactionTestVector> {
+        let rpc = &self.rpc;
+        let transaction = get_transaction(rpc, tx_hash).await?;
+        let block_hash = transaction
+            .block_hash
+            .with_context(|| format!("transaction {:?} is pending", tx_hash))?;
+        let block = get_block(rpc, block_hash).await?;
+        let mut vector = transaction_test_vector(&transaction, &block);
+
+        let replay: ReplayTrace = rpc
+            .request(
+                "trace_replayTransaction",
+                (tx_hash, ["trace", "stateDiff", "vmTrace"]),
+            )
+            .await?;
+        let receipt = get_receipt(rpc, tx_hash).await?;
+        let (prestate, mut poststate, mut vm) = diff_states(rpc, &transaction, &replay).await?;
+        vector.block_hashes.append(&mut vm.block_hashes);
+
+        let gas_used = receipt.gas_used.unwrap_or_default();
+        // the priority fee goes to the coinbase, not to the FEVM block producer
+        if let Some(coinbase) = poststate.get_mut(&vector.coinbase) {
+            if vector.coinbase != transaction.from {
+                let tip = vector
+                    .gas_price
+                    .saturating_sub(vector.base_fee_per_gas.unwrap_or_default());
+                coinbase.balance = coinbase.balance.saturating_sub(tip * gas_used);
+            }
+        }
+
+        vector.prestate = prestate;
+        vector.poststate = poststate;
+        vector.status = receipt.status.map_or(1, |status| status.as_u64());
+        vector.gas_used = gas_used;
+        vector.return_value = replay.output;
+        if replay.vm_trace.is_some() {
+            vector.accesses = Some(vm.accesses);
+        }
+        vector.call_tree = call_tree(&replay.trace);
+        finish_test_vector(&mut vector);
+        Ok(vector)
+    }
+}
+
+/// Pass the structLogs of the frame `trace` at `depth` and of the frames it enters to
+/// `emit` until it returns false, returned then. The stack of each log is rebuilt from the
+/// items the previous operations pushed.
+pub fn struct_logs(trace: &VmTrace, depth: u64, emit: &mut dyn FnMut(StructLog) -> bool) -> bool {
+    let mut stack: Vec<U256> = Vec::new();
+    for op in &trace.ops {
+        let opcode = trace.code.get(op.pc).copied().unwrap_or(0);
+        let log = StructLog {
+            depth,
+            error: op.ex.is_none().then(|| "execution failed".to_owned()),
+            gas: op.ex.as_ref().map_or(op.cost, |ex| ex.used + op.cost),
+            gas_cost: op.cost,
+            op: struct_log_op(opcode),
+            pc: op.pc as u64,
+            stack: Some(stack.clone()),
+            ..Default::default()
+        };
+        if !emit(log) {
+            return false;
+        }
+        if let Some(sub) = &op.sub {
+            if !struct_logs(sub, depth + 1, emit) {
+                return false;
+            }
+        }
+        match &op.ex {
+            Some(ex) => {
+                stack.truncate(stack.len().saturating_sub(stack_inputs(opcode)));
+                stack.extend_from_slice(&ex.push);
+            }
+            None => break,
+        }
+    }
+    true
+}
+
+/// `trace_replayTransaction` of Erigon, Nethermind and OpenEthereum, its `vmTrace` is
+/// replayed as structLogs on top of the prestate taken from its `stateDiff`.
+pub struct VmTraceSource<'a, P> {
+    rpc: CachedRpc<'a, P>,
+    transaction: RefCell<Option<Transaction>>,
+    replay: RefCell<Option<Arc<ReplayTrace>>>,
+    /// diffed once, it needs the changes of the transactions before it in the block
+    prestate: RefCell<Option<EthState>>,
+}
+
+impl<'a, P: JsonRpcClient> VmTraceSource<'a, P> {
+    pub fn new(provider: &'a Provider<P>, tx_hash: H256, cache: Option<&'a RpcCache>) -> Self {
+        Self {
+            rpc: CachedRpc {
+                provider,
+                cache,
+                tx_hash,
+            },
+            transaction: RefCell::new(None),
+            replay: RefCell::new(None),
+            prestate: RefCell::new(None),
+        }
+    }
+
+    /// The traces of `tx_hash`, fetched once as the vmTrace can be big.
+    async fn replay(&self, tx_hash: H256) -> anyhow::Result<Arc<ReplayTrace>> {
+        if let Some(replay) = self.replay.borrow().as_ref() {
+            return Ok(replay.clone());
+        }
+        let replay: Arc<ReplayTrace> = Arc::new(
+            self.rpc
+                .request(
+                    "trace_replayTransaction",
+                    (tx_hash, ["trace", "stateDiff", "vmTrace"]),
+                )
+                .await?,
+        );
+        *self.replay.borrow_mut() = Some(replay.clone());
+        Ok(replay)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a, P: JsonRpcClient> EthTraceSource for VmTraceSource<'a, P> {
+    async fn transaction(&self, tx_hash: H256) -> anyhow::Result<Transaction> {
+        if let Some(transaction) = self.transaction.borrow().as_ref() {
+            return Ok(transaction.clone());
+        }
+        let transaction = get_transaction(&self.rpc, tx_hash).await?;
+        *self.transaction.borrow_mut() = Some(transaction.clone());
+        Ok(transaction)
+    }
+
+    async fn block(&self, block_hash: H256) -> anyhow::Result<Block<Transaction>> {
+        get_block(&self.rpc, block_hash).await
+    }
+
+    async fn prestate(&self, tx_hash: H256) -> anyhow::Result<EthState> {
+        if let Some(prestate) = self.prestate.borrow().as_ref() {
+            return Ok(prestate.clone());
+        }
+        let transaction = self.transaction(tx_hash).await?;
+        let replay = self.replay(tx_hash).await?;
+        let (prestate, _, _) = diff_states(&self.rpc, &transaction, &replay).await?;
+        *self.prestate.borrow_mut() = Some(prestate.clone());
+        Ok(prestate)
+    }
+
+    // The vmTrace is converted to structLogs as they're replayed.
+    async fn execution_trace(&self, tx_hash: H256) -> anyhow::Result<StructLogStream> {
+        let replay = self.replay(tx_hash).await?;
+        ensure!(
+            replay.vm_trace.is_some(),
+            "trace_replayTransaction returned no vmTrace"
+        );
+        let receipt = get_receipt(&self.rpc, tx_hash).await?;
+        let summary = TraceSummary {
+            failed: receipt.status.map_or(false, |status| status.is_zero()),
+            gas: receipt.gas_used.unwrap_or_default().as_u64(),
+            return_value: replay.output.clone(),
+        };
+        Ok(StructLogStream::from_producer(move |emit| {
+            if let Some(vm_trace) = &replay.vm_trace {
+                struct_logs(vm_trace, 1, emit);
+            }
+            Ok(summary)
+        }))
+    }
+
+    async fn code_at(&self, address: H160, block_number: u64) -> anyhow::Result<Bytes> {
+        get_code(&self.rpc, address, block_number).await
+    }
+
+    async fn call_trace(&self, tx_hash: H256) -> anyhow::Result<Option<EthCallFrame>> {
+        Ok(call_tree(&self.replay(tx_hash).await?.trace))
+    }
+}
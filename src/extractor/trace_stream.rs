@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::thread::JoinHandle;
+
+use anyhow::anyhow;
+use ethers::types::{Bytes, StructLog};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt};
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+/// Number of logs parsed ahead of the replay.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Fields of a structLog trace besides the logs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceSummary {
+    pub failed: bool,
+    pub gas: u64,
+    pub return_value: Bytes,
+}
+
+/// The structLogs of a trace, parsed on a separate thread as they're consumed, so the
+/// whole trace is never held in memory.
+pub struct StructLogStream {
+    logs: mpsc::Receiver<StructLog>,
+    parser: JoinHandle<anyhow::Result<TraceSummary>>,
+    /// logs read ahead by `find_ahead` and `peek`, not consumed yet
+    ahead: VecDeque<StructLog>,
+}
+
+impl StructLogStream {
+    /// Parse the `debug_traceTransaction` result read from `reader`.
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        let (sender, logs) = mpsc::channel(CHANNEL_CAPACITY);
+        let parser = std::thread::spawn(move || {
+            let mut de = serde_json::Deserializer::from_reader(BufReader::new(reader));
+            let summary = de.deserialize_map(TraceVisitor { logs: sender })?;
+            de.end()?;
+            Ok(summary)
+        });
+        Self {
+            logs,
+            parser,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    /// Stream logs already in memory.
+    pub fn from_logs(logs: Vec<StructLog>, summary: TraceSummary) -> Self {
+        Self::from_producer(move |emit| {
+            for log in logs {
+                if !emit(log) {
+                    break;
+                }
+            }
+            Ok(summary)
+        })
+    }
+
+    /// Stream the logs `produce` passes to its callback, e.g. converted from another trace
+    /// format, as they're consumed. The callback returns false once the stream is finished
+    /// before consuming all the logs.
+    pub fn from_producer<F>(produce: F) -> Self
+    where
+        F: FnOnce(&mut dyn FnMut(StructLog) -> bool) -> anyhow::Result<TraceSummary>
+            + Send
+            + 'static,
+    {
+        let (mut sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let parser =
+            std::thread::spawn(move || produce(&mut |log| block_on(sender.send(log)).is_ok()));
+        Self {
+            logs: receiver,
+            parser,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<StructLog> {
+        match self.ahead.pop_front() {
+            Some(log) => Some(log),
+            None => self.logs.next().await,
+        }
+    }
+
+    /// The log after the last one consumed.
+    pub async fn peek(&mut self) -> Option<&StructLog> {
+        self.find_ahead(|_| true).await
+    }
+
+    /// The first log not consumed yet matching `predicate`, the logs before it are kept
+    /// in memory until they're consumed.
+    pub async fn find_ahead<F: Fn(&StructLog) -> bool>(
+        &mut self,
+        predicate: F,
+    ) -> Option<&StructLog> {
+        let mut i = match self.ahead.iter().position(&predicate) {
+            Some(i) => return self.ahead.get(i),
+            None => self.ahead.len(),
+        };
+        loop {
+            let log = self.logs.next().await?;
+            let found = predicate(&log);
+            self.ahead.push_back(log);
+            if found {
+                return self.ahead.get(i);
+            }
+            i += 1;
+        }
+    }
+
+    /// Wait for the parser and return the rest of the trace, fails if the trace is
+    /// malformed. The logs not consumed yet are dropped.
+    pub fn finish(self) -> anyhow::Result<TraceSummary> {
+        // unblock the parser if it's waiting for room in the channel
+        drop(self.logs);
+        self.parser
+            .join()
+            .map_err(|_| anyhow!("structLog parser panicked"))?
+    }
+}
+
+struct TraceVisitor {
+    logs: mpsc::Sender<StructLog>,
+}
+
+impl<'de> Visitor<'de> for TraceVisitor {
+    type Value = TraceSummary;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a structLog trace")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<TraceSummary, A::Error> {
+        let mut summary = TraceSummary::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "failed" => summary.failed = map.next_value()?,
+                "gas" => summary.gas = map.next_value()?,
+                "returnValue" => summary.return_value = map.next_value()?,
+                "structLogs" => map.next_value_seed(StructLogsSeed {
+                    logs: &mut self.logs,
+                })?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Sends the structLogs to the replay one by one instead of collecting them.
+struct StructLogsSeed<'a> {
+    logs: &'a mut mpsc::Sender<StructLog>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for StructLogsSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for StructLogsSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of structLogs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(log) = seq.next_element::<StructLog>()? {
+            if block_on(self.logs.send(log)).is_err() {
+                // the replay stopped, the rest of the trace isn't needed
+                return Err(serde::de::Error::custom("structLog stream closed"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,328 +1,53 @@
 use std::collections::BTreeMap;
 use std::str::FromStr;
-use anyhow::ensure;
 
 use ethers::prelude::*;
 use ethers::providers::{Middleware, Provider};
-use ethers::utils;
-use ethers::utils::get_contract_address;
 
-use super::opcodes::*;
-use crate::extractor::types::{EthState, EthTransactionTestVector};
+use super::backend::Backend;
+use super::cache::RpcCache;
+use super::precompiles::{is_precompile, precompile_name};
+use super::source::{GethSource, TestVectorSource};
+use super::state_diff::{StateDiffSource, VmTraceSource};
+use crate::extractor::types::{EthCallFrame, EthPrecompileCall, EthTransactionTestVector};
 
-/// Extract pre-transaction and post-transaction states for the given tx hash from Geth node.
+/// Extract pre-transaction and post-transaction states for the given tx hash from the node,
+/// through the tracing API of `backend`.
+/// The RPC responses are taken from `cache` when they're there, and added to it otherwise.
 pub async fn extract_eth_transaction_test_vector<P: JsonRpcClient>(
     provider: &Provider<P>,
     tx_hash: H256,
+    cache: Option<&RpcCache>,
+    backend: Backend,
 ) -> anyhow::Result<EthTransactionTestVector> {
-    let transaction = provider.get_transaction(tx_hash).await?.unwrap();
-
-    let block = provider
-        .get_block_with_txs(transaction.block_hash.unwrap())
-        .await?
-        .unwrap();
-
-    let next_block_id: BlockId = (block.number.unwrap() + 1).into();
+    let source: Box<dyn TestVectorSource + '_> = match backend {
+        Backend::Geth => Box::new(GethSource::new(provider, tx_hash, cache)),
+        Backend::Erigon => Box::new(VmTraceSource::new(provider, tx_hash, cache)),
+        Backend::Parity => Box::new(StateDiffSource::new(provider, tx_hash, cache)),
+    };
+    source.test_vector(tx_hash).await
+}
 
+/// Test vector of `transaction` with the fields of the transaction and of its block, the
+/// states and the execution result are left to the backend.
+pub(crate) fn transaction_test_vector(
+    transaction: &Transaction,
+    block: &Block<Transaction>,
+) -> EthTransactionTestVector {
     let mut block_hashes = BTreeMap::new();
     block_hashes.insert(block.number.unwrap().as_u64(), block.hash.unwrap());
 
-    let tx_from = transaction.from;
-    let tx_to = transaction
-        .to
-        .unwrap_or_else(|| get_contract_address(tx_from, transaction.nonce));
-
-    // Get pre-transaction state simply by built-in prestate tracer of Geth,
-    // all accounts involved in the transaction will be traced, (accounts accessed by
-    // BALANCE, EXTCODE* opcode are also included), each account state consists of
-    // nonce, balance, code and storage(accessed slots only).
-    // see https://geth.ethereum.org/docs/developers/evm-tracing/built-in-tracers#prestate-tracer
-    // for more info about prestate trace.
-    let prestate_tracing_options: GethDebugTracingOptions = GethDebugTracingOptions {
-        tracer: Some("prestateTracer".to_owned()),
-        ..Default::default()
-    };
-    let prestate: EthState = provider
-        .request(
-            "debug_traceTransaction",
-            [
-                utils::serialize(&tx_hash),
-                utils::serialize(&prestate_tracing_options),
-            ],
-        )
-        .await?;
-
-    // all state modification made by this transaction will apply to poststate,
-    // it's based on prestate.
-    let mut poststate = prestate.clone();
-
-    // trace the state-change made by this transaction through structLogger tracer,
-    // which is the default tracer of Geth traceTransaction RPC.
-    // Note: there seems be a "diff mode" of prestate tracer, but it's not available
-    // currently on latest Geth release(v1.10.26)
-    let trace_options: GethDebugTracingOptions = GethDebugTracingOptions {
-        disable_storage: Some(true), // disable storage capture since we can get it from the stack.
-        enable_memory: Some(false), // memory capture would result in huge response size(GB) on some transactions.
-        disable_stack: Some(false),
-        enable_return_data: Some(true),
-        ..Default::default()
-    };
-    let transaction_trace = provider
-        .debug_trace_transaction(tx_hash, trace_options)
-        .await?;
-
-    let sender_account = poststate.get_mut(&tx_from).unwrap();
-
-    // calculate gas fee(including leftover gas)
-    let gas_price = transaction.gas_price.unwrap();
-    let gas_fee = gas_price * transaction.gas;
-    sender_account.balance -= gas_fee;
-
-    // increase sender nonce
-    sender_account.nonce += 1;
-
-    // used to track real execution context(e.g. which contract's storage is read, written)
-    let mut execution_contexts = vec![tx_to];
-    // used to handle reverting and other errors, the first poststate snapshot should
-    // be taken after gas fee deduction but tx value transfer
-    let mut snapshots = vec![poststate.clone()];
-
-    if transaction.to.is_none() {
-        // FIXME The contract may have self-destructed.
-        // We can get runtime code of the created contract(ether created by
-        // topmost transaction or created by CREATE,CREATE2 opcode) from
-        // memory in structLog(created by structLogger tracer), this require
-        // us to enable memory trace option, but this would result in
-        // huge response size on some transactions.
-        let code = provider.get_code(tx_to, Some(next_block_id)).await?;
-        ensure!(code.len() == 0, "failed to get code for {tx_to:?}");
-        let eth_account_state = poststate.get_mut(&tx_to).unwrap();
-        eth_account_state.code = code;
-    }
-
-    // transaction value transfer
-    if !transaction.value.is_zero() {
-        let account_state = poststate.get_mut(&tx_from).unwrap();
-        account_state.balance -= transaction.value;
-        let account_state = poststate.get_mut(&tx_to).unwrap();
-        account_state.balance += transaction.value;
-    }
-
-    // start to apply changes made by tx on poststate
-    let mut depth = 1u64;
-    let mut i = 0;
-    while i < transaction_trace.struct_logs.len() {
-        let log = &transaction_trace.struct_logs[i];
-
-        if depth > log.depth {
-            depth = log.depth;
-            execution_contexts.truncate(depth.try_into().unwrap());
-            snapshots.truncate(depth.try_into().unwrap());
-        }
-
-        // handle opcodes that might change the state
-        match log.op.as_str() {
-            OP_SSTORE => {
-                let stack = log.stack.as_ref().unwrap();
-
-                let key = U256_to_H256(stack[stack.len() - 1]);
-                let val = U256_to_H256(stack[stack.len() - 2]);
-
-                let account_state = poststate
-                    .get_mut(execution_contexts.last().unwrap())
-                    .unwrap();
-                account_state.storage.insert(key, val);
-            }
-            OP_CALL => {
-                snapshots.push(poststate.clone());
-
-                let stack = log.stack.as_ref().unwrap();
-
-                let address = decode_address(stack[stack.len() - 2]);
-
-                let value = stack[stack.len() - 3];
-
-                if !value.is_zero() {
-                    let caller = execution_contexts.last().unwrap();
-
-                    let caller_account_state = poststate.get_mut(caller).unwrap();
-
-                    // In some cases, the "CALL" will fail without any error and there's no "revert".
-                    if depth <= 1024 && caller_account_state.balance >= value {
-                        caller_account_state.balance -= value;
-
-                        let callee_account_state = poststate.get_mut(&address).unwrap();
-                        callee_account_state.balance += value;
-                    }
-                }
-
-                execution_contexts.push(address);
-
-                depth += 1;
-            }
-            OP_STATICCALL => {
-                let stack = log.stack.as_ref().unwrap();
-
-                let address = decode_address(stack[stack.len() - 2]);
-
-                execution_contexts.push(address);
-                snapshots.push(poststate.clone());
-
-                depth += 1;
-            }
-            OP_DELEGATECALL => {
-                execution_contexts.push(*execution_contexts.last().unwrap());
-                snapshots.push(poststate.clone());
-
-                depth += 1;
-            }
-            OP_CALLCODE => {
-                execution_contexts.push(*execution_contexts.last().unwrap());
-                snapshots.push(poststate.clone());
-
-                depth += 1;
-            }
-            OP_CREATE => {
-                snapshots.push(poststate.clone());
-
-                let stack = log.stack.as_ref().unwrap();
-
-                let value = stack[stack.len() - 1];
-
-                let mut address = H160::zero();
-                // get the address of the created contract, it's on the stack
-                // of next log with the same call depth.
-                for log in &transaction_trace.struct_logs[i + 1..] {
-                    if log.depth == depth {
-                        let stack = log.stack.as_ref().unwrap();
-                        address = decode_address(stack[stack.len() - 1]);
-                        break;
-                    }
-                }
-
-                let caller = execution_contexts.last().unwrap();
-
-                // In some cases, the "CREATE" will fail without any error and there's no "revert".
-                if depth <= 1024 && poststate.get(caller).unwrap().balance >= value {
-                    if !value.is_zero() {
-                        poststate.get_mut(caller).unwrap().balance -= value;
-
-                        poststate.get_mut(&address).unwrap().balance += value;
-                    }
-
-                    poststate.get_mut(caller).unwrap().nonce += 1;
-
-                    // FIXME
-                    let code = provider.get_code(address, Some(next_block_id)).await?;
-                    ensure!(code.len() == 0, "failed to get code for {tx_to:?}");
-                    poststate.get_mut(&address).unwrap().code = code;
-                }
-
-                execution_contexts.push(address);
-
-                depth += 1;
-            }
-            OP_CREATE2 => {
-                snapshots.push(poststate.clone());
-
-                let stack = log.stack.as_ref().unwrap();
-
-                let value = stack[stack.len() - 1];
-
-                let mut address = H160::zero();
-                for log in &transaction_trace.struct_logs[i + 1..] {
-                    if log.depth == depth {
-                        let stack = log.stack.as_ref().unwrap();
-                        address = decode_address(stack[stack.len() - 1]);
-                        break;
-                    }
-                }
-
-                let caller = execution_contexts.last().unwrap();
-
-                // In some cases, the "CREATE2" will fail without any error and there's no "revert".
-                if depth <= 1024 && poststate.get(caller).unwrap().balance >= value {
-                    if !value.is_zero() {
-                        poststate.get_mut(caller).unwrap().balance -= value;
-
-                        poststate.get_mut(&address).unwrap().balance += value;
-                    }
-
-                    poststate.get_mut(caller).unwrap().nonce += 1;
-
-                    // FIXME
-                    let code = provider.get_code(address, Some(next_block_id)).await?;
-                    ensure!(code.len() == 0, "failed to get code for {tx_to:?}");
-                    poststate.get_mut(&address).unwrap().code = code;
-                }
-
-                execution_contexts.push(address);
-
-                depth += 1;
-            }
-            OP_SELFDESTRUCT => {
-                let stack = log.stack.as_ref().unwrap();
-                let beneficiary = decode_address(stack[stack.len() - 1]);
-
-                let caller = execution_contexts.last().unwrap();
-
-                let caller_balance = poststate.get_mut(caller).unwrap().balance;
-                if caller_balance != 0.into() {
-                    poststate.get_mut(&beneficiary).unwrap().balance += caller_balance;
-                }
-
-                // consider delete the account?
-                let caller_account_state = poststate.get_mut(caller).unwrap();
-                caller_account_state.balance = 0.into();
-                caller_account_state.nonce = 0;
-                caller_account_state.code = Bytes::default();
-            }
-            OP_BLOCKHASH => {
-                let stack = log.stack.as_ref().unwrap();
-
-                let stack_after = transaction_trace.struct_logs[i + 1].clone().stack.unwrap();
-
-                let num = stack[stack.len() - 1].as_u64();
-                let hash = stack_after[stack_after.len() - 1];
-                let mut bytes = [0; 32];
-                hash.to_big_endian(&mut bytes);
-                block_hashes.insert(num, bytes.into());
-            }
-            OP_REVERT => {
-                poststate = snapshots.pop().unwrap();
-            }
-            OP_INVALID => {
-                poststate = snapshots.pop().unwrap();
-            }
-            _ => (),
-        }
-
-        if log.error.is_some() {
-            poststate = snapshots.pop().unwrap();
-        }
-        i += 1;
-    }
-
-    // refund unused gas to tx sender
-    // Note: Some opcodes(e.g. SSTORE) have additional gas refund. But it seems that
-    // we don't need further handling it, because there's no opcode gas refund on FEVM?
-    let leftover_gas = transaction.gas - transaction_trace.gas;
-    poststate.get_mut(&tx_from).unwrap().balance += leftover_gas * gas_price;
-
-    let eth_transaction_test_vector = EthTransactionTestVector {
+    EthTransactionTestVector {
         hash: transaction.hash,
         nonce: transaction.nonce.as_u64(),
         from: transaction.from,
         to: transaction.to.unwrap_or_else(|| H160::zero()),
         value: transaction.value,
-        input: transaction.input,
+        input: transaction.input.clone(),
         gas: transaction.gas,
         gas_price: transaction.gas_price.unwrap(),
         max_priority_fee_per_gas: transaction.max_priority_fee_per_gas,
         max_fee_per_gas: transaction.max_fee_per_gas,
-        status: if transaction_trace.failed { 0 } else { 1 },
-        gas_used: transaction_trace.gas.into(),
-        return_value: transaction_trace.return_value,
         coinbase: block.author.unwrap(),
         base_fee_per_gas: block.base_fee_per_gas,
         difficultly: block.difficulty,
@@ -335,19 +60,66 @@ pub async fn extract_eth_transaction_test_vector<P: JsonRpcClient>(
         block_number: block.number.unwrap().as_u64(),
         block_hashes,
         timestamp: block.timestamp,
-        prestate,
-        poststate,
-    };
-    Ok(eth_transaction_test_vector)
+        ..Default::default()
+    }
+}
+
+/// Keep the calls into precompiles of `vector`, which are not accounts on FEVM, and drop
+/// them from its states.
+pub(crate) fn finish_test_vector(vector: &mut EthTransactionTestVector) {
+    if let Some(call_tree) = &vector.call_tree {
+        collect_precompile_calls(call_tree, &mut vector.precompile_calls);
+    }
+    vector.prestate.retain(|address, _| !is_precompile(address));
+    vector
+        .poststate
+        .retain(|address, _| !is_precompile(address));
+}
+
+/// Trace the given tx hash through the structLogger tracer of Geth node, with stack enabled.
+pub async fn trace_transaction<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: H256,
+) -> anyhow::Result<GethTrace> {
+    Ok(provider
+        .debug_trace_transaction(tx_hash, struct_log_tracing_options())
+        .await?)
+}
+
+pub(crate) fn struct_log_tracing_options() -> GethDebugTracingOptions {
+    GethDebugTracingOptions {
+        disable_storage: Some(true), // disable storage capture since we can get it from the stack.
+        enable_memory: Some(false), // memory capture would result in huge response size(GB) on some transactions.
+        disable_stack: Some(false),
+        enable_return_data: Some(true),
+        ..Default::default()
+    }
+}
+
+fn collect_precompile_calls(frame: &EthCallFrame, calls: &mut Vec<EthPrecompileCall>) {
+    for call in &frame.calls {
+        if let Some(name) = precompile_name(&call.callee) {
+            calls.push(EthPrecompileCall {
+                address: call.callee,
+                name: name.to_owned(),
+                call_type: call.call_type.clone(),
+                value: call.value,
+                input: call.input.clone(),
+                output: call.output.clone(),
+                success: !call.reverted,
+            });
+        }
+        collect_precompile_calls(call, calls);
+    }
 }
 
-fn decode_address(raw_address: U256) -> H160 {
+pub(crate) fn decode_address(raw_address: U256) -> H160 {
     let mut bytes = [0; 32];
     raw_address.to_big_endian(&mut bytes);
     H160::from_slice(&bytes[12..])
 }
 
-fn U256_to_H256(val: U256) -> H256 {
+pub(crate) fn U256_to_H256(val: U256) -> H256 {
     let mut bytes = [0; 32];
     val.to_big_endian(&mut bytes);
     H256::from_slice(&bytes)
@@ -368,7 +140,7 @@ async fn test_extract_eth_tv() {
 
     let provider = Provider::<Http>::try_from(rpc).expect("could not instantiate HTTP Provider");
 
-    let r = extract_eth_transaction_test_vector(&provider, tx_hash)
+    let r = extract_eth_transaction_test_vector(&provider, tx_hash, None, Backend::Geth)
         .await
         .unwrap();
     for (address, account) in r.prestate {
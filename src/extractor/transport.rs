@@ -0,0 +1,137 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, Provider, ProviderError, PubsubClient, Ws, WsClientError,
+};
+#[cfg(unix)]
+use ethers::providers::{Ipc, IpcError};
+use ethers::types::U256;
+use futures::channel::mpsc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use thiserror::Error;
+
+use super::policy::{PolicyClient, RpcPolicy, TransientError};
+
+/// Transport to a geth node, picked from the endpoint: `http(s)://` and `ws(s)://` urls,
+/// anything else is the path of an IPC socket.
+#[derive(Debug, Clone)]
+pub enum GethTransport {
+    Http(Http),
+    Ws(Ws),
+    #[cfg(unix)]
+    Ipc(Ipc),
+}
+
+#[derive(Debug, Error)]
+pub enum GethTransportError {
+    #[error(transparent)]
+    Http(#[from] HttpClientError),
+    #[error(transparent)]
+    Ws(#[from] WsClientError),
+    #[cfg(unix)]
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+    #[error("subscriptions need a WebSocket or IPC endpoint")]
+    NoSubscriptions,
+}
+
+impl From<GethTransportError> for ProviderError {
+    fn from(e: GethTransportError) -> Self {
+        match e {
+            GethTransportError::Http(e) => e.into(),
+            GethTransportError::Ws(e) => e.into(),
+            #[cfg(unix)]
+            GethTransportError::Ipc(e) => e.into(),
+            GethTransportError::NoSubscriptions => ProviderError::JsonRpcClientError(Box::new(e)),
+        }
+    }
+}
+
+impl TransientError for GethTransportError {
+    fn is_transient(&self) -> bool {
+        match self {
+            GethTransportError::Http(e) => e.is_transient(),
+            GethTransportError::Ws(e) => e.is_transient(),
+            #[cfg(unix)]
+            GethTransportError::Ipc(e) => e.is_transient(),
+            GethTransportError::NoSubscriptions => false,
+        }
+    }
+}
+
+impl GethTransport {
+    pub async fn connect(endpoint: &str) -> anyhow::Result<Self> {
+        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            return Ok(GethTransport::Http(Http::from_str(endpoint)?));
+        }
+        if endpoint.starts_with("ws://") || endpoint.starts_with("wss://") {
+            return Ok(GethTransport::Ws(Ws::connect(endpoint).await?));
+        }
+        Self::connect_ipc(endpoint).await
+    }
+
+    #[cfg(unix)]
+    async fn connect_ipc(path: &str) -> anyhow::Result<Self> {
+        Ok(GethTransport::Ipc(Ipc::connect(path).await?))
+    }
+
+    #[cfg(not(unix))]
+    async fn connect_ipc(path: &str) -> anyhow::Result<Self> {
+        anyhow::bail!("IPC endpoint {} is only supported on unix", path)
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for GethTransport {
+    type Error = GethTransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let res = match self {
+            GethTransport::Http(http) => http.request(method, params).await?,
+            GethTransport::Ws(ws) => ws.request(method, params).await?,
+            #[cfg(unix)]
+            GethTransport::Ipc(ipc) => ipc.request(method, params).await?,
+        };
+        Ok(res)
+    }
+}
+
+impl PubsubClient for GethTransport {
+    type NotificationStream = mpsc::UnboundedReceiver<Box<RawValue>>;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        match self {
+            GethTransport::Http(_) => Err(GethTransportError::NoSubscriptions),
+            GethTransport::Ws(ws) => Ok(ws.subscribe(id)?),
+            #[cfg(unix)]
+            GethTransport::Ipc(ipc) => Ok(ipc.subscribe(id)?),
+        }
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        match self {
+            GethTransport::Http(_) => Err(GethTransportError::NoSubscriptions),
+            GethTransport::Ws(ws) => Ok(ws.unsubscribe(id)?),
+            #[cfg(unix)]
+            GethTransport::Ipc(ipc) => Ok(ipc.unsubscribe(id)?),
+        }
+    }
+}
+
+/// Provider for `endpoint`, over HTTP, WebSocket or IPC, sending requests according to
+/// `policy`.
+pub async fn connect_provider(
+    endpoint: &str,
+    policy: RpcPolicy,
+) -> anyhow::Result<Provider<PolicyClient<GethTransport>>> {
+    let transport = GethTransport::connect(endpoint).await?;
+    Ok(Provider::new(PolicyClient::new(transport, policy)))
+}
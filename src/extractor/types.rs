@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
 
 use ethers::types::{Bytes, H160, H256, U256};
@@ -25,6 +25,67 @@ impl EthAccountState {
 
 pub type EthState = BTreeMap<H160, EthAccountState>;
 
+/// Accounts and storage slots observed while replaying the structLog trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EthAccesses {
+    /// slots read by SLOAD, per storage context
+    pub storage_reads: BTreeMap<H160, BTreeSet<H256>>,
+    /// slots written by SSTORE, per storage context
+    pub storage_writes: BTreeMap<H160, BTreeSet<H256>>,
+    /// accounts whose balance is read by BALANCE or SELFBALANCE
+    pub balances: BTreeSet<H160>,
+    /// accounts whose code is executed or read by EXTCODESIZE, EXTCODECOPY or EXTCODEHASH
+    pub codes: BTreeSet<H160>,
+    /// accounts touched by the transaction, calls, creations and self-destructs
+    pub accounts: BTreeSet<H160>,
+}
+
+impl EthAccesses {
+    pub fn is_accessed(&self, addr: &H160) -> bool {
+        self.accounts.contains(addr)
+            || self.balances.contains(addr)
+            || self.codes.contains(addr)
+            || self.storage_reads.contains_key(addr)
+            || self.storage_writes.contains_key(addr)
+    }
+}
+
+/// One call frame of the transaction, as reconstructed by the structLog replay.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EthCallFrame {
+    /// CALL, STATICCALL, DELEGATECALL, CALLCODE, CREATE or CREATE2
+    pub call_type: String,
+    pub caller: H160,
+    /// code address, or the created contract address
+    pub callee: H160,
+    pub value: U256,
+    pub input: Bytes,
+    pub output: Bytes,
+    /// gas available to the frame
+    pub gas: u64,
+    pub gas_used: u64,
+    pub reverted: bool,
+    /// slots written by SSTORE in this frame, DELEGATECALL and CALLCODE frames
+    /// write to the storage of the caller.
+    pub storage_writes: BTreeMap<H256, H256>,
+    pub calls: Vec<EthCallFrame>,
+    /// salt of CREATE2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<H256>,
+}
+
+/// A call into a precompile, no account state is recorded for precompiles.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EthPrecompileCall {
+    pub address: H160,
+    pub name: String,
+    pub call_type: String,
+    pub value: U256,
+    pub input: Bytes,
+    pub output: Bytes,
+    pub success: bool,
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EthTransactionTestVector {
     // transaction fields
@@ -55,6 +116,13 @@ pub struct EthTransactionTestVector {
     // pre-state and post-state
     pub prestate: EthState,
     pub poststate: EthState,
+    // observations made by the structLog replay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accesses: Option<EthAccesses>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_tree: Option<EthCallFrame>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub precompile_calls: Vec<EthPrecompileCall>,
 }
 
 impl EthTransactionTestVector {
@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use ethers::prelude::*;
+use ethers::providers::{Middleware, Provider, PubsubClient};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::backend::Backend;
+use super::batch::{filter_block_transactions, TxFilter};
+use super::cache::RpcCache;
+use super::transaction::extract_eth_transaction_test_vector;
+use super::types::EthTransactionTestVector;
+
+/// Name of the file recording the blocks watched in the output dir.
+pub const WATCH_STATE_FILE: &str = ".watch-state.json";
+
+/// Number of blocks remembered to detect reorgs, vectors of older blocks are final.
+pub const REORG_DEPTH: u64 = 64;
+
+/// Attempts at extracting a transaction before the watch gives up on it.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Block a test vector was extracted from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorBlock {
+    pub number: u64,
+    pub hash: H256,
+}
+
+/// Blocks processed by a watch, saved after each block so it resumes where it left off.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    /// hashes of the last processed blocks
+    pub blocks: BTreeMap<u64, H256>,
+    /// transactions extracted from the remembered blocks
+    pub vectors: BTreeMap<H256, VectorBlock>,
+    /// block whose extraction failed, to process again
+    #[serde(default)]
+    pub retry: Option<u64>,
+    /// failed attempts of the transactions of the block to process again
+    #[serde(default)]
+    pub attempts: BTreeMap<H256, u32>,
+    /// transactions given up on after `MAX_ATTEMPTS` -> error of the last attempt
+    #[serde(default)]
+    pub failed: BTreeMap<H256, String>,
+}
+
+impl WatchState {
+    pub fn load(out_dir: &Path) -> anyhow::Result<Self> {
+        let path = out_dir.join(WATCH_STATE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path)?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("invalid watch state {:?}", path))
+    }
+
+    pub fn save(&self, out_dir: &Path) -> anyhow::Result<()> {
+        let tmp = out_dir.join(format!("{}.tmp", WATCH_STATE_FILE));
+        serde_json::to_writer_pretty(File::create(&tmp)?, self)?;
+        std::fs::rename(tmp, out_dir.join(WATCH_STATE_FILE))?;
+        Ok(())
+    }
+
+    /// Remember `hash` as block `number`, forgetting the blocks too old to be reorged.
+    pub fn push_block(&mut self, number: u64, hash: H256) {
+        self.blocks.insert(number, hash);
+        let oldest = number.saturating_sub(REORG_DEPTH);
+        self.blocks = self.blocks.split_off(&oldest);
+        self.vectors.retain(|_, block| block.number >= oldest);
+    }
+
+    /// Forget the blocks from `number` on, returns the transactions extracted from them.
+    pub fn discard_from(&mut self, number: u64) -> Vec<H256> {
+        self.blocks.split_off(&number);
+        let discarded: Vec<H256> = self
+            .vectors
+            .iter()
+            .filter(|(_, block)| block.number >= number)
+            .map(|(tx_hash, _)| *tx_hash)
+            .collect();
+        for tx_hash in &discarded {
+            self.vectors.remove(tx_hash);
+        }
+        discarded
+    }
+}
+
+/// Follows the chain head and extracts the transactions matching `filter` into `out_dir`.
+pub struct Watcher<'a, P, F> {
+    pub provider: &'a Provider<P>,
+    pub out_dir: &'a Path,
+    pub filter: TxFilter,
+    pub cache: Option<&'a RpcCache>,
+    pub backend: Backend,
+    /// writes the vector of a transaction to the given path
+    pub write: F,
+    pub state: WatchState,
+}
+
+impl<'a, P, F> Watcher<'a, P, F>
+where
+    P: JsonRpcClient,
+    F: Fn(&Path, EthTransactionTestVector) -> anyhow::Result<()>,
+{
+    fn vector_path(&self, tx_hash: &H256) -> PathBuf {
+        self.out_dir.join(format!("{:?}.json", tx_hash))
+    }
+
+    /// Process the blocks from `from_block`, or the block after the last one processed,
+    /// up to the chain head. Stops at a block whose extraction failed, the next poll
+    /// retries it, unless its failed transactions were attempted `MAX_ATTEMPTS` times.
+    pub async fn poll(&mut self, from_block: Option<u64>) -> anyhow::Result<()> {
+        let head = self.provider.get_block_number().await?.as_u64();
+        let mut number = match self.state.blocks.keys().next_back() {
+            Some(last) => last + 1,
+            None => self.state.retry.or(from_block).unwrap_or(head),
+        };
+        while number <= head {
+            let block = self
+                .provider
+                .get_block_with_txs(U64::from(number))
+                .await?
+                .with_context(|| format!("block {} not found", number))?;
+            let parent = number
+                .checked_sub(1)
+                .and_then(|parent| self.state.blocks.get(&parent));
+            if parent.map_or(false, |parent| *parent != block.parent_hash) {
+                number = self.rewind().await?;
+                continue;
+            }
+            let hash = block.hash.context("pending block")?;
+            let mut failed = false;
+            for tx_hash in filter_block_transactions(self.provider, &block, &self.filter).await? {
+                if self.state.failed.contains_key(&tx_hash) {
+                    continue;
+                }
+                let path = self.vector_path(&tx_hash);
+                let res = extract_eth_transaction_test_vector(
+                    self.provider,
+                    tx_hash,
+                    self.cache,
+                    self.backend,
+                )
+                .await
+                .and_then(|evm_input| (self.write)(&path, evm_input));
+                match res {
+                    Ok(()) => {
+                        log::info!("block {}: extracted {:?}", number, tx_hash);
+                        self.state.attempts.remove(&tx_hash);
+                        self.state
+                            .vectors
+                            .insert(tx_hash, VectorBlock { number, hash });
+                    }
+                    Err(e) => {
+                        log::error!("block {}: {:?} failed: {:#}", number, tx_hash, e);
+                        let attempts = self.state.attempts.entry(tx_hash).or_default();
+                        *attempts += 1;
+                        if *attempts < MAX_ATTEMPTS {
+                            failed = true;
+                        } else {
+                            log::warn!("giving up on {:?}", tx_hash);
+                            self.state.attempts.remove(&tx_hash);
+                            self.state.failed.insert(tx_hash, format!("{:#}", e));
+                        }
+                    }
+                }
+            }
+            if failed {
+                // the block isn't processed, the next poll extracts it again
+                log::warn!("block {} will be retried", number);
+                self.state.retry = Some(number);
+                self.state.save(self.out_dir)?;
+                return Ok(());
+            }
+            self.state.push_block(number, hash);
+            self.state.retry = None;
+            self.state.attempts.clear();
+            self.state.save(self.out_dir)?;
+            number += 1;
+        }
+        Ok(())
+    }
+
+    /// Drop the remembered blocks which are no longer in the chain along with their
+    /// vectors, returns the number of the first block to process again.
+    async fn rewind(&mut self) -> anyhow::Result<u64> {
+        let mut fork = None;
+        for (number, hash) in self.state.blocks.iter().rev() {
+            let block = self.provider.get_block(*number).await?;
+            if block.and_then(|block| block.hash) == Some(*hash) {
+                break;
+            }
+            fork = Some(*number);
+        }
+        let fork = fork.ok_or_else(|| anyhow!("no reorg found"))?;
+        log::warn!("reorg from block {}", fork);
+        for tx_hash in self.state.discard_from(fork) {
+            log::warn!("discarding {:?}", tx_hash);
+            let path = self.vector_path(&tx_hash);
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        self.state.save(self.out_dir)?;
+        Ok(fork)
+    }
+}
+
+impl<'a, P, F> Watcher<'a, P, F>
+where
+    P: PubsubClient,
+    F: Fn(&Path, EthTransactionTestVector) -> anyhow::Result<()>,
+{
+    /// Process new blocks as they come, notified by a subscription when the transport
+    /// supports it and polled every `poll_interval` otherwise. Never returns unless an
+    /// error occurs.
+    pub async fn run(
+        &mut self,
+        from_block: Option<u64>,
+        poll_interval: Duration,
+    ) -> anyhow::Result<()> {
+        self.poll(from_block).await?;
+        let provider = self.provider;
+        match provider.subscribe_blocks().await {
+            Ok(mut heads) => {
+                log::info!("subscribed to new blocks");
+                while heads.next().await.is_some() {
+                    self.poll(None).await?;
+                }
+                Err(anyhow!("block subscription closed"))
+            }
+            Err(e) => {
+                log::info!("polling new blocks every {:?}: {}", poll_interval, e);
+                loop {
+                    tokio::time::sleep(poll_interval).await;
+                    self.poll(None).await?;
+                }
+            }
+        }
+    }
+}
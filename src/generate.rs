@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{anyhow, Context};
+use async_std::task::block_on;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::export_test_vector_file;
+use crate::extractor::types::EthTransactionTestVector;
+use crate::mock::MockConfig;
+
+/// Outcome of the generation of a test vector from one input file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateResult {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+    /// size of the test vector file, in bytes
+    pub output_size: Option<u64>,
+}
+
+/// Generate the test vectors of `files` into `out_dir` over a pool of `jobs` worker
+/// threads, the results are in the order of `files`.
+pub fn generate_in_parallel(
+    files: &[PathBuf],
+    out_dir: &Path,
+    config: &MockConfig,
+    jobs: usize,
+) -> Vec<GenerateResult> {
+    process_in_parallel(files, jobs, |input| {
+        generate_from_file(input, out_dir, config)
+    })
+}
+
+/// Run `generate` on every file of `files` over a pool of `jobs` worker threads.
+/// `generate` returns the path of the file it wrote, a panic only fails its input.
+pub fn process_in_parallel<F>(files: &[PathBuf], jobs: usize, generate: F) -> Vec<GenerateResult>
+where
+    F: Fn(&Path) -> anyhow::Result<PathBuf> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results = Mutex::new(BTreeMap::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(files.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let p = match files.get(i) {
+                    Some(p) => p,
+                    None => break,
+                };
+                let result = process_file(p, &generate);
+                log::info!(
+                    "[{}/{}] {:?} {} in {}ms",
+                    done.fetch_add(1, Ordering::SeqCst) + 1,
+                    files.len(),
+                    p,
+                    if result.success {
+                        "generated"
+                    } else {
+                        "failed"
+                    },
+                    result.duration_ms
+                );
+                results.lock().unwrap().insert(i, result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_values().collect()
+}
+
+fn process_file<F>(input: &Path, generate: &F) -> GenerateResult
+where
+    F: Fn(&Path) -> anyhow::Result<PathBuf>,
+{
+    let started = Instant::now();
+    let res = catch_unwind(AssertUnwindSafe(|| generate(input)))
+        .unwrap_or_else(|panic| Err(anyhow!("panicked: {}", panic_message(&*panic))))
+        .and_then(|path| Ok((std::fs::metadata(&path)?.len(), path)));
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match res {
+        Ok((size, path)) => GenerateResult {
+            input: input.to_path_buf(),
+            output: Some(path),
+            success: true,
+            error: None,
+            duration_ms,
+            output_size: Some(size),
+        },
+        Err(e) => {
+            log::error!("{:?}: {:#}", input, e);
+            GenerateResult {
+                input: input.to_path_buf(),
+                output: None,
+                success: false,
+                error: Some(format!("{:#}", e)),
+                duration_ms,
+                output_size: None,
+            }
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("unknown panic"),
+    }
+}
+
+fn generate_from_file(
+    input: &Path,
+    out_dir: &Path,
+    config: &MockConfig,
+) -> anyhow::Result<PathBuf> {
+    let path = out_dir.join(input.file_name().context("input is not a file")?);
+    let evm_input = load_test_vector(input)?;
+    block_on(export_test_vector_file(evm_input, path.clone(), config))?;
+    Ok(path)
+}
+
+/// Deserialize the JSON file at `path`.
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let file = File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Serialization failed: {:?}", path))
+}
+
+/// Read the transaction detail file at `path`.
+pub fn load_test_vector(path: &Path) -> anyhow::Result<EthTransactionTestVector> {
+    load_json(path)
+}
@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
 use std::fs::File;
 use std::io::Read;
@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, Once};
 
+use anyhow::Context;
 use async_std::channel::bounded;
 use async_std::sync::RwLock;
 use bytes::Buf;
@@ -15,15 +16,19 @@ use ethers::abi::AbiEncode;
 use fil_actor_eam::EthAddress;
 use fil_actor_evm::interpreter::system::StateKamt;
 use fil_actor_evm::interpreter::U256;
+use fil_actor_evm::EVM_CONTRACT_REVERTED;
 use fil_actors_runtime::runtime::builtins::Type;
 use fil_actors_runtime::runtime::EMPTY_ARR_CID;
-use fil_actors_runtime::{AsActorError, BURNT_FUNDS_ACTOR_ID, EAM_ACTOR_ID, REWARD_ACTOR_ID};
+use fil_actors_runtime::{
+    AsActorError, BURNT_FUNDS_ACTOR_ID, EAM_ACTOR_ID, FIRST_NON_SINGLETON_ADDR, REWARD_ACTOR_ID,
+};
 use flate2::bufread::GzEncoder;
 use flate2::Compression;
 use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
 use fvm_ipld_car::CarHeader;
 use fvm_ipld_encoding::{BytesDe, Cbor, CborStore, RawBytes, DAG_CBOR};
 use fvm_ipld_hamt::Hamt;
+use fvm_ipld_kamt::Config as KamtConfig;
 use fvm_shared::address::Address;
 use fvm_shared::bigint::{BigInt, Integer};
 use fvm_shared::clock::ChainEpoch;
@@ -34,22 +39,33 @@ use fvm_shared::message::Message;
 use fvm_shared::receipt::Receipt;
 use fvm_shared::state::StateRoot;
 use fvm_shared::version::NetworkVersion;
-use fvm_shared::{MethodNum, HAMT_BIT_WIDTH, IDENTITY_HASH, METHOD_SEND};
+use fvm_shared::{ActorID, MethodNum, HAMT_BIT_WIDTH, IDENTITY_HASH, METHOD_SEND};
 use util::get_code_cid_map;
 use vector::{ApplyMessage, PreConditions, StateTreeVector, TestVector, Variant};
 
 use crate::evm_state::State as EvmState;
-use crate::extractor::types::EthTransactionTestVector;
-use crate::mock::{address_to_eth, Actor, Mock, KAMT_CONFIG};
+use crate::extractor::opcodes::{OP_CREATE, OP_CREATE2};
+use crate::extractor::precompiles::{fevm_difference, is_precompile};
+use crate::extractor::types::{EthCallFrame, EthTransactionTestVector};
+use crate::mock::{address_to_eth, default_robust_address, Actor, Mock, MockConfig};
 use crate::tracing_blockstore::TracingBlockStore;
 use crate::types::{ContractParams, CreateParams};
-use crate::util::{compute_address_create, hex_to_u256, u256_to_bytes};
+use crate::util::{
+    compute_address_create, compute_robust_address, eth_to_fil_address, hex_to_u256, masked_id,
+    u256_to_bytes, BUNDLE_EVM_STATE_VERSION,
+};
 use crate::vector::{GenerationData, MetaData, RandomnessMatch, RandomnessRule, TipsetCid};
 
+pub mod anonymize;
 mod cidjson;
+pub mod coverage;
 pub mod evm_state;
 pub mod extractor;
+pub mod generate;
+pub mod minimize;
 pub mod mock;
+pub mod scenario;
+pub mod storage_stats;
 pub mod tracing_blockstore;
 pub mod types;
 pub mod util;
@@ -67,60 +83,34 @@ pub fn init_log() {
 pub async fn export_test_vector_file(
     input: EthTransactionTestVector,
     path: PathBuf,
+    config: &MockConfig,
 ) -> anyhow::Result<()> {
+    // the EVM states must have the layout the actor code of the bundle expects
+    anyhow::ensure!(
+        config.evm_state_version == BUNDLE_EVM_STATE_VERSION,
+        "EVM state version {} doesn't match the {} of the embedded actor bundle",
+        config.evm_state_version,
+        BUNDLE_EVM_STATE_VERSION
+    );
     let actor_codes = get_code_cid_map()?;
     let store = TracingBlockStore::new(MemoryBlockstore::new());
 
-    let (pre_actors, post_actors, contract_addrs) =
-        load_evm_contract_input(&store, actor_codes, &input)?;
-    let pre_state_root = store.put_cbor(
-        &StateRoot {
-            version: fvm_shared::state::StateTreeVersion::V5,
-            actors: pre_actors,
-            info: EMPTY_ARR_CID,
-        },
-        Code::Blake2b256,
-    )?;
-    let post_state_root = store.put_cbor(
-        &StateRoot {
-            version: fvm_shared::state::StateTreeVersion::V5,
-            actors: post_actors,
-            info: EMPTY_ARR_CID,
-        },
-        Code::Blake2b256,
-    )?;
-
-    //car_bytes
-    let car_header = CarHeader::new(vec![pre_state_root, post_state_root], 1);
-    let (tx, mut rx) = bounded(100);
-    let buffer: Arc<RwLock<Vec<u8>>> = Default::default();
-    let buffer_cloned = buffer.clone();
-    let write_task = async_std::task::spawn(async move {
-        car_header
-            .write_stream_async(&mut *buffer_cloned.write().await, &mut rx)
-            .await
-            .unwrap()
-    });
-    for cid in (&store).traced.borrow().iter() {
-        tx.send((cid.clone(), store.base.get(cid).unwrap().unwrap()))
-            .await
-            .unwrap();
-    }
-    drop(tx);
-    write_task.await;
-    let car_bytes = buffer.read().await.clone();
+    let (pre_state_root, post_state_root, contract_addrs) =
+        put_state_roots(&store, actor_codes, &input, config)?;
 
-    //gzip car_bytes
-    let mut gz_car_bytes: Vec<u8> = Default::default();
-    let mut gz_encoder = GzEncoder::new(car_bytes.reader(), Compression::new(9));
-    gz_encoder.read_to_end(&mut gz_car_bytes).unwrap();
+    let gz_car_bytes = export_car(&store, vec![pre_state_root, post_state_root]).await?;
 
     //message
-    let message = to_message(&input);
+    let message = to_message(&input)?;
+    let comment = precompile_comment(&input);
 
-    //receipt
+    //receipt, a failed transaction is a revert of the EVM actor
     let receipt = Receipt {
-        exit_code: ExitCode::OK,
+        exit_code: if input.status == 0 {
+            EVM_CONTRACT_REVERTED
+        } else {
+            ExitCode::OK
+        },
         return_data: RawBytes::serialize(BytesDe(input.return_value.to_vec()))?,
         gas_used: 0,
         events_root: None,
@@ -134,7 +124,7 @@ pub async fn export_test_vector_file(
             epoch: block_number as ChainEpoch,
             cid: Cid::new_v1(
                 DAG_CBOR,
-                multihash::Multihash::wrap(IDENTITY_HASH, &block_hash.0).unwrap(),
+                multihash::Multihash::wrap(IDENTITY_HASH, &block_hash.0)?,
             ),
         });
     }
@@ -168,7 +158,7 @@ pub async fn export_test_vector_file(
             id: input.hash.encode_hex(),
             version: String::from(""),
             description: String::from(""),
-            comment: String::from(""),
+            comment,
             gen: vec![GenerationData {
                 source: env!("CARGO_PKG_REPOSITORY").to_string(),
                 version: env!("CARGO_PKG_VERSION").to_string(),
@@ -211,88 +201,251 @@ pub async fn export_test_vector_file(
     Ok(())
 }
 
+/// Gzipped CAR of the blocks traced by `store`, with `roots` in its header.
+pub(crate) async fn export_car<BS: Blockstore>(
+    store: &TracingBlockStore<BS>,
+    roots: Vec<Cid>,
+) -> anyhow::Result<Vec<u8>> {
+    //car_bytes
+    let car_header = CarHeader::new(roots, 1);
+    let (tx, mut rx) = bounded(100);
+    let buffer: Arc<RwLock<Vec<u8>>> = Default::default();
+    let buffer_cloned = buffer.clone();
+    let write_task = async_std::task::spawn(async move {
+        car_header
+            .write_stream_async(&mut *buffer_cloned.write().await, &mut rx)
+            .await
+    });
+    let traced: Vec<Cid> = store.traced.borrow().iter().cloned().collect();
+    for cid in traced {
+        let block = store
+            .base
+            .get(&cid)?
+            .with_context(|| format!("traced block {} not found", cid))?;
+        // the writer only stops receiving on failure, its error is reported below
+        if tx.send((cid, block)).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    write_task.await.context("failed to write the CAR")?;
+    let car_bytes = buffer.read().await.clone();
+
+    //gzip car_bytes
+    let mut gz_car_bytes: Vec<u8> = Default::default();
+    let mut gz_encoder = GzEncoder::new(car_bytes.reader(), Compression::new(9));
+    gz_encoder.read_to_end(&mut gz_car_bytes)?;
+    Ok(gz_car_bytes)
+}
+
+/// Compute the pre and post state tree roots the test vector of `input` would carry.
+pub fn compute_state_roots(input: &EthTransactionTestVector) -> anyhow::Result<(Cid, Cid)> {
+    let store = MemoryBlockstore::new();
+    let (pre_state_root, post_state_root, _) =
+        put_state_roots(&store, get_code_cid_map()?, input, &MockConfig::default())?;
+    Ok((pre_state_root, post_state_root))
+}
+
+pub fn put_state_roots<BS>(
+    store: &BS,
+    actor_codes: BTreeMap<Type, Cid>,
+    input: &EthTransactionTestVector,
+    config: &MockConfig,
+) -> anyhow::Result<(Cid, Cid, Vec<Address>)>
+where
+    BS: Blockstore,
+{
+    let (pre_actors, post_actors, contract_addrs) =
+        load_evm_contract_input(store, actor_codes, input, config)?;
+    let pre_state_root = store.put_cbor(
+        &StateRoot {
+            version: fvm_shared::state::StateTreeVersion::V5,
+            actors: pre_actors,
+            info: EMPTY_ARR_CID,
+        },
+        Code::Blake2b256,
+    )?;
+    let post_state_root = store.put_cbor(
+        &StateRoot {
+            version: fvm_shared::state::StateTreeVersion::V5,
+            actors: post_actors,
+            info: EMPTY_ARR_CID,
+        },
+        Code::Blake2b256,
+    )?;
+    Ok((pre_state_root, post_state_root, contract_addrs))
+}
+
+/// Note the precompile calls FEVM would execute differently.
+fn precompile_comment(input: &EthTransactionTestVector) -> String {
+    let mut notes: Vec<String> = Vec::new();
+    for call in &input.precompile_calls {
+        if let Some(note) = fevm_difference(call) {
+            if !notes.contains(&note) {
+                log::warn!("{:?}: {}", input.hash, note);
+                notes.push(note);
+            }
+        }
+    }
+    notes.join("; ")
+}
+
+/// Contracts created by the transaction in creation order, with whether their creation
+/// was reverted.
+fn created_contracts(input: &EthTransactionTestVector) -> Vec<(EthAddress, bool)> {
+    fn walk(frame: &EthCallFrame, parent_reverted: bool, created: &mut Vec<(EthAddress, bool)>) {
+        let reverted = parent_reverted || frame.reverted;
+        if (frame.call_type == OP_CREATE || frame.call_type == OP_CREATE2)
+            && !frame.callee.is_zero()
+        {
+            created.push((EthAddress(frame.callee.0), reverted));
+        }
+        for call in &frame.calls {
+            walk(call, reverted, created);
+        }
+    }
+
+    let mut created = Vec::new();
+    match &input.call_tree {
+        Some(call_tree) => walk(call_tree, false, &mut created),
+        None if input.create_contract() => created.push((
+            compute_address_create(&EthAddress(input.from.0), input.nonce),
+            false,
+        )),
+        None => (),
+    }
+    created
+}
+
 pub fn load_evm_contract_input<BS>(
     store: &BS,
     actor_codes: BTreeMap<Type, Cid>,
     input: &EthTransactionTestVector,
+    config: &MockConfig,
 ) -> anyhow::Result<(Cid, Cid, Vec<Address>)>
 where
     BS: Blockstore,
 {
     let mut contract_addrs = Vec::new();
 
-    let mut mock = Mock::new(store, actor_codes);
-    mock.mock_builtin_actor();
+    anyhow::ensure!(
+        masked_id(&input.from.0).is_none(),
+        "sender {:?} can't be a masked ID address",
+        input.from
+    );
+
+    let mut mock = Mock::new_with_config(store, actor_codes, config.clone());
+    mock.mock_builtin_actor()?;
 
-    let from = Address::new_delegated(EAM_ACTOR_ID, &input.from.0).unwrap();
-    mock.mock_embryo_address_actor(from, TokenAmount::from_whole(100000000), input.nonce);
+    // actor IDs referenced through masked ID addresses must not be allocated to other actors
+    let referenced_ids: BTreeSet<ActorID> = input
+        .prestate
+        .keys()
+        .chain(input.poststate.keys())
+        .chain([&input.to])
+        .filter_map(|k| masked_id(&k.0))
+        .collect();
+    mock.reserve_actor_ids(referenced_ids)?;
+
+    let from = Address::new_delegated(EAM_ACTOR_ID, &input.from.0)?;
+    mock.mock_embryo_address_actor(from, TokenAmount::from_whole(100000000), input.nonce)
+        .with_context(|| format!("failed to mock sender {:?}", input.from))?;
 
     // preconditions
-    let create_contract_eth_addr = if input.create_contract() {
-        Some(compute_address_create(
-            &EthAddress(input.from.0),
-            input.nonce,
-        ))
-    } else {
-        None
-    };
+    let created = created_contracts(input);
     for (k, state) in &input.prestate {
+        if is_precompile(k) {
+            continue;
+        }
         let eth_addr = EthAddress(k.0);
-        let to = Address::new_delegated(EAM_ACTOR_ID, &eth_addr.0).unwrap();
+        let to = eth_to_fil_address(&eth_addr.0)?;
         let balance = TokenAmount::from_atto(state.get_balance());
         if eth_addr.eq(&EthAddress(input.from.0)) {
             continue;
         }
+        let id = masked_id(&eth_addr.0);
+        if let Some(id) = id {
+            if id < FIRST_NON_SINGLETON_ADDR {
+                if mock.find_actor(to)?.is_none() {
+                    log::warn!("{:?} refers to builtin actor {} which isn't mocked", k, id);
+                }
+                continue;
+            }
+        }
 
         contract_addrs.push(to.clone());
 
-        if let Some(create_contract_eth_addr) = create_contract_eth_addr {
-            if eth_addr.eq(&create_contract_eth_addr) {
-                continue;
-            }
+        if created.iter().any(|(addr, _)| *addr == eth_addr) {
+            continue;
+        }
+        match id {
+            Some(id) => mock.mock_evm_actor_with_id(id, balance),
+            None => mock.mock_evm_actor(to, default_robust_address(&to), balance),
         }
-        mock.mock_evm_actor(to, balance, state.nonce);
+        .with_context(|| format!("failed to mock prestate account {:?}", k))?;
         let mut storage = HashMap::<U256, U256>::new();
         for (k, v) in &state.storage {
             let key = hex_to_u256(&hex::encode(k.0));
             let value = hex_to_u256(&hex::encode(v.0));
             storage.insert(key, value);
         }
-        mock.mock_evm_actor_state(&to, storage, Some(state.code.to_vec()))?;
+        mock.mock_evm_actor_state(&to, storage, Some(state.code.to_vec()), Some(state.nonce))
+            .with_context(|| format!("failed to mock prestate of {:?}", k))?;
     }
-    let pre_actors = mock.get_actors();
+    let pre_actors = mock.get_actors()?;
     mock.print_evm_actors("pre", pre_actors)?;
 
     // postconditions
+    // the Init actor numbers every actor created by the message, including the reverted
+    // ones, to derive their robust address.
+    for (index, (eth_addr, reverted)) in created.iter().enumerate() {
+        if *reverted {
+            continue;
+        }
+        let to = eth_to_fil_address(&eth_addr.0)?;
+        let robust_address = compute_robust_address(&from, input.nonce, index as u64);
+        mock.mock_evm_actor(to, robust_address, TokenAmount::default())
+            .with_context(|| format!("failed to mock created contract {:?}", eth_addr))?;
+    }
     for (k, state) in &input.poststate {
+        if is_precompile(k) {
+            continue;
+        }
         let eth_addr = EthAddress(k.0);
-        let to = Address::new_delegated(EAM_ACTOR_ID, &eth_addr.0).unwrap();
+        let to = eth_to_fil_address(&eth_addr.0)?;
         let balance = TokenAmount::from_atto(state.get_balance());
         if eth_addr.eq(&EthAddress(input.from.0)) {
             continue;
         }
-        if let Some(create_contract_eth_addr) = create_contract_eth_addr {
-            if eth_addr.eq(&create_contract_eth_addr) {
-                mock.mock_evm_actor(to, balance.clone(), state.nonce);
+        if let Some(id) = masked_id(&eth_addr.0) {
+            if id < FIRST_NON_SINGLETON_ADDR {
+                continue;
             }
         }
+        if created
+            .iter()
+            .any(|(addr, reverted)| *addr == eth_addr && *reverted)
+        {
+            continue;
+        }
         let mut storage = HashMap::<U256, U256>::new();
         for (k, v) in &state.storage {
             let key = hex_to_u256(&hex::encode(k.0));
             let value = hex_to_u256(&hex::encode(v.0));
             storage.insert(key, value);
         }
-        mock.mock_evm_actor_state(&to, storage, Some(state.code.to_vec()))?;
-        mock.mock_actor_balance(&to, balance, Some(state.nonce))?;
+        mock.mock_evm_actor_state(&to, storage, Some(state.code.to_vec()), Some(state.nonce))
+            .and_then(|_| mock.mock_actor_balance(&to, balance, None))
+            .with_context(|| format!("failed to mock poststate of {:?}", k))?;
     }
-    let post_actors = mock.get_actors();
+    let post_actors = mock.get_actors()?;
     mock.print_evm_actors("post", post_actors)?;
 
     return Ok((pre_actors, post_actors, contract_addrs));
 }
 
-pub fn to_message(context: &EthTransactionTestVector) -> Message {
-    let from = Address::new_delegated(EAM_ACTOR_ID, &context.from.0).unwrap();
+pub fn to_message(context: &EthTransactionTestVector) -> anyhow::Result<Message> {
+    let from = eth_to_fil_address(&context.from.0)?;
     let to: Address;
     let method_num: MethodNum;
     let mut params = RawBytes::from(vec![0u8; 0]);
@@ -303,17 +456,17 @@ pub fn to_message(context: &EthTransactionTestVector) -> Message {
             initcode: context.input.to_vec(),
             nonce: context.nonce,
         };
-        params = RawBytes::serialize(params2).unwrap();
+        params = RawBytes::serialize(params2)?;
     } else {
-        to = Address::new_delegated(EAM_ACTOR_ID, &context.to.0).unwrap();
+        to = eth_to_fil_address(&context.to.0)?;
         if context.input.len() > 0 {
-            params = RawBytes::serialize(ContractParams(context.input.to_vec())).unwrap();
+            params = RawBytes::serialize(ContractParams(context.input.to_vec()))?;
             method_num = fil_actor_evm::Method::InvokeContract as u64
         } else {
             method_num = METHOD_SEND;
         }
     }
-    Message {
+    Ok(Message {
         version: 0,
         from,
         to,
@@ -324,13 +477,14 @@ pub fn to_message(context: &EthTransactionTestVector) -> Message {
         gas_limit: (context.gas.as_u64() * 1000000) as i64,
         gas_fee_cap: TokenAmount::from_atto(context.get_max_fee_per_gas()),
         gas_premium: TokenAmount::from_atto(context.get_max_priority_fee_per_gas()),
-    }
+    })
 }
 
 pub fn get_evm_actors_slots<BS: Blockstore>(
     identifier: impl Display,
     state_root: Cid,
     store: &BS,
+    kamt_config: &KamtConfig,
 ) -> anyhow::Result<HashMap<String, HashMap<U256, U256>>> {
     println!(
         "--- {} evm actors, state_root:{} ---",
@@ -341,7 +495,7 @@ pub fn get_evm_actors_slots<BS: Blockstore>(
     actors.for_each(|_, v| {
         let state_root = v.head;
         let store = store.clone();
-        match store.get_cbor::<EvmState>(&state_root) {
+        match EvmState::load(store, &state_root) {
             Ok(res) => match res {
                 Some(state) => {
                     if v.predictable_address.is_some() {
@@ -357,7 +511,7 @@ pub fn get_evm_actors_slots<BS: Blockstore>(
                         let slots = StateKamt::load_with_config(
                             &state.contract_state,
                             store,
-                            KAMT_CONFIG.clone(),
+                            kamt_config.clone(),
                         )
                         .context_code(ExitCode::USR_ILLEGAL_STATE, "state not in blockstore")?;
                         if !slots.is_empty() {
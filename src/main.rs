@@ -1,14 +1,36 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
+use anyhow::{anyhow, ensure, Context};
 use async_std::task::block_on;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use ethers::prelude::*;
-use ethers::providers::{Http, Provider};
-use fevm_test_vectors::extractor::transaction::extract_eth_transaction_test_vector;
+use ethers::providers::Provider;
+use fevm_test_vectors::anonymize::anonymize_test_vector;
+use fevm_test_vectors::coverage::Coverage;
+use fevm_test_vectors::extractor::backend::Backend;
+use fevm_test_vectors::extractor::batch::{
+    extract_batch, read_tx_hashes, select_transactions, TxFilter, EXTRACT_STATE_FILE,
+};
+use fevm_test_vectors::extractor::cache::RpcCache;
+use fevm_test_vectors::extractor::evm::{synthesize_test_vector, EvmInput};
+use fevm_test_vectors::extractor::lotus::{export_lotus_test_vector_file, Precursors};
+use fevm_test_vectors::extractor::policy::RpcPolicy;
+use fevm_test_vectors::extractor::transaction::{
+    extract_eth_transaction_test_vector, trace_transaction,
+};
+use fevm_test_vectors::extractor::transport::connect_provider;
 use fevm_test_vectors::extractor::types::EthTransactionTestVector;
+use fevm_test_vectors::extractor::watch::{WatchState, Watcher};
+use fevm_test_vectors::generate::{generate_in_parallel, load_json, load_test_vector};
+use fevm_test_vectors::minimize::{minimize_test_vector, verify_minimized, EvmExecutor};
+use fevm_test_vectors::mock::MockConfig;
+use fevm_test_vectors::scenario::{compile_scenario, load_scenario};
+use fevm_test_vectors::storage_stats::storage_layout_stats;
 use fevm_test_vectors::{export_test_vector_file, init_log};
 use walkdir::{DirEntry, WalkDir};
 
@@ -23,32 +45,242 @@ enum SubCommand {
     Extract(Extract),
     Generate(Generate),
     GenerateFromFile(GenerateFromFile),
+    Anonymize(Anonymize),
+    Minimize(Minimize),
+    Coverage(CoverageCmd),
+    Stats(Stats),
+    Cache(CacheCmd),
+    Watch(Watch),
+    Lotus(Lotus),
+    Synthesize(Synthesize),
+    Scenario(ScenarioCmd),
+}
+
+/// Transactions to extract, a single one, a list file, or a block range plus filters.
+#[derive(Debug, Args)]
+pub struct TxSelection {
+    /// eth transaction hash
+    #[clap(short, long)]
+    tx_hash: Option<String>,
+
+    /// file of eth transaction hashes, one per line
+    #[clap(long)]
+    tx_file: Option<String>,
+
+    /// first block of the range to extract transactions from
+    #[clap(long)]
+    from_block: Option<u64>,
+
+    /// last block of the range, defaults to from_block
+    #[clap(long)]
+    to_block: Option<u64>,
+
+    #[clap(flatten)]
+    filter: TxFilterArgs,
+
+    /// number of transactions extracted concurrently
+    #[clap(long, default_value = "4")]
+    concurrency: usize,
+}
+
+impl TxSelection {
+    async fn tx_hashes<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> anyhow::Result<Vec<H256>> {
+        if let Some(tx_file) = &self.tx_file {
+            return read_tx_hashes(Path::new(tx_file));
+        }
+        let from_block = self
+            .from_block
+            .context("one of --tx-hash, --tx-file or --from-block is required")?;
+        let to_block = self.to_block.unwrap_or(from_block);
+        select_transactions(provider, from_block..=to_block, &self.filter.tx_filter()?).await
+    }
+}
+
+/// Criteria of the transactions to extract from blocks.
+#[derive(Debug, Args)]
+pub struct TxFilterArgs {
+    /// only transactions sent to this address, or contract creations deployed by it
+    #[clap(long)]
+    to: Option<String>,
+
+    /// only transactions whose input starts with this 4-byte selector
+    #[clap(long)]
+    selector: Option<String>,
+
+    /// only contract creations
+    #[clap(long)]
+    creations_only: bool,
+
+    /// only failed transactions, their test vectors expect a revert
+    #[clap(long)]
+    failed_only: bool,
+
+    /// only transactions using at least this much gas
+    #[clap(long)]
+    min_gas_used: Option<u64>,
+}
+
+impl TxFilterArgs {
+    fn tx_filter(&self) -> anyhow::Result<TxFilter> {
+        let selector = match &self.selector {
+            Some(selector) => Some(
+                <[u8; 4]>::try_from(hex::decode(selector.trim_start_matches("0x"))?.as_slice())
+                    .map_err(|_| anyhow!("selector must be 4 bytes"))?,
+            ),
+            None => None,
+        };
+        Ok(TxFilter {
+            to: self.to.as_deref().map(H160::from_str).transpose()?,
+            selector,
+            creations_only: self.creations_only,
+            failed_only: self.failed_only,
+            min_gas_used: self.min_gas_used,
+        })
+    }
+}
+
+/// Retries, timeout and rate limit of the RPC requests.
+#[derive(Debug, Args)]
+pub struct RpcPolicyArgs {
+    /// number of times a failed RPC request is retried
+    #[clap(long, default_value = "3")]
+    rpc_retries: u32,
+
+    /// delay before the first retry in milliseconds, doubled at each retry
+    #[clap(long, default_value = "500")]
+    rpc_backoff: u64,
+
+    /// timeout of each RPC request attempt, in seconds
+    #[clap(long)]
+    rpc_timeout: Option<u64>,
+
+    /// max number of RPC requests per second, shared by concurrent extractions
+    #[clap(long)]
+    rpc_rate_limit: Option<f64>,
+}
+
+impl RpcPolicyArgs {
+    fn policy(&self) -> anyhow::Result<RpcPolicy> {
+        if let Some(rate) = self.rpc_rate_limit {
+            ensure!(
+                rate.is_finite() && rate > 0.0,
+                "--rpc-rate-limit must be a positive number, got {}",
+                rate
+            );
+        }
+        Ok(RpcPolicy {
+            retries: self.rpc_retries,
+            backoff: Duration::from_millis(self.rpc_backoff),
+            timeout: self.rpc_timeout.map(Duration::from_secs),
+            requests_per_second: self.rpc_rate_limit,
+        })
+    }
+}
+
+/// Local cache of the RPC responses of the extraction.
+#[derive(Debug, Args)]
+pub struct CacheArgs {
+    /// directory caching the RPC responses, nothing is cached if not set
+    #[clap(long)]
+    cache_dir: Option<String>,
+
+    /// size in MiB above which the oldest cached transactions are evicted
+    #[clap(long)]
+    cache_max_size: Option<u64>,
+}
+
+impl CacheArgs {
+    fn open(&self) -> anyhow::Result<Option<RpcCache>> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| RpcCache::open(dir, self.cache_max_size.map(|size| size << 20)))
+            .transpose()
+    }
+}
+
+/// Parameters of the generated state, they must match the target actor version. The EVM
+/// state layout is the one of the embedded actor bundle.
+#[derive(Debug, Args)]
+pub struct StateArgs {
+    /// bit width of the contract storage KAMTs
+    #[clap(long)]
+    kamt_bit_width: Option<u32>,
+
+    /// min data depth of the contract storage KAMTs
+    #[clap(long)]
+    kamt_min_data_depth: Option<usize>,
+
+    /// max array width of the contract storage KAMTs
+    #[clap(long)]
+    kamt_max_array_width: Option<usize>,
+}
+
+impl StateArgs {
+    fn mock_config(&self) -> MockConfig {
+        let mut config = MockConfig::default();
+        if let Some(bit_width) = self.kamt_bit_width {
+            config.kamt.bit_width = bit_width;
+        }
+        if let Some(min_data_depth) = self.kamt_min_data_depth {
+            config.kamt.min_data_depth = min_data_depth;
+        }
+        if let Some(max_array_width) = self.kamt_max_array_width {
+            config.kamt.max_array_width = max_array_width;
+        }
+        config
+    }
 }
 
 #[derive(Debug, Parser)]
 #[clap(about = "Generate test vector from geth rpc directly.", long_about = None)]
 pub struct Generate {
+    /// geth rpc endpoint, an http(s):// or ws(s):// url or the path of an IPC socket
     #[clap(short, long)]
     geth_rpc_endpoint: String,
 
-    /// eth transaction hash
-    #[clap(short, long)]
-    tx_hash: String,
+    #[clap(flatten)]
+    rpc_policy: RpcPolicyArgs,
+
+    /// tracing api of the node: geth, erigon or parity
+    #[clap(long, default_value = "geth")]
+    backend: Backend,
+
+    #[clap(flatten)]
+    txs: TxSelection,
+
+    #[clap(flatten)]
+    cache: CacheArgs,
 
     /// test vector output dir path
     #[clap(short, long)]
     out_dir: String,
+
+    #[clap(flatten)]
+    state: StateArgs,
 }
 
 #[derive(Debug, Parser)]
 #[clap(about = "Extract transaction detail file through evm tracing.", long_about = None)]
 pub struct Extract {
+    /// geth rpc endpoint, an http(s):// or ws(s):// url or the path of an IPC socket
     #[clap(short, long)]
     geth_rpc_endpoint: String,
 
-    /// eth transaction hash
-    #[clap(short, long)]
-    tx_hash: String,
+    #[clap(flatten)]
+    rpc_policy: RpcPolicyArgs,
+
+    /// tracing api of the node: geth, erigon or parity
+    #[clap(long, default_value = "geth")]
+    backend: Backend,
+
+    #[clap(flatten)]
+    txs: TxSelection,
+
+    #[clap(flatten)]
+    cache: CacheArgs,
 
     /// test vector output dir path
     #[clap(short, long)]
@@ -65,6 +297,214 @@ pub struct GenerateFromFile {
     /// fvm test vector output dir path
     #[clap(short, long)]
     out_dir: String,
+
+    /// batch summary output file path, printed to stdout if not set
+    #[clap(short, long)]
+    summary: Option<String>,
+
+    /// number of files generated in parallel, defaults to the number of CPUs
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    #[clap(flatten)]
+    state: StateArgs,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Remap account addresses of transation detail files to synthetic ones.", long_about = None)]
+pub struct Anonymize {
+    /// evm test vector input file/dir path
+    #[clap(short, long)]
+    input: String,
+
+    /// secret seed the synthetic addresses are derived from
+    #[clap(short, long)]
+    seed: String,
+
+    /// anonymized evm test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Drop accounts and slots the transaction doesn't depend on from transation detail files.", long_about = None)]
+pub struct Minimize {
+    /// evm test vector input file/dir path
+    #[clap(short, long)]
+    input: String,
+
+    /// minimized evm test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+
+    /// re-execute minimized vectors with the embedded EVM and compare post-state roots
+    #[clap(long)]
+    verify: bool,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Report opcodes, precompiles and EVM features exercised by transactions.", long_about = None)]
+pub struct CoverageCmd {
+    /// structLog trace or evm test vector input file/dir path
+    #[clap(short, long)]
+    input: String,
+
+    /// geth rpc used to re-trace evm test vectors
+    #[clap(short, long)]
+    geth_rpc_endpoint: Option<String>,
+
+    #[clap(flatten)]
+    rpc_policy: RpcPolicyArgs,
+
+    /// coverage report output file path
+    #[clap(short, long)]
+    out: String,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Report the depth, node count and size of the contract storage KAMTs of transation detail files.", long_about = None)]
+pub struct Stats {
+    /// evm test vector input file/dir path
+    #[clap(short, long)]
+    input: String,
+
+    /// stats report output file path
+    #[clap(short, long)]
+    out: String,
+
+    #[clap(flatten)]
+    state: StateArgs,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Follow the chain head and generate test vectors of matching transactions.", long_about = None)]
+pub struct Watch {
+    /// geth rpc endpoint, new blocks are subscribed to over WebSocket and IPC
+    #[clap(short, long)]
+    geth_rpc_endpoint: String,
+
+    #[clap(flatten)]
+    rpc_policy: RpcPolicyArgs,
+
+    /// tracing api of the node: geth, erigon or parity
+    #[clap(long, default_value = "geth")]
+    backend: Backend,
+
+    #[clap(flatten)]
+    filter: TxFilterArgs,
+
+    #[clap(flatten)]
+    cache: CacheArgs,
+
+    /// first block to watch when starting afresh, defaults to the chain head
+    #[clap(long)]
+    from_block: Option<u64>,
+
+    /// seconds between two polls of the chain head, without subscription
+    #[clap(long, default_value = "4")]
+    poll_interval: u64,
+
+    /// write transaction detail files instead of test vectors
+    #[clap(long)]
+    extract_only: bool,
+
+    /// test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+
+    #[clap(flatten)]
+    state: StateArgs,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Generate test vectors from FEVM transactions executed by a Lotus node.", long_about = None)]
+pub struct Lotus {
+    /// lotus rpc endpoint, e.g. http://127.0.0.1:1234/rpc/v1
+    #[clap(short, long)]
+    lotus_rpc_endpoint: String,
+
+    #[clap(flatten)]
+    rpc_policy: RpcPolicyArgs,
+
+    /// eth transaction hash
+    #[clap(short, long, required = true)]
+    tx_hash: Vec<String>,
+
+    /// messages of the tipset applied before the transaction: all, or only the sender's
+    #[clap(long, default_value = "all")]
+    precursors: Precursors,
+
+    /// test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Generate test vectors from transactions executed by an embedded EVM, without a node.", long_about = None)]
+pub struct Synthesize {
+    /// prestate, transaction and block json file path
+    #[clap(short, long, required = true)]
+    input: Vec<String>,
+
+    /// test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+
+    /// write the transaction detail file instead of the test vector
+    #[clap(long)]
+    extract_only: bool,
+
+    #[clap(flatten)]
+    state: StateArgs,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Compile hand written YAML or TOML scenarios into test vectors.", long_about = None)]
+pub struct ScenarioCmd {
+    /// scenario file path, .yaml, .toml or .json
+    #[clap(short, long, required = true)]
+    input: Vec<String>,
+
+    /// test vector output dir path
+    #[clap(short, long)]
+    out_dir: String,
+
+    /// write the transaction detail file instead of the test vector
+    #[clap(long)]
+    extract_only: bool,
+
+    #[clap(flatten)]
+    state: StateArgs,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about = "Inspect and invalidate the local cache of RPC responses.", long_about = None)]
+pub struct CacheCmd {
+    /// directory caching the RPC responses
+    #[clap(long)]
+    cache_dir: String,
+
+    #[clap(subcommand)]
+    action: CacheAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheAction {
+    /// Print the number of cached transactions and the cache size
+    Info,
+    /// Drop the cached responses of the given transactions
+    Invalidate {
+        /// eth transaction hash
+        #[clap(short, long, required = true)]
+        tx_hash: Vec<String>,
+    },
+    /// Evict the oldest cached transactions until the cache fits in max-size MiB
+    Prune {
+        #[clap(long)]
+        max_size: u64,
+    },
+    /// Drop all the cached responses
+    Clear,
 }
 
 #[tokio::main]
@@ -73,61 +513,350 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     match cli.cmd {
         SubCommand::Generate(config) => {
-            let out_dir = Path::new(&config.out_dir);
-            assert!(out_dir.is_dir(), "out_dir must directory");
-            let tx_hash = H256::from_str(&*config.tx_hash)?;
-            let provider = Provider::<Http>::try_from(config.geth_rpc_endpoint)
-                .expect("could not instantiate HTTP Provider");
-            let evm_input = extract_eth_transaction_test_vector(&provider, tx_hash).await?;
-            let path = out_dir.join(format!("{}.json", config.tx_hash));
-            block_on(export_test_vector_file(evm_input, path))?;
+            let out_dir = output_dir(&config.out_dir)?;
+            let provider =
+                connect_provider(&config.geth_rpc_endpoint, config.rpc_policy.policy()?).await?;
+            let cache = config.cache.open()?;
+            let mock_config = config.state.mock_config();
+            if let Some(tx_hash) = &config.txs.tx_hash {
+                let tx_hash = H256::from_str(tx_hash)?;
+                let evm_input = extract_eth_transaction_test_vector(
+                    &provider,
+                    tx_hash,
+                    cache.as_ref(),
+                    config.backend,
+                )
+                .await?;
+                let path = out_dir.join(format!("{:?}.json", tx_hash));
+                block_on(export_test_vector_file(evm_input, path, &mock_config))?;
+            } else {
+                let hashes = config.txs.tx_hashes(&provider).await?;
+                let state = extract_batch(
+                    &provider,
+                    hashes,
+                    out_dir,
+                    config.txs.concurrency,
+                    cache.as_ref(),
+                    config.backend,
+                    |tx_hash, evm_input| {
+                        let path = out_dir.join(format!("{:?}.json", tx_hash));
+                        block_on(export_test_vector_file(evm_input, path, &mock_config))
+                    },
+                )
+                .await?;
+                if !state.failed.is_empty() {
+                    return Err(anyhow!(
+                        "{} transactions failed, see {:?}",
+                        state.failed.len(),
+                        out_dir.join(EXTRACT_STATE_FILE)
+                    ));
+                }
+            }
         }
         SubCommand::Extract(config) => {
-            let out_dir = Path::new(&config.out_dir);
-            assert!(out_dir.is_dir(), "out_dir must directory");
-            let tx_hash = H256::from_str(&*config.tx_hash)?;
-            let provider = Provider::<Http>::try_from(config.geth_rpc_endpoint)
-                .expect("could not instantiate HTTP Provider");
-            let evm_input = extract_eth_transaction_test_vector(&provider, tx_hash).await?;
-            let path = out_dir.join(format!("{}.json", config.tx_hash));
-            let output = File::create(&path)?;
-            serde_json::to_writer_pretty(output, &evm_input)?;
+            let out_dir = output_dir(&config.out_dir)?;
+            let provider =
+                connect_provider(&config.geth_rpc_endpoint, config.rpc_policy.policy()?).await?;
+            let cache = config.cache.open()?;
+            if let Some(tx_hash) = &config.txs.tx_hash {
+                let tx_hash = H256::from_str(tx_hash)?;
+                let evm_input = extract_eth_transaction_test_vector(
+                    &provider,
+                    tx_hash,
+                    cache.as_ref(),
+                    config.backend,
+                )
+                .await?;
+                let path = out_dir.join(format!("{:?}.json", tx_hash));
+                let output = File::create(&path)?;
+                serde_json::to_writer_pretty(output, &evm_input)?;
+            } else {
+                let hashes = config.txs.tx_hashes(&provider).await?;
+                let state = extract_batch(
+                    &provider,
+                    hashes,
+                    out_dir,
+                    config.txs.concurrency,
+                    cache.as_ref(),
+                    config.backend,
+                    |tx_hash, evm_input| {
+                        let path = out_dir.join(format!("{:?}.json", tx_hash));
+                        let output = File::create(&path)?;
+                        serde_json::to_writer_pretty(output, &evm_input)?;
+                        Ok(())
+                    },
+                )
+                .await?;
+                if !state.failed.is_empty() {
+                    return Err(anyhow!(
+                        "{} transactions failed, see {:?}",
+                        state.failed.len(),
+                        out_dir.join(EXTRACT_STATE_FILE)
+                    ));
+                }
+            }
         }
         SubCommand::GenerateFromFile(config) => {
-            let out_dir = Path::new(&config.out_dir);
-            assert!(out_dir.is_dir(), "out_dir must directory");
-            let input = Path::new(&config.input);
-            if input.is_dir() {
-                let files: Vec<PathBuf> = WalkDir::new(input)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(is_runnable)
-                    .map(|e| e.path().to_path_buf())
-                    .collect();
-
-                for p in files {
-                    let file_name = p.file_name().unwrap().to_str().unwrap();
-                    let file = File::open(p.clone())?;
-                    let reader = BufReader::new(file);
-                    let evm_input: EthTransactionTestVector = serde_json::from_reader(reader)
-                        .expect(&*format!("Serialization failed: {:?}", p));
-                    let path = out_dir.join(file_name);
-                    block_on(export_test_vector_file(evm_input, path))?;
+            let out_dir = output_dir(&config.out_dir)?;
+            let mock_config = config.state.mock_config();
+            let files = input_files(Path::new(&config.input));
+
+            let jobs = config.jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            });
+            // every input is processed, failures are reported in the summary
+            let results = generate_in_parallel(&files, out_dir, &mock_config, jobs);
+            let failed = results.iter().filter(|r| !r.success).count();
+            match &config.summary {
+                Some(summary) => serde_json::to_writer_pretty(File::create(summary)?, &results)?,
+                None => println!("{}", serde_json::to_string_pretty(&results)?),
+            }
+            if failed > 0 {
+                return Err(anyhow!("{} of {} inputs failed", failed, results.len()));
+            }
+        }
+        SubCommand::Anonymize(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let files = input_files(Path::new(&config.input));
+            for p in files {
+                let evm_input = load_test_vector(&p)?;
+                let (evm_output, report) =
+                    anonymize_test_vector(&evm_input, config.seed.as_bytes());
+                for occurrence in &report.unresolved {
+                    log::warn!(
+                        "{:?}: unresolved {:?} at {}: {}",
+                        p,
+                        occurrence.address,
+                        occurrence.location,
+                        occurrence.reason
+                    );
                 }
-            } else {
-                let file_name = input.file_name().unwrap().to_str().unwrap();
-                let file = File::open(input.clone())?;
-                let reader = BufReader::new(file);
-                let evm_input: EthTransactionTestVector = serde_json::from_reader(reader)
-                    .expect(&*format!("Serialization failed: {:?}", input));
-                let path = out_dir.join(file_name);
-                block_on(export_test_vector_file(evm_input, path))?;
+                log::info!(
+                    "{:?}: remapped {} accounts, rewrote {} occurrences, {} unresolved",
+                    p,
+                    report.remapped_accounts,
+                    report.rewritten_occurrences,
+                    report.unresolved.len()
+                );
+                let path = out_dir.join(p.file_name().unwrap());
+                let output = File::create(&path)?;
+                serde_json::to_writer_pretty(output, &evm_output)?;
+            }
+        }
+        SubCommand::Minimize(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let files = input_files(Path::new(&config.input));
+            for p in files {
+                let evm_input = load_test_vector(&p)?;
+                let (evm_output, report) = minimize_test_vector(&evm_input)?;
+                if config.verify {
+                    verify_minimized(&evm_input, &evm_output, &EvmExecutor)
+                        .with_context(|| format!("{:?} does not minimize safely", p))?;
+                }
+                log::info!(
+                    "{:?}: dropped {} accounts and {} slots, kept {} slots",
+                    p,
+                    report.dropped_accounts.len(),
+                    report.dropped_slots,
+                    report.kept_slots
+                );
+                let path = out_dir.join(p.file_name().unwrap());
+                let output = File::create(&path)?;
+                serde_json::to_writer_pretty(output, &evm_output)?;
+            }
+        }
+        SubCommand::Coverage(config) => {
+            let files = input_files(Path::new(&config.input));
+            let provider = match &config.geth_rpc_endpoint {
+                Some(endpoint) => {
+                    Some(connect_provider(endpoint, config.rpc_policy.policy()?).await?)
+                }
+                None => None,
+            };
+            let mut coverage = Coverage::default();
+            for p in files {
+                let value: serde_json::Value = load_json(&p)?;
+                let trace: GethTrace = if value.get("structLogs").is_some() {
+                    serde_json::from_value(value)?
+                } else {
+                    let evm_input: EthTransactionTestVector = serde_json::from_value(value)
+                        .with_context(|| format!("Serialization failed: {:?}", p))?;
+                    let provider = provider.as_ref().with_context(|| {
+                        format!(
+                            "--geth-rpc-endpoint is required to re-trace the transaction of {:?}",
+                            p
+                        )
+                    })?;
+                    trace_transaction(provider, evm_input.hash).await?
+                };
+                coverage.record_trace(&trace);
+            }
+            let report = coverage.report();
+            log::info!(
+                "{} transactions, {} opcodes and {} precompiles not covered, missing features: {:?}",
+                report.coverage.transactions,
+                report.missing_opcodes.len(),
+                report.missing_precompiles.len(),
+                report.missing_features
+            );
+            let output = File::create(&config.out)?;
+            serde_json::to_writer_pretty(output, &report)?;
+        }
+        SubCommand::Stats(config) => {
+            let kamt_config = config.state.mock_config().kamt;
+            let files = input_files(Path::new(&config.input));
+            let mut report = BTreeMap::new();
+            for p in files {
+                let evm_input = load_test_vector(&p)?;
+                let stats = storage_layout_stats(&evm_input, &kamt_config)?;
+                for contract in &stats {
+                    log::info!(
+                        "{:?} {:?}: post storage of {} slots, depth {}, {} nodes, {} bytes",
+                        p,
+                        contract.address,
+                        contract.post.entries,
+                        contract.post.depth,
+                        contract.post.nodes,
+                        contract.post.size
+                    );
+                }
+                report.insert(p.display().to_string(), stats);
+            }
+            let output = File::create(&config.out)?;
+            serde_json::to_writer_pretty(output, &report)?;
+        }
+        SubCommand::Watch(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let provider =
+                connect_provider(&config.geth_rpc_endpoint, config.rpc_policy.policy()?).await?;
+            let cache = config.cache.open()?;
+            let mock_config = config.state.mock_config();
+            let extract_only = config.extract_only;
+            let mut watcher = Watcher {
+                provider: &provider,
+                out_dir,
+                filter: config.filter.tx_filter()?,
+                cache: cache.as_ref(),
+                backend: config.backend,
+                write: |path: &Path, evm_input| {
+                    if extract_only {
+                        let output = File::create(path)?;
+                        serde_json::to_writer_pretty(output, &evm_input)?;
+                        Ok(())
+                    } else {
+                        block_on(export_test_vector_file(
+                            evm_input,
+                            path.to_path_buf(),
+                            &mock_config,
+                        ))
+                    }
+                },
+                state: WatchState::load(out_dir)?,
+            };
+            watcher
+                .run(config.from_block, Duration::from_secs(config.poll_interval))
+                .await?;
+        }
+        SubCommand::Lotus(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let provider =
+                connect_provider(&config.lotus_rpc_endpoint, config.rpc_policy.policy()?).await?;
+            for tx_hash in &config.tx_hash {
+                let path = out_dir.join(format!("{}.json", tx_hash));
+                export_lotus_test_vector_file(
+                    &provider,
+                    H256::from_str(tx_hash)?,
+                    &path,
+                    config.precursors,
+                )
+                .await?;
+            }
+        }
+        SubCommand::Synthesize(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let mock_config = config.state.mock_config();
+            for input in &config.input {
+                let input = Path::new(input);
+                let evm_input: EvmInput =
+                    serde_json::from_reader(BufReader::new(File::open(input)?))
+                        .with_context(|| format!("invalid embedded EVM input {:?}", input))?;
+                let test_vector = synthesize_test_vector(&evm_input).await?;
+                let path = out_dir.join(input.file_name().context("input is not a file")?);
+                if config.extract_only {
+                    serde_json::to_writer_pretty(File::create(&path)?, &test_vector)?;
+                } else {
+                    block_on(export_test_vector_file(test_vector, path, &mock_config))?;
+                }
+            }
+        }
+        SubCommand::Scenario(config) => {
+            let out_dir = output_dir(&config.out_dir)?;
+            let mock_config = config.state.mock_config();
+            for input in &config.input {
+                let input = Path::new(input);
+                let test_vector = compile_scenario(&load_scenario(input)?)
+                    .with_context(|| format!("cannot compile {:?}", input))?;
+                let stem = input.file_stem().context("input is not a file")?;
+                let path = out_dir.join(format!("{}.json", stem.to_string_lossy()));
+                if config.extract_only {
+                    serde_json::to_writer_pretty(File::create(&path)?, &test_vector)?;
+                } else {
+                    block_on(export_test_vector_file(test_vector, path, &mock_config))?;
+                }
+            }
+        }
+        SubCommand::Cache(config) => {
+            let cache = RpcCache::open(&config.cache_dir, None)?;
+            match config.action {
+                CacheAction::Info => {
+                    let info = cache.info()?;
+                    println!(
+                        "{} transactions, {} responses, {} bytes",
+                        info.transactions, info.blobs, info.size
+                    );
+                }
+                CacheAction::Invalidate { tx_hash } => {
+                    for tx_hash in tx_hash {
+                        if !cache.invalidate(&H256::from_str(&tx_hash)?)? {
+                            log::warn!("{} is not cached", tx_hash);
+                        }
+                    }
+                }
+                CacheAction::Prune { max_size } => cache.prune(max_size << 20)?,
+                CacheAction::Clear => cache.clear()?,
             }
         }
     }
     Ok(())
 }
 
+/// Returns the output directory, which must exist.
+fn output_dir(out_dir: &str) -> anyhow::Result<&Path> {
+    let out_dir = Path::new(out_dir);
+    ensure!(
+        out_dir.is_dir(),
+        "out_dir {:?} must be a directory",
+        out_dir
+    );
+    Ok(out_dir)
+}
+
+/// Returns the input file, or the runnable files under the input directory.
+fn input_files(input: &Path) -> Vec<PathBuf> {
+    if input.is_dir() {
+        WalkDir::new(input)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(is_runnable)
+            .map(|e| e.path().to_path_buf())
+            .collect()
+    } else {
+        vec![input.to_path_buf()]
+    }
+}
+
 pub fn is_runnable(entry: &DirEntry) -> bool {
     let file_name = match entry.path().to_str() {
         Some(file) => file,
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, ensure, Context};
+use ethers::types::H160;
+use serde::{Deserialize, Serialize};
+
+use crate::compute_state_roots;
+use crate::extractor::evm::{EvmBlock, EvmInput, EvmSource, EvmTransaction};
+use crate::extractor::types::{EthState, EthTransactionTestVector};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MinimizeReport {
+    pub dropped_accounts: Vec<H160>,
+    pub dropped_slots: usize,
+    pub kept_slots: usize,
+}
+
+/// Executes the transaction of a vector against its prestate.
+pub trait VectorExecutor {
+    /// Returns the poststate resulting from the execution.
+    fn execute(&self, input: &EthTransactionTestVector) -> anyhow::Result<EthState>;
+}
+
+/// Executes vectors with the embedded EVM, London rules with the identity precompile only.
+pub struct EvmExecutor;
+
+impl VectorExecutor for EvmExecutor {
+    fn execute(&self, input: &EthTransactionTestVector) -> anyhow::Result<EthState> {
+        let evm_input = EvmInput {
+            prestate: input.prestate.clone(),
+            transaction: EvmTransaction {
+                from: input.from,
+                to: (!input.create_contract()).then_some(input.to),
+                value: input.value,
+                input: input.input.clone(),
+                gas: input.gas,
+                gas_price: input.gas_price,
+            },
+            block: EvmBlock {
+                number: input.block_number,
+                coinbase: input.coinbase,
+                timestamp: input.timestamp,
+                base_fee_per_gas: input.base_fee_per_gas,
+                difficulty: input.difficultly,
+                random: input.random,
+                chain_id: input.chain_id,
+                block_hashes: input.block_hashes.clone(),
+                ..Default::default()
+            },
+        };
+        let mut poststate = EvmSource::execute(&evm_input)?.poststate;
+        // the embedded EVM doesn't pay the priority fee to the coinbase
+        match input.poststate.get(&input.coinbase) {
+            Some(coinbase) => poststate.insert(input.coinbase, coinbase.clone()),
+            None => poststate.remove(&input.coinbase),
+        };
+        Ok(poststate)
+    }
+}
+
+/// Keep only the accounts and storage slots the execution of `input` depends on,
+/// according to the observations made by the structLog replay.
+///
+/// An account is kept if it's accessed or changed by the transaction. A prestate slot is
+/// kept if it's read by SLOAD, or written without changing its value, otherwise the
+/// poststate alone carries it.
+pub fn minimize_test_vector(
+    input: &EthTransactionTestVector,
+) -> anyhow::Result<(EthTransactionTestVector, MinimizeReport)> {
+    let accesses = input.accesses.as_ref().ok_or_else(|| {
+        anyhow!(
+            "no replay observations for {:?}, extract the transaction again",
+            input.hash
+        )
+    })?;
+
+    let mut output = input.clone();
+    let mut report = MinimizeReport::default();
+    for (addr, pre) in &input.prestate {
+        let post = input.poststate.get(addr);
+        let changed = post.map(|post| post != pre).unwrap_or(true);
+        if *addr != input.from && !changed && !accesses.is_accessed(addr) {
+            output.prestate.remove(addr);
+            output.poststate.remove(addr);
+            report.dropped_accounts.push(*addr);
+            report.dropped_slots += pre.storage.len();
+            continue;
+        }
+
+        let reads = accesses.storage_reads.get(addr);
+        let writes = accesses.storage_writes.get(addr);
+        let pre_min = output.prestate.get_mut(addr).unwrap();
+        pre_min.storage.retain(|key, value| {
+            let read = reads.map(|r| r.contains(key)).unwrap_or(false);
+            let written = writes.map(|w| w.contains(key)).unwrap_or(false);
+            let unchanged = post.and_then(|post| post.storage.get(key)) == Some(value);
+            read || (written && unchanged)
+        });
+        report.dropped_slots += pre.storage.len() - pre_min.storage.len();
+        report.kept_slots += pre_min.storage.len();
+
+        // untouched slots dropped from the prestate must not show up in the poststate.
+        if let Some(post_min) = output.poststate.get_mut(addr) {
+            post_min.storage.retain(|key, value| {
+                pre_min.storage.contains_key(key) || pre.storage.get(key) != Some(value)
+            });
+        }
+    }
+    Ok((output, report))
+}
+
+/// Check that executing the minimized vector yields the same post-state root as the one
+/// built from its poststate.
+///
+/// The executor must first reproduce the poststate of the `original` vector, otherwise it
+/// runs other rules than the transaction did (a later fork, an access list, a precompile
+/// it doesn't implement) and the check would say nothing about the minimization.
+pub fn verify_minimized(
+    original: &EthTransactionTestVector,
+    minimized: &EthTransactionTestVector,
+    executor: &dyn VectorExecutor,
+) -> anyhow::Result<()> {
+    check_execution(original, executor).context("the minimization can't be verified")?;
+    check_execution(minimized, executor).context("the minimized prestate changes the execution")
+}
+
+/// Check that executing `vector` yields the post-state root of its poststate.
+fn check_execution(
+    vector: &EthTransactionTestVector,
+    executor: &dyn VectorExecutor,
+) -> anyhow::Result<()> {
+    let (_, expected) = compute_state_roots(vector)?;
+    let mut executed = vector.clone();
+    executed.poststate = executor.execute(vector)?;
+    let (_, actual) = compute_state_roots(&executed)?;
+    ensure!(
+        expected == actual,
+        "post-state root mismatch for {:?}, expected {} but execution yields {}",
+        vector.hash,
+        expected,
+        actual
+    );
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
 
 use cid::multihash::MultihashDigest;
@@ -7,7 +8,7 @@ use cid::Cid;
 use fil_actor_account::State as AccountState;
 use fil_actor_eam::EthAddress;
 use fil_actor_evm::interpreter::system::StateKamt;
-use fil_actor_evm::interpreter::{StatusCode, U256};
+use fil_actor_evm::interpreter::U256;
 use fil_actor_init::State as InitState;
 use fil_actor_reward::State as RewardState;
 use fil_actor_system::State as SystemState;
@@ -27,14 +28,14 @@ use fvm_shared::crypto::hash::SupportedHashes;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ExitCode;
 use fvm_shared::sector::StoragePower;
-use fvm_shared::{HAMT_BIT_WIDTH, IPLD_RAW};
-use multihash::{Code, MultihashGeneric};
+use fvm_shared::{ActorID, HAMT_BIT_WIDTH, IPLD_RAW};
+use multihash::Code;
 use num_traits::Zero;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::evm_state::State as EvmState;
-use crate::util::u256_to_bytes;
+use crate::evm_state::{State as EvmState, StateVersion};
+use crate::util::{u256_to_bytes, BUNDLE_EVM_STATE_VERSION};
 
 lazy_static::lazy_static! {
     // The Solidity compiler creates contiguous array item keys.
@@ -56,7 +57,8 @@ lazy_static::lazy_static! {
     // or by not allowing many KV pairs in a slot.
     //
     // The following values have been set by looking at how the charts evolved
-    // with the test contract. They might not be the best for other contracts.
+    // with the test contract. They might not be the best for other contracts,
+    // and must match the ones of the target actor version, see `MockConfig`.
     pub static ref KAMT_CONFIG: KamtConfig = KamtConfig {
         min_data_depth: 0,
         bit_width: 5,
@@ -89,13 +91,72 @@ pub fn actor(
     }
 }
 
+/// Parameters of the state written by `Mock`, they must match the target actor bundle.
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    /// parameters of the contract storage KAMTs
+    pub kamt: KamtConfig,
+    /// layout of the EVM actor states
+    pub evm_state_version: StateVersion,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            kamt: KAMT_CONFIG.clone(),
+            evm_state_version: BUNDLE_EVM_STATE_VERSION,
+        }
+    }
+}
+
+/// EVM state of a contract, with its storage KAMT loaded.
+struct LiveContract<'bs, BS>
+where
+    BS: Blockstore,
+{
+    // `contract_state` is stale until the storage is flushed
+    state: EvmState,
+    slots: StateKamt<&'bs BS>,
+    dirty: bool,
+}
+
 pub struct Mock<'bs, BS>
 where
     BS: Blockstore,
 {
     store: &'bs BS,
-    actors: RefCell<Cid>,
+    // changes are kept in memory until the next `get_actors`
+    actors: RefCell<Hamt<&'bs BS, Actor>>,
+    contracts: RefCell<HashMap<Address, LiveContract<'bs, BS>>>,
     actor_codes: BTreeMap<Type, Cid>,
+    // actor IDs referenced through masked ID addresses, never allocated to other actors
+    reserved_ids: BTreeSet<ActorID>,
+    config: MockConfig,
+}
+
+/// Errors raised while mocking the state tree of a transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum MockError {
+    #[error("actor bundle has no code for actor type {0:?}")]
+    MissingActorCode(Type),
+    #[error("actor {0} not found in the state tree")]
+    ActorNotFound(Address),
+    #[error("state of actor {0} not found in the blockstore")]
+    StateNotFound(Address),
+    #[error("address {0} isn't assigned to an actor")]
+    UnresolvedAddress(Address),
+    #[error("address {0} is already assigned to an actor")]
+    AddressInUse(Address),
+    #[error("bytecode {0} not found in the blockstore")]
+    BytecodeNotFound(Cid),
+    #[error("actors HAMT: {0}")]
+    Hamt(#[from] fvm_ipld_hamt::Error),
+    #[error("storage KAMT: {0}")]
+    Kamt(#[from] fvm_ipld_kamt::Error),
+    #[error(transparent)]
+    Actor(#[from] ActorError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
 impl<'bs, BS> Mock<'bs, BS>
@@ -103,139 +164,171 @@ where
     BS: Blockstore,
 {
     pub fn new(store: &'bs BS, actor_codes: BTreeMap<Type, Cid>) -> Self {
-        let mut actors = Hamt::<&BS, Actor>::new_with_bit_width(store, HAMT_BIT_WIDTH);
-        let actors_cid = actors.flush().unwrap();
+        Self::new_with_config(store, actor_codes, MockConfig::default())
+    }
+
+    pub fn new_with_config(
+        store: &'bs BS,
+        actor_codes: BTreeMap<Type, Cid>,
+        config: MockConfig,
+    ) -> Self {
         Self {
             store,
-            actors: RefCell::new(actors_cid),
+            actors: RefCell::new(Hamt::new_with_bit_width(store, HAMT_BIT_WIDTH)),
+            contracts: Default::default(),
             actor_codes,
+            reserved_ids: BTreeSet::new(),
+            config,
+        }
+    }
+
+    pub fn reserve_actor_ids(
+        &mut self,
+        ids: impl IntoIterator<Item = ActorID>,
+    ) -> Result<(), MockError> {
+        self.reserved_ids.extend(ids);
+        // a snapshot must not leave the Init actor about to hand out a reserved ID
+        if self.find_actor(INIT_ACTOR_ADDR)?.is_some() {
+            let reserved_ids = self.reserved_ids.clone();
+            self.mutate_state(INIT_ACTOR_ADDR, |st: &mut InitState| {
+                skip_reserved_ids(st, &reserved_ids);
+                Ok(())
+            })?;
         }
+        Ok(())
     }
 
-    pub fn mock_builtin_actor(&mut self) -> () {
+    pub fn mock_builtin_actor(&mut self) -> Result<(), MockError> {
         // system
-        let sys_st = SystemState::new(self.store).unwrap();
-        let head_cid = self
-            .store
-            .put_cbor(&sys_st, multihash::Code::Blake2b256)
-            .unwrap();
+        let sys_st = SystemState::new(self.store)?;
+        let head_cid = self.put_store(&sys_st)?;
         let faucet_total = TokenAmount::from_whole(1_000_000_000i64);
         self.set_actor(
             SYSTEM_ACTOR_ADDR,
             actor(
-                self.get_actor_code(Type::System),
+                self.get_actor_code(Type::System)?,
                 head_cid,
                 0,
                 faucet_total,
                 None,
             ),
-        );
+        )?;
 
         //init
-        let init_st = InitState::new(self.store, "integration-test".to_string()).unwrap();
-        let head_cid = self
-            .store
-            .put_cbor(&init_st, multihash::Code::Blake2b256)
-            .unwrap();
+        let mut init_st = InitState::new(self.store, "integration-test".to_string())?;
+        skip_reserved_ids(&mut init_st, &self.reserved_ids);
+        let head_cid = self.put_store(&init_st)?;
         let faucet_total = TokenAmount::from_whole(1_000_000_000i64);
         self.set_actor(
             INIT_ACTOR_ADDR,
             actor(
-                self.get_actor_code(Type::Init),
+                self.get_actor_code(Type::Init)?,
                 head_cid,
                 0,
                 faucet_total,
                 None,
             ),
-        );
+        )?;
 
         // reward
         let reward_total = TokenAmount::from_whole(1_100_000_000i64);
-        let reward_head = self.put_store(&RewardState::new(StoragePower::zero()));
+        let reward_head = self.put_store(&RewardState::new(StoragePower::zero()))?;
         self.set_actor(
             REWARD_ACTOR_ADDR,
             actor(
-                self.get_actor_code(Type::Reward),
+                self.get_actor_code(Type::Reward)?,
                 reward_head,
                 0,
                 reward_total,
                 None,
             ),
-        );
+        )?;
 
         // Ethereum Address Manager
         self.set_actor(
             EAM_ACTOR_ADDR,
             actor(
-                self.get_actor_code(Type::EAM),
+                self.get_actor_code(Type::EAM)?,
                 EMPTY_ARR_CID,
                 0,
                 TokenAmount::zero(),
                 None,
             ),
-        );
+        )?;
 
         // burnt funds
         let burnt_funds_head = self.put_store(&AccountState {
             address: BURNT_FUNDS_ACTOR_ADDR,
-        });
+        })?;
         self.set_actor(
             BURNT_FUNDS_ACTOR_ADDR,
             actor(
-                self.get_actor_code(Type::Account),
+                self.get_actor_code(Type::Account)?,
                 burnt_funds_head,
                 0,
                 TokenAmount::zero(),
                 None,
             ),
-        );
+        )?;
+        Ok(())
     }
 
-    pub fn mock_embryo_address_actor(&mut self, addr: Address, balance: TokenAmount, nonce: u64) {
-        let mut id_addr = Address::new_id(0);
-        self.mutate_state(INIT_ACTOR_ADDR, |st: &mut InitState| {
-            let (addr_id, exist) = st.map_addresses_to_id(self.store, &addr, None).unwrap();
-            assert!(
-                !exist,
-                "should never have existing actor when no f4 address is specified"
-            );
-            id_addr = Address::new_id(addr_id);
-        });
+    pub fn mock_embryo_address_actor(
+        &mut self,
+        addr: Address,
+        balance: TokenAmount,
+        nonce: u64,
+    ) -> Result<(), MockError> {
+        let code = self.get_actor_code(Type::Placeholder)?;
+        let reserved_ids = self.reserved_ids.clone();
+        let id_addr = self.mutate_state(INIT_ACTOR_ADDR, |st: &mut InitState| {
+            let (addr_id, exist) = st.map_addresses_to_id(self.store, &addr, None)?;
+            if exist {
+                return Err(MockError::AddressInUse(addr));
+            }
+            skip_reserved_ids(st, &reserved_ids);
+            Ok(Address::new_id(addr_id))
+        })?;
         self.set_actor(
             id_addr,
-            actor(
-                self.get_actor_code(Type::Placeholder),
-                EMPTY_ARR_CID,
-                nonce,
-                balance,
-                Some(addr),
-            ),
-        );
+            actor(code, EMPTY_ARR_CID, nonce, balance, Some(addr)),
+        )
     }
 
-    pub fn mock_evm_actor(&mut self, addr: Address, balance: TokenAmount, nonce: u64) {
-        let mut id_addr = Address::new_id(0);
-        let robust_address = Address::new_actor(&addr.to_bytes());
-        self.mutate_state(INIT_ACTOR_ADDR, |st: &mut InitState| {
-            let (addr_id, exist) = st
-                .map_addresses_to_id(self.store, &robust_address, Some(&addr))
-                .unwrap();
-            assert!(
-                !exist,
-                "should never have existing actor when no f4 address is specified"
-            );
-            id_addr = Address::new_id(addr_id);
-        });
+    /// Mock an EVM actor the way the EAM and Init actors create it, the Ethereum nonce
+    /// lives in the EVM state so the actor sequence is zero.
+    pub fn mock_evm_actor(
+        &mut self,
+        addr: Address,
+        robust_address: Address,
+        balance: TokenAmount,
+    ) -> Result<(), MockError> {
+        let code = self.get_actor_code(Type::EVM)?;
+        let reserved_ids = self.reserved_ids.clone();
+        let id_addr = self.mutate_state(INIT_ACTOR_ADDR, |st: &mut InitState| {
+            let (addr_id, exist) =
+                st.map_addresses_to_id(self.store, &robust_address, Some(&addr))?;
+            if exist {
+                return Err(MockError::AddressInUse(addr));
+            }
+            skip_reserved_ids(st, &reserved_ids);
+            Ok(Address::new_id(addr_id))
+        })?;
+        self.set_actor(id_addr, actor(code, EMPTY_ARR_CID, 0, balance, Some(addr)))
+    }
+
+    /// Mock the EVM actor an Ethereum masked ID address refers to, it has no delegated
+    /// address so FEVM sees it as the masked ID address.
+    pub fn mock_evm_actor_with_id(
+        &mut self,
+        id: ActorID,
+        balance: TokenAmount,
+    ) -> Result<(), MockError> {
+        let code = self.get_actor_code(Type::EVM)?;
         self.set_actor(
-            id_addr,
-            actor(
-                self.get_actor_code(Type::EVM),
-                EMPTY_ARR_CID,
-                nonce,
-                balance,
-                Some(addr),
-            ),
-        );
+            Address::new_id(id),
+            actor(code, EMPTY_ARR_CID, 0, balance, None),
+        )
     }
 
     pub fn hash(&self, hasher: SupportedHashes, data: &[u8]) -> Vec<u8> {
@@ -249,132 +342,118 @@ where
         addr: &Address,
         storage: HashMap<U256, U256>,
         bytecode: Option<Vec<u8>>,
-    ) -> anyhow::Result<()> {
-        if storage.len() == 0 && bytecode.is_none() {
+        nonce: Option<u64>,
+    ) -> Result<(), MockError> {
+        if storage.len() == 0 && bytecode.is_none() && nonce.is_none() {
             return Ok(());
         }
-        let addr = self
-            .normalize_address(addr)
-            .expect("failed to normalize address");
-        let head = self.get_actor(addr).unwrap().head;
-        let (mut slots, bytecode_cid, bytecode_hash, nonce) =
-            match self.store.get_cbor::<EvmState>(&head) {
-                Ok(res) => match res {
+        let addr = self.normalize_address(addr)?;
+
+        let generate = |bytecode: Vec<u8>| -> Result<([u8; 32], Cid), MockError> {
+            let code_hash: [u8; 32] = self
+                .hash(SupportedHashes::Keccak256, &bytecode)
+                .try_into()
+                .expect("keccak256 digest is 32 bytes");
+            let bytecode_cid = self
+                .store
+                .put(Code::Blake2b256, &Block::new(IPLD_RAW, bytecode))?;
+            Ok((code_hash, bytecode_cid))
+        };
+
+        let mut contracts = self.contracts.borrow_mut();
+        let contract = match contracts.entry(addr) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let head = self.get_actor(addr)?.head;
+                let contract = match EvmState::load(self.store, &head)? {
                     Some(state) => {
                         let slots = StateKamt::load_with_config(
                             &state.contract_state,
                             self.store,
-                            KAMT_CONFIG.clone(),
-                        )
-                        .context_code(ExitCode::USR_ILLEGAL_STATE, "state not in blockstore")
-                        .unwrap();
-                        (
+                            self.config.kamt.clone(),
+                        )?;
+                        // states are rewritten with the layout of the target bundle
+                        let dirty = state.version != self.config.evm_state_version;
+                        LiveContract {
+                            state,
                             slots,
-                            Some(state.bytecode),
-                            Some(state.bytecode_hash),
-                            state.nonce,
-                        )
+                            dirty,
+                        }
                     }
                     None => {
-                        let slots = StateKamt::new_with_config(self.store, KAMT_CONFIG.clone());
-                        (slots, None, None, 1)
+                        let slots =
+                            StateKamt::new_with_config(self.store, self.config.kamt.clone());
+                        let (bytecode_hash, bytecode) = generate(Vec::new())?;
+                        LiveContract {
+                            state: EvmState::new(
+                                self.config.evm_state_version,
+                                bytecode,
+                                bytecode_hash,
+                                EMPTY_ARR_CID,
+                                1,
+                            ),
+                            slots,
+                            // the actor head isn't an EVM state yet
+                            dirty: true,
+                        }
                     }
-                },
-                Err(_) => {
-                    let slots = StateKamt::new_with_config(self.store, KAMT_CONFIG.clone());
-                    (slots, None, None, 1)
-                }
-            };
-        let mut unchanged = true;
+                };
+                entry.insert(contract)
+            }
+        };
+        contract.state.version = self.config.evm_state_version;
+
+        // the EVM state nonce is the Ethereum account nonce
+        if let Some(nonce) = nonce {
+            if nonce != contract.state.nonce {
+                contract.state.nonce = nonce;
+                contract.dirty = true;
+            }
+        }
 
         for (key, value) in storage {
             let changed = if value.is_zero() {
-                slots.delete(&key).map(|v| v.is_some())
+                contract.slots.delete(&key)?.is_some()
             } else {
-                slots.set(key, value).map(|v| v != Some(value))
-            }
-            .map_err(|e| StatusCode::InternalError(e.to_string()))
-            .unwrap();
+                contract.slots.set(key, value)? != Some(value)
+            };
             if changed {
-                unchanged = false;
+                contract.dirty = true;
             }
         }
 
-        let generate = |bytecode: Vec<u8>| -> (MultihashGeneric<64_usize>, Cid) {
-            let code_hash = multihash::Multihash::wrap(
-                SupportedHashes::Keccak256 as u64,
-                &self.hash(SupportedHashes::Keccak256, &bytecode),
-            )
-            .context_code(
-                ExitCode::USR_ILLEGAL_STATE,
-                "failed to hash bytecode with keccak",
-            )
-            .unwrap();
-            let bytecode_cid = self
+        if let Some(bytecode) = bytecode {
+            let old_bytecode = self
                 .store
-                .put(Code::Blake2b256, &Block::new(IPLD_RAW, bytecode))
-                .context_code(ExitCode::USR_ILLEGAL_STATE, "failed to write bytecode")
-                .unwrap();
-            (code_hash, bytecode_cid)
-        };
-
-        let (bytecode_hash, bytecode) = if let Some(bytecode_cid) = bytecode_cid {
-            if let Some(bytecode) = bytecode {
-                let old_bytecode = self
-                    .store
-                    .get(&bytecode_cid)
-                    .context_code(ExitCode::USR_NOT_FOUND, "failed to read bytecode")
-                    .unwrap()
-                    .expect("bytecode not in state tree");
-                if bytecode.eq(&old_bytecode) {
-                    (bytecode_hash.unwrap(), bytecode_cid)
-                } else {
-                    unchanged = false;
-                    generate(bytecode)
-                }
-            } else {
-                (bytecode_hash.unwrap(), bytecode_cid)
+                .get(&contract.state.bytecode)?
+                .ok_or(MockError::BytecodeNotFound(contract.state.bytecode))?;
+            if !bytecode.eq(&old_bytecode) {
+                let (bytecode_hash, bytecode) = generate(bytecode)?;
+                contract.state.bytecode_hash = bytecode_hash;
+                contract.state.bytecode = bytecode;
+                contract.dirty = true;
             }
-        } else {
-            let bytecode = if let Some(bytecode) = bytecode {
-                unchanged = false;
-                bytecode
-            } else {
-                vec![0u8; 0]
-            };
-            generate(bytecode)
-        };
-
-        if unchanged {
-            return Ok(());
         }
-        let new_root = self
-            .store
-            .put_cbor(
-                &EvmState {
-                    bytecode,
-                    bytecode_hash,
-                    contract_state: slots
-                        .flush()
-                        .context_code(
-                            ExitCode::USR_ILLEGAL_STATE,
-                            "failed to flush contract state",
-                        )
-                        .unwrap(),
-                    nonce,
-                },
-                Code::Blake2b256,
-            )
-            .context_code(
-                ExitCode::USR_ILLEGAL_STATE,
-                "failed to write contract state",
-            )
-            .unwrap();
-
-        let mut a = self.get_actor(addr).unwrap();
-        a.head = new_root;
-        self.set_actor(addr, a);
+        Ok(())
+    }
 
+    /// Write the EVM states changed since the last flush and point their actors to them.
+    fn flush_contracts(&self) -> Result<(), MockError> {
+        let mut contracts = self.contracts.borrow_mut();
+        let mut actors = self.actors.borrow_mut();
+        for (addr, contract) in contracts.iter_mut() {
+            if !contract.dirty {
+                continue;
+            }
+            contract.state.contract_state = contract.slots.flush()?;
+            let mut a = actors
+                .get(&addr.to_bytes())?
+                .cloned()
+                .ok_or(MockError::ActorNotFound(*addr))?;
+            a.head = contract.state.store(self.store)?;
+            actors.set(addr.to_bytes().into(), a)?;
+            contract.dirty = false;
+        }
         Ok(())
     }
 
@@ -383,11 +462,9 @@ where
         addr: &Address,
         balance: TokenAmount,
         nonce: Option<u64>,
-    ) -> anyhow::Result<()> {
-        let addr = self
-            .normalize_address(addr)
-            .expect("failed to normalize address");
-        let mut a = self.get_actor(addr).unwrap();
+    ) -> Result<(), MockError> {
+        let addr = self.normalize_address(addr)?;
+        let mut a = self.get_actor(addr)?;
         let mut unchanged = true;
         if !a.balance.eq(&balance) {
             a.balance = balance;
@@ -400,72 +477,74 @@ where
             }
         }
         if !unchanged {
-            self.set_actor(addr, a);
+            self.set_actor(addr, a)?;
         }
         Ok(())
     }
 
-    pub fn get_actors(&self) -> Cid {
-        let cid: &Cid = &self.actors.borrow();
-        cid.clone()
+    /// Flush the pending changes and return the root of the actors HAMT.
+    pub fn get_actors(&self) -> Result<Cid, MockError> {
+        self.flush_contracts()?;
+        Ok(self.actors.borrow_mut().flush()?)
     }
 
-    pub fn put_store<S>(&self, obj: &S) -> Cid
+    pub fn put_store<S>(&self, obj: &S) -> Result<Cid, MockError>
     where
         S: serde::ser::Serialize,
     {
-        self.store.put_cbor(obj, Code::Blake2b256).unwrap()
+        Ok(self.store.put_cbor(obj, Code::Blake2b256)?)
     }
 
-    pub fn get_state<T: DeserializeOwned>(&self, addr: Address) -> Option<T> {
-        let a_opt = self.get_actor(addr);
-        if a_opt == None {
-            return None;
-        };
-        let a = a_opt.unwrap();
-        self.store.get_cbor::<T>(&a.head).unwrap()
+    pub fn get_state<T: DeserializeOwned>(&self, addr: Address) -> Result<T, MockError> {
+        let a = self.get_actor(addr)?;
+        self.store
+            .get_cbor::<T>(&a.head)?
+            .ok_or(MockError::StateNotFound(addr))
     }
 
-    pub fn set_actor(&mut self, actor_addr: Address, actor: Actor) -> () {
-        let mut actors = Hamt::<&BS, Actor>::load_with_bit_width(
-            &self.actors.borrow(),
-            self.store,
-            HAMT_BIT_WIDTH,
-        )
-        .unwrap();
-        actors.set(actor_addr.to_bytes().into(), actor).unwrap();
-        self.actors.replace(actors.flush().unwrap());
+    pub fn set_actor(&mut self, actor_addr: Address, actor: Actor) -> Result<(), MockError> {
+        self.actors
+            .borrow_mut()
+            .set(actor_addr.to_bytes().into(), actor)?;
+        Ok(())
     }
 
-    pub fn get_actor(&self, addr: Address) -> Option<Actor> {
-        let actors = Hamt::<&BS, Actor>::load_with_bit_width(
-            &self.actors.borrow(),
-            self.store,
-            HAMT_BIT_WIDTH,
-        )
-        .unwrap();
-        actors.get(&addr.to_bytes()).unwrap().cloned()
+    /// The head of an EVM actor with pending state changes is only updated by `get_actors`.
+    pub fn get_actor(&self, addr: Address) -> Result<Actor, MockError> {
+        self.find_actor(addr)?.ok_or(MockError::ActorNotFound(addr))
+    }
+
+    pub fn find_actor(&self, addr: Address) -> Result<Option<Actor>, MockError> {
+        Ok(self.actors.borrow().get(&addr.to_bytes())?.cloned())
     }
 
-    pub fn normalize_address(&self, addr: &Address) -> Option<Address> {
-        let st = self.get_state::<InitState>(INIT_ACTOR_ADDR).unwrap();
-        st.resolve_address::<BS>(self.store, addr).unwrap()
+    pub fn normalize_address(&self, addr: &Address) -> Result<Address, MockError> {
+        let st = self.get_state::<InitState>(INIT_ACTOR_ADDR)?;
+        st.resolve_address::<BS>(self.store, addr)?
+            .ok_or(MockError::UnresolvedAddress(*addr))
     }
 
-    pub fn mutate_state<S, F>(&mut self, addr: Address, f: F)
+    pub fn mutate_state<S, F, R>(&mut self, addr: Address, f: F) -> Result<R, MockError>
     where
         S: Serialize + DeserializeOwned,
-        F: FnOnce(&mut S),
+        F: FnOnce(&mut S) -> Result<R, MockError>,
     {
-        let mut a = self.get_actor(addr).unwrap();
-        let mut st = self.store.get_cbor::<S>(&a.head).unwrap().unwrap();
-        f(&mut st);
-        a.head = self.store.put_cbor(&st, Code::Blake2b256).unwrap();
-        self.set_actor(addr, a);
+        let mut a = self.get_actor(addr)?;
+        let mut st = self
+            .store
+            .get_cbor::<S>(&a.head)?
+            .ok_or(MockError::StateNotFound(addr))?;
+        let ret = f(&mut st)?;
+        a.head = self.put_store(&st)?;
+        self.set_actor(addr, a)?;
+        Ok(ret)
     }
 
-    pub fn get_actor_code(&self, actor_type: Type) -> Cid {
-        self.actor_codes.get(&actor_type).unwrap().clone()
+    pub fn get_actor_code(&self, actor_type: Type) -> Result<Cid, MockError> {
+        self.actor_codes
+            .get(&actor_type)
+            .cloned()
+            .ok_or(MockError::MissingActorCode(actor_type))
     }
 
     pub fn print_evm_actors(&self, identifier: impl Display, actors: Cid) -> anyhow::Result<()> {
@@ -474,17 +553,14 @@ where
         actors.for_each(|_, v| {
             let head = v.head;
             let store = self.store.clone();
-            match store.get_cbor::<EvmState>(&head) {
+            match EvmState::load(store, &head) {
                 Ok(res) => match res {
                     Some(state) => {
                         if v.predictable_address.is_some() {
                             let receiver_eth_addr =
                                 address_to_eth(&v.predictable_address.unwrap())?;
-                            let addr =
-                                Address::new_delegated(EAM_ACTOR_ID, &receiver_eth_addr.0).unwrap();
-                            let addr = self
-                                .normalize_address(&addr)
-                                .expect("failed to normalize address");
+                            let addr = Address::new_delegated(EAM_ACTOR_ID, &receiver_eth_addr.0)?;
+                            let addr = self.normalize_address(&addr)?;
                             log::info!(
                                 "--- actor_id:{} actor_address:{} eth_addr:{} ---",
                                 addr,
@@ -495,14 +571,13 @@ where
                         log::info!("actor: {:?}", v);
                         log::info!("state: {:?}", &state);
                         let bytecode = store
-                            .get(&state.bytecode)
-                            .context_code(ExitCode::USR_NOT_FOUND, "failed to read bytecode")?
-                            .expect("bytecode not in state tree");
+                            .get(&state.bytecode)?
+                            .ok_or(MockError::BytecodeNotFound(state.bytecode))?;
                         log::debug!("bytecode: {:?}", hex::encode(bytecode));
                         let slots = StateKamt::load_with_config(
                             &state.contract_state,
                             store,
-                            KAMT_CONFIG.clone(),
+                            self.config.kamt.clone(),
                         )
                         .context_code(ExitCode::USR_ILLEGAL_STATE, "state not in blockstore")?;
                         if !slots.is_empty() {
@@ -528,6 +603,20 @@ where
     }
 }
 
+/// Robust address of an actor which wasn't created by a known message, derived from
+/// its delegated address so it's stable across vectors.
+pub fn default_robust_address(addr: &Address) -> Address {
+    Address::new_actor(&addr.to_bytes())
+}
+
+/// Advance the next ID of the Init actor past reserved IDs, it's kept off them after each
+/// allocation so FVM never hands one out either.
+fn skip_reserved_ids(st: &mut InitState, reserved_ids: &BTreeSet<ActorID>) {
+    while reserved_ids.contains(&st.next_id) {
+        st.next_id += 1;
+    }
+}
+
 pub fn address_to_eth(addr: &Address) -> anyhow::Result<EthAddress> {
     let delegated_addr = match addr.payload() {
         Payload::Delegated(delegated) if delegated.namespace() == EAM_ACTOR_ID => {
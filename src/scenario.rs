@@ -0,0 +1,583 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fmt, fs};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use ethers::abi::{self, AbiParser, ParamType, Token};
+use ethers::types::{Bytes, H160, H256, U256};
+use ethers::utils::{keccak256, parse_units};
+use fil_actor_eam::EthAddress;
+use serde::{Deserialize, Serialize};
+
+use crate::extractor::evm::EvmBlock;
+use crate::extractor::types::{EthAccountState, EthState, EthTransactionTestVector};
+use crate::util::{compute_address_create, compute_address_create2};
+
+/// Labels can refer to each other, this bounds the chain so a cycle fails.
+const MAX_LABEL_DEPTH: usize = 32;
+
+/// A word written as a number, or as a string evaluated by [`Resolver::eval`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Expr {
+    Number(u64),
+    Text(String),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Account of the prestate, or fields of an account expected in the poststate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSpec {
+    /// derived from the account name if not set
+    pub address: Option<Expr>,
+    pub balance: Option<Expr>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    /// slot expressions to values
+    pub storage: BTreeMap<Expr, Expr>,
+    /// the account is expected to be gone from the poststate, e.g. self-destructed
+    pub deleted: bool,
+}
+
+/// Function called with ABI encoded arguments, or constructor arguments appended to the
+/// creation code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CallSpec {
+    /// human readable signature, e.g. `transfer(address,uint256)`
+    pub function: String,
+    #[serde(default)]
+    pub args: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct TransactionSpec {
+    pub from: Expr,
+    /// `None` to create a contract from `input`
+    #[serde(default)]
+    pub to: Option<Expr>,
+    #[serde(default)]
+    pub value: Option<Expr>,
+    /// raw calldata or creation code
+    #[serde(default)]
+    pub input: Bytes,
+    #[serde(default)]
+    pub call: Option<CallSpec>,
+    pub gas: Expr,
+    #[serde(default)]
+    pub gas_price: Option<Expr>,
+    #[serde(default)]
+    pub max_fee_per_gas: Option<Expr>,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct BlockSpec {
+    pub number: Option<u64>,
+    pub coinbase: Option<Expr>,
+    pub timestamp: Option<Expr>,
+    pub base_fee_per_gas: Option<Expr>,
+    pub difficulty: Option<Expr>,
+    pub random: Option<Expr>,
+    pub chain_id: Option<Expr>,
+    pub block_hashes: BTreeMap<u64, H256>,
+}
+
+/// Outcome of the transaction, the poststate is the prestate with these changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ExpectSpec {
+    #[serde(default = "default_success")]
+    pub success: bool,
+    pub gas_used: Expr,
+    #[serde(default)]
+    pub return_value: Bytes,
+    /// accounts by name, the ones not declared in the prestate need an address
+    #[serde(default)]
+    pub poststate: BTreeMap<String, AccountSpec>,
+}
+
+fn default_success() -> bool {
+    true
+}
+
+/// A hand written transaction and its expected outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// named values, referred to as `$name`
+    #[serde(default)]
+    pub labels: BTreeMap<String, Expr>,
+    /// prestate accounts by name, referred to as `@name`
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AccountSpec>,
+    pub transaction: TransactionSpec,
+    #[serde(default)]
+    pub block: BlockSpec,
+    pub expect: ExpectSpec,
+}
+
+/// Read a YAML, TOML or JSON scenario, according to the extension of `path`.
+pub fn load_scenario(path: &Path) -> anyhow::Result<Scenario> {
+    let text = fs::read_to_string(path)?;
+    let scenario = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(anyhow::Error::from),
+        Some("toml") => toml::from_str(&text).map_err(anyhow::Error::from),
+        Some("json") => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        _ => bail!("{:?} is not a .yaml, .toml or .json scenario", path),
+    };
+    scenario.with_context(|| format!("invalid scenario {:?}", path))
+}
+
+/// Evaluates the expressions of a scenario once its accounts have addresses.
+///
+/// An expression is a decimal or `0x` hex number, an amount such as `1.5 ether`, an
+/// account address `@name`, a label `$name`, or one of:
+/// - `mapping(slot, key)`, the slot of `key` in the mapping at `slot`
+/// - `array(slot, index)`, the slot of `index` in the dynamic array at `slot`
+/// - `create(@deployer, nonce)`, the address of a contract created by CREATE
+/// - `create2(@deployer, salt, initcode_hash)`, the address of a contract created by CREATE2
+pub struct Resolver<'a> {
+    pub addresses: BTreeMap<String, H160>,
+    pub labels: &'a BTreeMap<String, Expr>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn eval(&self, expr: &Expr) -> anyhow::Result<U256> {
+        match expr {
+            Expr::Number(n) => Ok((*n).into()),
+            Expr::Text(s) => self
+                .eval_str(s, 0)
+                .with_context(|| format!("cannot evaluate `{}`", s)),
+        }
+    }
+
+    pub fn eval_address(&self, expr: &Expr) -> anyhow::Result<H160> {
+        let word = self.eval(expr)?;
+        ensure!(word.bits() <= 160, "`{}` is not an address", expr);
+        Ok(word_to_address(word))
+    }
+
+    fn eval_str(&self, s: &str, depth: usize) -> anyhow::Result<U256> {
+        let s = s.trim();
+        if let Some(name) = s.strip_prefix('@') {
+            return self
+                .addresses
+                .get(name)
+                .map(|address| U256::from_big_endian(address.as_bytes()))
+                .ok_or_else(|| anyhow!("unknown account {}", name));
+        }
+        if let Some(name) = s.strip_prefix('$') {
+            ensure!(
+                depth < MAX_LABEL_DEPTH,
+                "labels refer to each other in a cycle"
+            );
+            return match self.labels.get(name) {
+                Some(Expr::Number(n)) => Ok((*n).into()),
+                Some(Expr::Text(text)) => self.eval_str(text, depth + 1),
+                None => Err(anyhow!("unknown label {}", name)),
+            };
+        }
+        if let Some(hex) = s.strip_prefix("0x") {
+            return Ok(U256::from_str_radix(hex, 16)?);
+        }
+        if let Some((function, args)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+            let args = split_args(args)
+                .into_iter()
+                .map(|arg| self.eval_str(arg, depth))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return call_function(function.trim(), &args);
+        }
+        if let Some((amount, unit)) = s.split_once(' ') {
+            return Ok(parse_units(amount, unit.trim())?.into());
+        }
+        Ok(U256::from_dec_str(s)?)
+    }
+
+    /// Token of type `kind` for the call argument `value`, a word expression for the
+    /// scalar types, a list for arrays and tuples.
+    fn token(&self, kind: &ParamType, value: &serde_json::Value) -> anyhow::Result<Token> {
+        use serde_json::Value;
+
+        let word = |value: &Value| -> anyhow::Result<U256> {
+            match value {
+                Value::Number(n) => n
+                    .as_u64()
+                    .map(U256::from)
+                    .ok_or_else(|| anyhow!("{} is not a word", n)),
+                Value::String(s) => self
+                    .eval_str(s, 0)
+                    .with_context(|| format!("cannot evaluate `{}`", s)),
+                _ => bail!("{} is not a word", value),
+            }
+        };
+        let items = |value: &Value| -> anyhow::Result<Vec<Value>> {
+            match value {
+                Value::Array(items) => Ok(items.clone()),
+                _ => bail!("{} is not a list", value),
+            }
+        };
+        Ok(match kind {
+            ParamType::Address => Token::Address(word_to_address(word(value)?)),
+            ParamType::Uint(_) => Token::Uint(word(value)?),
+            ParamType::Int(_) => match value {
+                Value::Number(n) if n.is_i64() => {
+                    let n = n.as_i64().unwrap();
+                    let word = U256::from(n.unsigned_abs());
+                    Token::Int(if n < 0 { !word + 1 } else { word })
+                }
+                _ => Token::Int(word(value)?),
+            },
+            ParamType::Bool => match value {
+                Value::Bool(b) => Token::Bool(*b),
+                _ => bail!("{} is not a bool", value),
+            },
+            ParamType::String => match value {
+                Value::String(s) => Token::String(s.clone()),
+                _ => bail!("{} is not a string", value),
+            },
+            ParamType::Bytes => Token::Bytes(bytes_value(value)?),
+            ParamType::FixedBytes(size) => {
+                let bytes = bytes_value(value)?;
+                ensure!(bytes.len() == *size, "{} is not {} bytes long", value, size);
+                Token::FixedBytes(bytes)
+            }
+            ParamType::Array(kind) => Token::Array(
+                items(value)?
+                    .iter()
+                    .map(|item| self.token(kind, item))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            ParamType::FixedArray(kind, size) => {
+                let items = items(value)?;
+                ensure!(
+                    items.len() == *size,
+                    "{} doesn't have {} items",
+                    value,
+                    size
+                );
+                Token::FixedArray(
+                    items
+                        .iter()
+                        .map(|item| self.token(kind, item))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            ParamType::Tuple(kinds) => {
+                let items = items(value)?;
+                ensure!(
+                    items.len() == kinds.len(),
+                    "{} doesn't match the tuple",
+                    value
+                );
+                Token::Tuple(
+                    kinds
+                        .iter()
+                        .zip(&items)
+                        .map(|(kind, item)| self.token(kind, item))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+        })
+    }
+
+    /// Calldata of `call`, without the selector for constructors.
+    pub fn encode_call(&self, call: &CallSpec, constructor: bool) -> anyhow::Result<Vec<u8>> {
+        let function = AbiParser::default()
+            .parse_function(&call.function)
+            .with_context(|| format!("invalid function `{}`", call.function))?;
+        ensure!(
+            function.inputs.len() == call.args.len(),
+            "`{}` takes {} arguments, {} given",
+            call.function,
+            function.inputs.len(),
+            call.args.len()
+        );
+        let tokens = function
+            .inputs
+            .iter()
+            .zip(&call.args)
+            .map(|(param, arg)| {
+                self.token(&param.kind, arg)
+                    .with_context(|| format!("invalid argument {}", param.name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if constructor {
+            Ok(abi::encode(&tokens))
+        } else {
+            Ok(function.encode_input(&tokens)?)
+        }
+    }
+
+    fn account(
+        &self,
+        spec: &AccountSpec,
+        mut account: EthAccountState,
+    ) -> anyhow::Result<EthAccountState> {
+        if let Some(balance) = &spec.balance {
+            account.balance = self.eval(balance)?;
+        }
+        if let Some(nonce) = spec.nonce {
+            account.nonce = nonce;
+        }
+        if let Some(code) = &spec.code {
+            account.code = code.clone();
+        }
+        for (key, value) in &spec.storage {
+            account.storage.insert(
+                word_to_h256(self.eval(key)?),
+                word_to_h256(self.eval(value)?),
+            );
+        }
+        Ok(account)
+    }
+}
+
+/// Split the arguments of a function call at the commas outside nested calls.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if !args[start..].trim().is_empty() {
+        parts.push(&args[start..]);
+    }
+    parts
+}
+
+fn call_function(function: &str, args: &[U256]) -> anyhow::Result<U256> {
+    let arity = match function {
+        "mapping" | "array" | "create" => 2,
+        "create2" => 3,
+        _ => bail!("unknown function {}", function),
+    };
+    ensure!(
+        args.len() == arity,
+        "{} takes {} arguments, {} given",
+        function,
+        arity,
+        args.len()
+    );
+    Ok(match function {
+        "mapping" => {
+            let mut preimage = [0; 64];
+            args[1].to_big_endian(&mut preimage[..32]);
+            args[0].to_big_endian(&mut preimage[32..]);
+            U256::from_big_endian(&keccak256(preimage))
+        }
+        "array" => {
+            let start = U256::from_big_endian(&keccak256(word_to_h256(args[0])));
+            start.overflowing_add(args[1]).0
+        }
+        "create" => {
+            ensure!(args[1].bits() <= 64, "nonce {} too large", args[1]);
+            let deployer = EthAddress(word_to_address(args[0]).0);
+            let address = compute_address_create(&deployer, args[1].as_u64());
+            U256::from_big_endian(&address.0)
+        }
+        _ => {
+            let deployer = EthAddress(word_to_address(args[0]).0);
+            let address = compute_address_create2(
+                &deployer,
+                &word_to_h256(args[1]).0,
+                &word_to_h256(args[2]).0,
+            );
+            U256::from_big_endian(&address.0)
+        }
+    })
+}
+
+fn bytes_value(value: &serde_json::Value) -> anyhow::Result<Vec<u8>> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.parse::<Bytes>()?.to_vec()),
+        _ => bail!("{} is not hex bytes", value),
+    }
+}
+
+fn word_to_address(word: U256) -> H160 {
+    H160::from_slice(&word_to_h256(word).as_bytes()[12..])
+}
+
+fn word_to_h256(word: U256) -> H256 {
+    let mut bytes = [0; 32];
+    word.to_big_endian(&mut bytes);
+    H256(bytes)
+}
+
+/// Address of the accounts without an explicit one.
+fn default_address(name: &str) -> H160 {
+    H160::from_slice(&keccak256(name.as_bytes())[12..])
+}
+
+/// Give every account of the scenario an address, the accounts whose address refers to
+/// others are resolved once those are.
+fn resolve_addresses(scenario: &Scenario) -> anyhow::Result<Resolver<'_>> {
+    let mut resolver = Resolver {
+        addresses: BTreeMap::new(),
+        labels: &scenario.labels,
+    };
+    let mut pending: Vec<(&String, &Expr)> = Vec::new();
+    let accounts = scenario.accounts.iter().chain(
+        scenario
+            .expect
+            .poststate
+            .iter()
+            .filter(|(name, _)| !scenario.accounts.contains_key(*name)),
+    );
+    for (name, spec) in accounts {
+        match &spec.address {
+            Some(address) => pending.push((name, address)),
+            None => {
+                resolver
+                    .addresses
+                    .insert(name.clone(), default_address(name));
+            }
+        }
+    }
+    while !pending.is_empty() {
+        let mut unresolved = Vec::new();
+        let mut error = None;
+        for (name, address) in pending.iter().copied() {
+            match resolver.eval_address(address) {
+                Ok(address) => {
+                    resolver.addresses.insert(name.clone(), address);
+                }
+                Err(e) => {
+                    error = Some(e.context(format!("cannot resolve the address of {}", name)));
+                    unresolved.push((name, address));
+                }
+            }
+        }
+        if unresolved.len() == pending.len() {
+            return Err(error.unwrap());
+        }
+        pending = unresolved;
+    }
+    Ok(resolver)
+}
+
+/// Compile `scenario` into the test vector of its transaction, without executing it.
+pub fn compile_scenario(scenario: &Scenario) -> anyhow::Result<EthTransactionTestVector> {
+    let resolver = resolve_addresses(scenario)?;
+    let eval_or = |expr: &Option<Expr>, default: U256| -> anyhow::Result<U256> {
+        expr.as_ref()
+            .map_or(Ok(default), |expr| resolver.eval(expr))
+    };
+
+    let mut prestate = EthState::new();
+    for (name, spec) in &scenario.accounts {
+        ensure!(
+            !spec.deleted,
+            "{} can only be deleted from the poststate",
+            name
+        );
+        let account = resolver
+            .account(spec, EthAccountState::default())
+            .with_context(|| format!("invalid account {}", name))?;
+        prestate.insert(resolver.addresses[name], account);
+    }
+    let mut poststate = prestate.clone();
+    for (name, spec) in &scenario.expect.poststate {
+        let address = resolver.addresses[name];
+        if spec.deleted {
+            poststate.remove(&address);
+            continue;
+        }
+        let account = poststate.remove(&address).unwrap_or_default();
+        let account = resolver
+            .account(spec, account)
+            .with_context(|| format!("invalid expected account {}", name))?;
+        poststate.insert(address, account);
+    }
+
+    let tx = &scenario.transaction;
+    let from = resolver.eval_address(&tx.from)?;
+    let nonce = prestate
+        .get(&from)
+        .with_context(|| format!("sender {:?} is not in the prestate", from))?
+        .nonce;
+    let to = tx
+        .to
+        .as_ref()
+        .map(|to| resolver.eval_address(to))
+        .transpose()?;
+    let mut input = tx.input.to_vec();
+    if let Some(call) = &tx.call {
+        let encoded = resolver.encode_call(call, to.is_none())?;
+        if to.is_some() {
+            ensure!(input.is_empty(), "calldata given both as input and call");
+            input = encoded;
+        } else {
+            // constructor arguments follow the creation code
+            input.extend(encoded);
+        }
+    }
+
+    let defaults = EvmBlock::default();
+    let block = &scenario.block;
+    let mut vector = EthTransactionTestVector {
+        nonce,
+        from,
+        to: to.unwrap_or_default(),
+        value: eval_or(&tx.value, U256::zero())?,
+        input: input.into(),
+        gas: resolver.eval(&tx.gas)?,
+        gas_price: eval_or(&tx.gas_price, U256::zero())?,
+        max_priority_fee_per_gas: tx
+            .max_priority_fee_per_gas
+            .as_ref()
+            .map(|expr| resolver.eval(expr))
+            .transpose()?,
+        max_fee_per_gas: tx
+            .max_fee_per_gas
+            .as_ref()
+            .map(|expr| resolver.eval(expr))
+            .transpose()?,
+        status: scenario.expect.success as u64,
+        gas_used: resolver.eval(&scenario.expect.gas_used)?,
+        return_value: scenario.expect.return_value.clone(),
+        coinbase: block
+            .coinbase
+            .as_ref()
+            .map(|expr| resolver.eval_address(expr))
+            .transpose()?
+            .unwrap_or(defaults.coinbase),
+        base_fee_per_gas: block
+            .base_fee_per_gas
+            .as_ref()
+            .map(|expr| resolver.eval(expr))
+            .transpose()?,
+        difficultly: eval_or(&block.difficulty, defaults.difficulty)?,
+        random: eval_or(&block.random, defaults.random)?,
+        chain_id: eval_or(&block.chain_id, defaults.chain_id)?,
+        block_number: block.number.unwrap_or(defaults.number),
+        block_hashes: block.block_hashes.clone(),
+        timestamp: eval_or(&block.timestamp, defaults.timestamp)?,
+        prestate,
+        poststate,
+        ..Default::default()
+    };
+    vector.hash = H256(keccak256(serde_json::to_vec(&vector)?));
+    Ok(vector)
+}
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ethers::types::{H160, H256};
+use fil_actor_evm::interpreter::system::StateKamt;
+use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
+use fvm_ipld_kamt::Config as KamtConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::extractor::precompiles::is_precompile;
+use crate::extractor::types::EthTransactionTestVector;
+use crate::tracing_blockstore::TracingBlockStore;
+use crate::util::hex_to_u256;
+
+/// Shape of a contract storage KAMT.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KamtStats {
+    pub entries: usize,
+    /// number of nodes loaded to read the deepest slot
+    pub depth: usize,
+    pub nodes: usize,
+    /// serialized size of all the nodes, in bytes
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContractStorageStats {
+    pub address: H160,
+    pub pre: KamtStats,
+    pub post: KamtStats,
+}
+
+/// Build the storage KAMT of `storage` with `config` and measure it.
+pub fn kamt_stats(
+    storage: &BTreeMap<H256, H256>,
+    config: &KamtConfig,
+) -> anyhow::Result<KamtStats> {
+    let store = TracingBlockStore::new(MemoryBlockstore::new());
+    let mut slots = StateKamt::new_with_config(&store, config.clone());
+    let mut keys = Vec::new();
+    for (k, v) in storage {
+        if v.is_zero() {
+            continue;
+        }
+        let key = hex_to_u256(&hex::encode(k.0));
+        slots.set(key, hex_to_u256(&hex::encode(v.0)))?;
+        keys.push(key);
+    }
+    let root = slots.flush()?;
+
+    // the store is fresh, so the traced blocks are the nodes of the KAMT
+    let nodes = store.traced.replace(Default::default());
+    let mut size = 0;
+    for cid in &nodes {
+        size += store.base.get(cid)?.map(|block| block.len()).unwrap_or(0);
+    }
+
+    let mut depth = 0;
+    for key in &keys {
+        let slots = StateKamt::load_with_config(&root, &store, config.clone())?;
+        slots.get(key)?;
+        depth = depth.max(store.traced.replace(Default::default()).len());
+    }
+
+    Ok(KamtStats {
+        entries: keys.len(),
+        depth,
+        nodes: nodes.len(),
+        size,
+    })
+}
+
+/// Measure the pre and post storage KAMTs of each contract of `input`, the poststate
+/// slots are applied on top of the prestate ones like when the state is mocked.
+pub fn storage_layout_stats(
+    input: &EthTransactionTestVector,
+    config: &KamtConfig,
+) -> anyhow::Result<Vec<ContractStorageStats>> {
+    let addresses: BTreeSet<&H160> = input
+        .prestate
+        .keys()
+        .chain(input.poststate.keys())
+        .collect();
+    let mut stats = Vec::new();
+    for address in addresses {
+        let pre = input.prestate.get(address);
+        let post = input.poststate.get(address);
+        let is_contract = pre.into_iter().chain(post).any(|st| !st.code.is_empty());
+        if is_precompile(address) || !is_contract {
+            continue;
+        }
+        let pre_storage = pre.map(|st| st.storage.clone()).unwrap_or_default();
+        let mut post_storage = pre_storage.clone();
+        if let Some(post) = post {
+            post_storage.extend(post.storage.clone());
+        }
+        stats.push(ContractStorageStats {
+            address: *address,
+            pre: kamt_stats(&pre_storage, config)?,
+            post: kamt_stats(&post_storage, config)?,
+        });
+    }
+    Ok(stats)
+}
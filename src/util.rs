@@ -1,21 +1,43 @@
 use std::collections::BTreeMap;
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
 use async_std::task::block_on;
 use fil_actors_runtime::runtime::builtins::Type;
 use fil_actors_runtime::test_utils::ACTOR_CODES;
 use fvm_ipld_car::load_car_unchecked;
 use fvm_ipld_encoding::CborStore;
 use fvm_shared::version::NetworkVersion;
+use fvm_shared::ActorID;
 use num_traits::FromPrimitive;
 
+use crate::evm_state::StateVersion;
 use crate::*;
 
+/// Layout of the EVM actor states of the bundle loaded by `get_code_cid_map`.
+pub const BUNDLE_EVM_STATE_VERSION: StateVersion = StateVersion::V10;
+
+lazy_static::lazy_static! {
+    // the bundle is parsed once and shared by every generation, the error is kept as a
+    // string so it can be returned to each caller.
+    static ref CODE_CID_MAP: Result<BTreeMap<Type, Cid>, String> =
+        load_code_cid_map().map_err(|e| format!("{:#}", e));
+}
+
+/// Code CIDs of the actors of the bundle, parsed on first use.
 pub fn get_code_cid_map() -> anyhow::Result<BTreeMap<Type, Cid>> {
+    CODE_CID_MAP.clone().map_err(|e| anyhow!(e))
+}
+
+fn load_code_cid_map() -> anyhow::Result<BTreeMap<Type, Cid>> {
     let bs = MemoryBlockstore::new();
     let actor_v10_bundle = (NetworkVersion::V18, actors_v10::BUNDLE_CAR);
-    let roots = block_on(async { load_car_unchecked(&bs, actor_v10_bundle.1).await.unwrap() });
-    assert_eq!(roots.len(), 1);
+    let roots = block_on(load_car_unchecked(&bs, actor_v10_bundle.1))
+        .context("failed to load the actor bundle")?;
+    ensure!(
+        roots.len() == 1,
+        "actor bundle has {} roots instead of the manifest",
+        roots.len()
+    );
 
     let manifest_cid = roots[0];
     let (_, builtin_actors_cid): (u32, Cid) = bs
@@ -30,8 +52,9 @@ pub fn get_code_cid_map() -> anyhow::Result<BTreeMap<Type, Cid>> {
     };
 
     let mut by_id: BTreeMap<Type, Cid> = BTreeMap::new();
-    for ((_, code_cid), id) in vec.into_iter().zip(1u32..) {
-        let actor_type = Type::from_u32(id).unwrap();
+    for ((name, code_cid), id) in vec.into_iter().zip(1u32..) {
+        let actor_type =
+            Type::from_u32(id).with_context(|| format!("unknown type {} of actor {}", id, name))?;
         by_id.insert(actor_type, code_cid);
     }
     Ok(by_id)
@@ -47,6 +70,47 @@ pub fn compute_address_create(from: &EthAddress, nonce: u64) -> EthAddress {
     EthAddress(hash_20(&stream.out()))
 }
 
+/// Address of the contract CREATE2 deploys from `from` with `salt`, `initcode_hash` being
+/// the keccak256 hash of the creation code.
+pub fn compute_address_create2(
+    from: &EthAddress,
+    salt: &[u8; 32],
+    initcode_hash: &[u8; 32],
+) -> EthAddress {
+    let mut preimage = Vec::with_capacity(85);
+    preimage.push(0xff);
+    preimage.extend_from_slice(&from.0);
+    preimage.extend_from_slice(salt);
+    preimage.extend_from_slice(initcode_hash);
+    EthAddress(hash_20(&preimage))
+}
+
+/// Robust address the Init actor assigns to the `index`th actor created by the message
+/// with sequence `nonce` sent by `origin`.
+pub fn compute_robust_address(origin: &Address, nonce: u64, index: u64) -> Address {
+    let mut bytes = fvm_ipld_encoding::to_vec(origin).unwrap();
+    bytes.extend_from_slice(&nonce.to_be_bytes());
+    bytes.extend_from_slice(&index.to_be_bytes());
+    Address::new_actor(&bytes)
+}
+
+/// FEVM treats `0xff` + 11 zero bytes + big endian actor ID as the ID address of that actor.
+pub fn masked_id(addr: &[u8; 20]) -> Option<ActorID> {
+    if addr[0] == 0xff && addr[1..12].iter().all(|b| *b == 0) {
+        Some(u64::from_be_bytes(addr[12..].try_into().unwrap()))
+    } else {
+        None
+    }
+}
+
+/// The Filecoin address of an Ethereum address, ID address for masked IDs, f4 address otherwise.
+pub fn eth_to_fil_address(addr: &[u8; 20]) -> anyhow::Result<Address> {
+    Ok(match masked_id(addr) {
+        Some(id) => Address::new_id(id),
+        None => Address::new_delegated(EAM_ACTOR_ID, addr)?,
+    })
+}
+
 pub fn hash_20(data: &[u8]) -> [u8; 20] {
     hash(SupportedHashes::Keccak256, data)[12..32]
         .try_into()
@@ -105,6 +169,15 @@ pub fn u256_to_bytes(u: &U256) -> Vec<u8> {
     v
 }
 
+#[test]
+fn test_masked_id() {
+    let addr = hex_to_eth_address("0xff00000000000000000000000000000000000401");
+    assert_eq!(masked_id(&addr.0), Some(1025));
+    assert_eq!(eth_to_fil_address(&addr.0).unwrap(), Address::new_id(1025));
+    let addr = hex_to_eth_address("0xff00000000000000000000010000000000000401");
+    assert_eq!(masked_id(&addr.0), None);
+}
+
 #[test]
 fn test_get_code_cid_map() {
     let map = get_code_cid_map().unwrap();
@@ -177,6 +177,12 @@ mod message_receipt_vec {
         #[serde(rename = "return", with = "base64_bytes")]
         return_value: Vec<u8>,
         gas_used: i64,
+        #[serde(
+            default,
+            with = "crate::cidjson::opt",
+            skip_serializing_if = "Option::is_none"
+        )]
+        events_root: Option<Cid>,
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Receipt>, D::Error>
@@ -189,7 +195,7 @@ mod message_receipt_vec {
                 exit_code: v.exit_code,
                 return_data: RawBytes::new(v.return_value),
                 gas_used: v.gas_used,
-                events_root: None,
+                events_root: v.events_root,
             })
             .collect())
     }
@@ -204,6 +210,7 @@ mod message_receipt_vec {
                 exit_code: v.exit_code,
                 return_value: v.return_data.clone().into(),
                 gas_used: v.gas_used,
+                events_root: v.events_root,
             })
             .collect();
         output.serialize(serializer)
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use ethers::types::{Bytes, H160, H256};
+use ethers::utils::keccak256;
+use fevm_test_vectors::anonymize::{anonymize_test_vector, AddressRemapper};
+use fevm_test_vectors::extractor::types::{EthAccountState, EthTransactionTestVector};
+
+#[test]
+fn anonymize_remaps_accounts() {
+    let input: EthTransactionTestVector = serde_json::from_str(include_str!(
+        "../corpus/transactions/0x8b173a898bf30c0a540cabd92ea1ff6622dd3f7a2fd13068b040a340337133da.json"
+    ))
+    .unwrap();
+    let mut remapper = AddressRemapper::new(b"seed");
+    let (output, report) = remapper.anonymize(&input);
+    assert!(report.remapped_accounts > 0);
+    assert_ne!(output.from, input.from);
+    assert_eq!(output.to, remapper.mapping()[&input.to]);
+    assert_eq!(output.prestate.len(), input.prestate.len());
+    for (original, mapped) in remapper.mapping() {
+        assert!(output.prestate.contains_key(mapped) || !input.prestate.contains_key(original));
+    }
+
+    // the same seed yields the same synthetic addresses
+    let (again, _) = anonymize_test_vector(&input, b"seed");
+    assert_eq!(again, output);
+}
+
+fn balance_slot(holder: &H160) -> H256 {
+    let mut preimage = [0; 64];
+    preimage[12..32].copy_from_slice(holder.as_bytes());
+    H256(keccak256(preimage))
+}
+
+#[test]
+fn anonymize_rewrites_occurrences() {
+    let alice = H160::repeat_byte(0xaa);
+    let token = H160::repeat_byte(0xbb);
+    // PUSH20 alice POP STOP
+    let mut code = vec![0x73];
+    code.extend(alice.as_bytes());
+    code.extend([0x50, 0x00]);
+    let account = EthAccountState {
+        code: code.into(),
+        storage: BTreeMap::from([(balance_slot(&alice), H256::from_low_u64_be(100))]),
+        ..Default::default()
+    };
+    // transfer(alice, 10), followed by an unaligned copy of alice
+    let mut input = hex::decode("a9059cbb").unwrap();
+    input.extend(H256::from(alice).as_bytes());
+    input.extend(H256::from_low_u64_be(10).as_bytes());
+    input.push(0xff);
+    input.extend(alice.as_bytes());
+    let vector = EthTransactionTestVector {
+        from: alice,
+        to: token,
+        input: Bytes::from(input),
+        prestate: BTreeMap::from([(alice, Default::default()), (token, account.clone())]),
+        poststate: BTreeMap::from([(alice, Default::default()), (token, account)]),
+        ..Default::default()
+    };
+
+    let mut remapper = AddressRemapper::new(b"seed");
+    let (output, report) = remapper.anonymize(&vector);
+    let mapped_alice = remapper.mapping()[&alice];
+    let mapped_token = remapper.mapping()[&token];
+    assert_ne!(mapped_alice, alice);
+
+    // storage keys derived from the holder address
+    let storage = &output.prestate[&mapped_token].storage;
+    assert_eq!(
+        storage.get(&balance_slot(&mapped_alice)),
+        Some(&H256::from_low_u64_be(100))
+    );
+    assert!(!storage.contains_key(&balance_slot(&alice)));
+
+    // PUSH20 constants
+    let code = &output.poststate[&mapped_token].code;
+    assert_eq!(&code[1..21], mapped_alice.as_bytes());
+
+    // ABI encoded calldata
+    assert_eq!(&output.input[4..36], H256::from(mapped_alice).as_bytes());
+
+    // the unaligned copy is left as it is and reported
+    assert_eq!(&output.input[69..], alice.as_bytes());
+    assert_eq!(report.unresolved.len(), 1);
+    assert_eq!(report.unresolved[0].address, alice);
+    assert_eq!(report.unresolved[0].location, "input[69]");
+}
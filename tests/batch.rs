@@ -0,0 +1,192 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use common::{methods, Reply, TempDir};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Bytes, Transaction, TransactionReceipt, H160, H256, U64};
+use fevm_test_vectors::extractor::backend::Backend;
+use fevm_test_vectors::extractor::batch::{
+    extract_batch, read_tx_hashes, select_transactions, ExtractState, TxFilter,
+};
+use serde_json::{json, Value};
+
+#[test]
+fn read_tx_hash_file() {
+    let dir = TempDir::new("tx-file");
+    let path = dir.join("txs");
+    std::fs::write(
+        &path,
+        format!(
+            "# transactions\n{:?}\n\n  {:?} # second\n",
+            H256::from_low_u64_be(1),
+            H256::from_low_u64_be(2)
+        ),
+    )
+    .unwrap();
+    let hashes = read_tx_hashes(&path).unwrap();
+    assert_eq!(
+        hashes,
+        vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]
+    );
+
+    std::fs::write(&path, "# transactions\n0x01\n").unwrap();
+    let error = read_tx_hashes(&path).unwrap_err();
+    assert!(error.to_string().ends_with(":2: invalid transaction hash"));
+}
+
+fn transaction(hash: u64, from: u64, to: Option<u64>, input: &[u8]) -> Transaction {
+    Transaction {
+        hash: H256::from_low_u64_be(hash),
+        from: H160::from_low_u64_be(from),
+        to: to.map(H160::from_low_u64_be),
+        input: Bytes::from(input.to_vec()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn filter_transactions() {
+    let contract = 0xbb;
+    let filter = TxFilter {
+        to: Some(H160::from_low_u64_be(contract)),
+        selector: Some([0xa9, 0x05, 0x9c, 0xbb]),
+        ..Default::default()
+    };
+    assert!(filter.matches(&transaction(
+        1,
+        0xaa,
+        Some(contract),
+        &[0xa9, 0x05, 0x9c, 0xbb, 0]
+    )));
+    assert!(!filter.matches(&transaction(
+        1,
+        0xaa,
+        Some(contract),
+        &[0x01, 0x02, 0x03, 0x04]
+    )));
+    assert!(!filter.matches(&transaction(1, 0xaa, Some(0xcc), &[0xa9, 0x05, 0x9c, 0xbb])));
+
+    // creations match the deployer
+    let filter = TxFilter {
+        to: Some(H160::from_low_u64_be(0xaa)),
+        creations_only: true,
+        ..Default::default()
+    };
+    assert!(filter.matches(&transaction(1, 0xaa, None, &[])));
+    assert!(!filter.matches(&transaction(1, 0xcc, None, &[])));
+    assert!(!filter.matches(&transaction(1, 0xaa, Some(0xaa), &[])));
+}
+
+#[tokio::test]
+async fn select_block_range_transactions() {
+    // block 1: a call and a creation by 0xaa, block 2: a call to another contract
+    let blocks: BTreeMap<u64, Vec<Transaction>> = BTreeMap::from([
+        (
+            1,
+            vec![
+                transaction(1, 0xcc, Some(0xaa), &[]),
+                transaction(2, 0xaa, None, &[]),
+            ],
+        ),
+        (2, vec![transaction(3, 0xcc, Some(0xdd), &[])]),
+    ]);
+    let (url, requests) = common::fake_node(Duration::ZERO, move |_, request| {
+        let params = &request["params"];
+        Reply::Result(match request["method"].as_str().unwrap() {
+            "eth_getBlockByNumber" => {
+                let number: U64 = serde_json::from_value(params[0].clone()).unwrap();
+                let block = Block {
+                    number: Some(number),
+                    transactions: blocks[&number.as_u64()].clone(),
+                    ..Default::default()
+                };
+                serde_json::to_value(block).unwrap()
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = serde_json::from_value(params[0].clone()).unwrap();
+                // only the creation failed
+                let receipt = TransactionReceipt {
+                    transaction_hash: hash,
+                    status: Some(U64::from((hash != H256::from_low_u64_be(2)) as u64)),
+                    gas_used: Some(50_000.into()),
+                    ..Default::default()
+                };
+                serde_json::to_value(receipt).unwrap()
+            }
+            _ => Value::Null,
+        })
+    })
+    .await;
+    let provider = Provider::<Http>::try_from(url.as_str()).unwrap();
+
+    let filter = TxFilter {
+        to: Some(H160::from_low_u64_be(0xaa)),
+        ..Default::default()
+    };
+    let hashes = select_transactions(&provider, 1..=2, &filter)
+        .await
+        .unwrap();
+    assert_eq!(
+        hashes,
+        vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]
+    );
+    // receipts are only fetched when the filter needs them
+    assert_eq!(
+        methods(&requests),
+        vec!["eth_getBlockByNumber", "eth_getBlockByNumber"]
+    );
+
+    let filter = TxFilter {
+        failed_only: true,
+        ..Default::default()
+    };
+    let hashes = select_transactions(&provider, 1..=2, &filter)
+        .await
+        .unwrap();
+    assert_eq!(hashes, vec![H256::from_low_u64_be(2)]);
+}
+
+#[tokio::test]
+async fn resume_batch_extraction() {
+    let out_dir = TempDir::new("resume");
+    let (done, failed) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+    ExtractState {
+        done: [done].into(),
+        failed: BTreeMap::from([(failed, String::from("timed out"))]),
+    }
+    .save(&out_dir)
+    .unwrap();
+
+    // the node knows no transaction
+    let (url, requests) =
+        common::fake_node(Duration::ZERO, |_, _| Reply::Result(json!(null))).await;
+    let provider = Provider::<Http>::try_from(url.as_str()).unwrap();
+    let state = extract_batch(
+        &provider,
+        vec![done, failed],
+        &out_dir,
+        2,
+        None,
+        Backend::Geth,
+        |_, _| panic!("nothing is extracted"),
+    )
+    .await
+    .unwrap();
+    let saved = ExtractState::load(&out_dir).unwrap();
+
+    // the done transaction is skipped, the failed one is retried
+    let params: Vec<Value> = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request["params"][0].clone())
+        .collect();
+    assert!(!params.contains(&json!(done)));
+    assert!(params.contains(&json!(failed)));
+    assert_eq!(state.done, [done].into());
+    assert!(state.failed[&failed].contains("not found"));
+    assert_eq!(saved.done, state.done);
+    assert_eq!(saved.failed, state.failed);
+}
@@ -0,0 +1,134 @@
+//! Fake JSON-RPC node shared by the tests talking to a node.
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Directory of a test, emptied when it's created so the files of an earlier run, e.g. a
+/// state file, aren't picked up, and removed when the test ends.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("fevm-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Answer of the fake node to a request.
+pub enum Reply {
+    /// JSON-RPC result
+    Result(Value),
+    /// HTTP error page, with its status line
+    Page(&'static str, String),
+}
+
+/// Requests received by a fake node, in arrival order.
+pub type Requests = Arc<Mutex<Vec<Value>>>;
+
+/// Fake JSON-RPC node answering the `n`th request with `handler(n, request)`, after `delay`.
+pub async fn fake_node<F>(delay: Duration, handler: F) -> (String, Requests)
+where
+    F: Fn(usize, &Value) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests: Requests = Default::default();
+    let received = requests.clone();
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let body = match read_body(&mut socket).await {
+                    Some(body) => body,
+                    None => return,
+                };
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let n = {
+                    let mut received = received.lock().unwrap();
+                    received.push(request.clone());
+                    received.len() - 1
+                };
+                tokio::time::sleep(delay).await;
+                let (status, body) = match handler(n, &request) {
+                    Reply::Result(result) => (
+                        "200 OK",
+                        json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                            .to_string(),
+                    ),
+                    Reply::Page(status, body) => (status, body),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+    (url, requests)
+}
+
+/// Methods of the requests received so far.
+pub fn methods(requests: &Requests) -> Vec<String> {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request["method"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+/// Body of the HTTP request read from `socket`, `None` if it's closed first.
+async fn read_body(socket: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let headers = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+            let len: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map(|len| len.trim().parse().unwrap())
+                .unwrap_or(0);
+            if buf.len() >= end + 4 + len {
+                return Some(buf[end + 4..end + 4 + len].to_vec());
+            }
+        }
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
@@ -0,0 +1,74 @@
+use ethers::types::{GethTrace, StructLog, U256};
+use fevm_test_vectors::coverage::Coverage;
+
+fn log(op: &str, depth: u64, stack: Vec<U256>) -> StructLog {
+    StructLog {
+        depth,
+        op: op.to_string(),
+        stack: Some(stack),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn coverage_counts_opcodes_and_precompiles() {
+    let trace = GethTrace {
+        struct_logs: vec![
+            log("PUSH1", 1, vec![]),
+            // STATICCALL(gas, 0x02, ...) into the sha256 precompile
+            log(
+                "STATICCALL",
+                1,
+                vec![0.into(), 0.into(), 2.into(), 100.into()],
+            ),
+            log(
+                "CALL",
+                1,
+                vec![0.into(), 0.into(), 0x1234.into(), 100.into()],
+            ),
+            log("REVERT", 2, vec![0.into(), 0.into()]),
+            log("SHA3", 1, vec![]),
+        ],
+        ..Default::default()
+    };
+    let mut coverage = Coverage::default();
+    coverage.record_trace(&trace);
+    let report = coverage.report();
+
+    assert_eq!(coverage.opcodes["KECCAK256"], 1);
+    assert_eq!(coverage.precompiles["sha256"], 1);
+    assert_eq!(coverage.max_call_depth, 2);
+    assert_eq!(coverage.reverts[&2], 1);
+    assert!(!report.missing_opcodes.contains(&"CALL".to_string()));
+    assert!(report.missing_opcodes.contains(&"CREATE2".to_string()));
+    assert!(report
+        .missing_precompiles
+        .contains(&"ecrecover".to_string()));
+    assert!(report
+        .missing_features
+        .contains(&"top-level revert".to_string()));
+}
+
+#[test]
+fn coverage_skips_faulted_calls() {
+    let mut underflow = log("CALL", 1, vec![0.into()]);
+    underflow.error = Some("stack underflow (1 <=> 7)".to_string());
+    // out of gas before calling the identity precompile
+    let mut out_of_gas = log(
+        "STATICCALL",
+        1,
+        vec![0.into(), 0.into(), 4.into(), 100.into()],
+    );
+    out_of_gas.error = Some("out of gas".to_string());
+    let trace = GethTrace {
+        failed: true,
+        struct_logs: vec![underflow, out_of_gas],
+        ..Default::default()
+    };
+    let mut coverage = Coverage::default();
+    coverage.record_trace(&trace);
+
+    assert_eq!(coverage.opcodes["CALL"], 1);
+    assert_eq!(coverage.errors["out of gas"], 1);
+    assert!(coverage.precompiles.is_empty());
+}
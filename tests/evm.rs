@@ -0,0 +1,301 @@
+use ethers::types::{Bytes, H160, H256, U256};
+use ethers::utils::{get_contract_address, get_create2_address_from_hash, keccak256};
+use fevm_test_vectors::extractor::evm::{
+    synthesize_test_vector, EvmInput, EvmSource, EvmTransaction,
+};
+use fevm_test_vectors::extractor::types::{EthAccountState, EthState};
+
+fn input() -> EvmInput {
+    let sender = H160::from_low_u64_be(0xaa);
+    let contract = H160::from_low_u64_be(0xbb);
+    let mut prestate = EthState::new();
+    prestate.insert(
+        sender,
+        EthAccountState {
+            nonce: 3,
+            balance: 1_000_000.into(),
+            ..Default::default()
+        },
+    );
+    prestate.insert(
+        contract,
+        EthAccountState {
+            // PUSH1 0x2a PUSH1 0x01 SSTORE
+            // PUSH1 0x2a PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 RETURN
+            code: Bytes::from(vec![
+                0x60, 0x2a, 0x60, 0x01, 0x55, 0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00,
+                0xf3,
+            ]),
+            ..Default::default()
+        },
+    );
+    EvmInput {
+        prestate,
+        transaction: EvmTransaction {
+            from: sender,
+            to: Some(contract),
+            value: 10.into(),
+            gas: 100_000.into(),
+            gas_price: 1.into(),
+            ..Default::default()
+        },
+        block: Default::default(),
+    }
+}
+
+#[tokio::test]
+async fn synthesize_storage_write() {
+    let vector = synthesize_test_vector(&input()).await.unwrap();
+    assert_eq!(vector.status, 1);
+    // intrinsic gas, pushes, a cold SSTORE setting a slot and a word of memory
+    assert_eq!(vector.gas_used, U256::from(21000 + 22124));
+    assert_eq!(
+        vector.return_value.to_vec(),
+        H256::from_low_u64_be(0x2a).as_bytes().to_vec()
+    );
+
+    let sender = &vector.poststate[&H160::from_low_u64_be(0xaa)];
+    assert_eq!(sender.nonce, 4);
+    assert_eq!(sender.balance, U256::from(1_000_000 - 43124 - 10));
+    let contract = &vector.poststate[&H160::from_low_u64_be(0xbb)];
+    assert_eq!(contract.balance, U256::from(10));
+    assert_eq!(
+        contract.storage.get(&H256::from_low_u64_be(1)),
+        Some(&H256::from_low_u64_be(0x2a))
+    );
+
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.call_type, "CALL");
+    assert!(!call_tree.reverted);
+}
+
+/// The input of `input()` with `code` at the called contract and a higher gas limit.
+fn input_with_code(code: Vec<u8>) -> EvmInput {
+    let mut input = input();
+    let contract = input
+        .prestate
+        .get_mut(&H160::from_low_u64_be(0xbb))
+        .unwrap();
+    contract.nonce = 1;
+    contract.code = Bytes::from(code);
+    input.transaction.gas = 300_000.into();
+    input
+}
+
+fn negative(n: u64) -> H256 {
+    let mut bytes = [0; 32];
+    U256::zero()
+        .overflowing_sub(n.into())
+        .0
+        .to_big_endian(&mut bytes);
+    H256(bytes)
+}
+
+fn slot(n: u64) -> H256 {
+    H256::from_low_u64_be(n)
+}
+
+#[test]
+fn signed_arithmetic() {
+    // SDIV(-6, 2) to slot 0
+    let mut code = vec![
+        0x60, 0x02, 0x60, 0x06, 0x60, 0x00, 0x03, 0x05, 0x60, 0x00, 0x55,
+    ];
+    // SMOD(-7, 3) to slot 1
+    code.extend([
+        0x60, 0x03, 0x60, 0x07, 0x60, 0x00, 0x03, 0x07, 0x60, 0x01, 0x55,
+    ]);
+    // SAR(2, -16) to slot 2
+    code.extend([
+        0x60, 0x10, 0x60, 0x00, 0x03, 0x60, 0x02, 0x1d, 0x60, 0x02, 0x55,
+    ]);
+    // SIGNEXTEND(0, 0xff) to slot 3
+    code.extend([0x60, 0xff, 0x60, 0x00, 0x0b, 0x60, 0x03, 0x55]);
+    // SDIV(-2^255, -1) to slot 4
+    code.extend([
+        0x60, 0x01, 0x60, 0x00, 0x03, 0x60, 0x01, 0x60, 0xff, 0x1b, 0x05,
+    ]);
+    code.extend([0x60, 0x04, 0x55, 0x00]);
+    let evm = EvmSource::execute(&input_with_code(code)).unwrap();
+    assert!(!evm.summary.failed);
+    let storage = &evm.poststate[&H160::from_low_u64_be(0xbb)].storage;
+    assert_eq!(storage[&slot(0)], negative(3));
+    assert_eq!(storage[&slot(1)], negative(1));
+    assert_eq!(storage[&slot(2)], negative(4));
+    assert_eq!(storage[&slot(3)], negative(1));
+    let mut min = [0; 32];
+    min[0] = 0x80;
+    assert_eq!(storage[&slot(4)], H256(min));
+}
+
+#[test]
+fn memory_expansion_gas() {
+    // PUSH1 0x01 PUSH2 0x7fff MSTORE8 STOP
+    let evm = EvmSource::execute(&input_with_code(vec![
+        0x60, 0x01, 0x61, 0x7f, 0xff, 0x53, 0x00,
+    ]))
+    .unwrap();
+    // 1024 words of memory cost 3 * 1024 + 1024² / 512
+    assert_eq!(evm.logs[2].op, "MSTORE8");
+    assert_eq!(evm.logs[2].gas_cost, 3 + 5120);
+    assert_eq!(evm.summary.gas, 21000 + 3 + 3 + 3 + 5120);
+}
+
+#[test]
+fn storage_clear_refund() {
+    let mut input = input_with_code(vec![0x60, 0x00, 0x60, 0x00, 0x55, 0x00]);
+    let contract = H160::from_low_u64_be(0xbb);
+    input
+        .prestate
+        .get_mut(&contract)
+        .unwrap()
+        .storage
+        .insert(slot(0), slot(1));
+    let evm = EvmSource::execute(&input).unwrap();
+    // a cold SSTORE clearing a slot costs 5000 and refunds 4800
+    assert_eq!(evm.summary.gas, 21000 + 3 + 3 + 5000 - 4800);
+    assert_eq!(evm.poststate[&contract].storage[&slot(0)], H256::zero());
+    let sender = &evm.poststate[&H160::from_low_u64_be(0xaa)];
+    assert_eq!(sender.balance, U256::from(1_000_000 - 21206 - 10));
+}
+
+#[test]
+fn revert_rolls_back_the_frame() {
+    // CALL(GAS, 0xcc, 0, 0, 0, 0, 0) ISZERO PUSH1 0x01 SSTORE STOP
+    let mut input = input_with_code(vec![
+        0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0xcc, 0x5a, 0xf1, 0x15,
+        0x60, 0x01, 0x55, 0x00,
+    ]);
+    let callee = H160::from_low_u64_be(0xcc);
+    input.prestate.insert(
+        callee,
+        EthAccountState {
+            nonce: 1,
+            // PUSH1 0x01 PUSH1 0x00 SSTORE PUSH1 0x00 PUSH1 0x00 REVERT
+            code: Bytes::from(vec![
+                0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd,
+            ]),
+            ..Default::default()
+        },
+    );
+    let evm = EvmSource::execute(&input).unwrap();
+    assert!(!evm.summary.failed);
+    assert!(evm.poststate[&callee].storage.is_empty());
+    assert_eq!(
+        evm.poststate[&H160::from_low_u64_be(0xbb)].storage[&slot(1)],
+        slot(1)
+    );
+    let call_tree = evm.call_tree.unwrap();
+    assert!(!call_tree.reverted);
+    assert_eq!(call_tree.calls[0].callee, callee);
+    assert!(call_tree.calls[0].reverted);
+
+    // reverting the whole transaction keeps the value and only charges the gas
+    input.transaction.to = Some(callee);
+    let evm = EvmSource::execute(&input).unwrap();
+    assert!(evm.summary.failed);
+    assert!(evm.poststate[&callee].storage.is_empty());
+    assert_eq!(evm.poststate[&callee].balance, U256::zero());
+    let sender = &evm.poststate[&H160::from_low_u64_be(0xaa)];
+    assert_eq!(sender.nonce, 4);
+    assert_eq!(sender.balance, U256::from(1_000_000 - evm.summary.gas));
+}
+
+#[test]
+fn call_transfers_value() {
+    // CALL(GAS, 0xcc, 5, 0, 0, 0, 0) STOP
+    let evm = EvmSource::execute(&input_with_code(vec![
+        0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x05, 0x60, 0xcc, 0x5a, 0xf1, 0x00,
+    ]))
+    .unwrap();
+    assert!(!evm.summary.failed);
+    let callee = H160::from_low_u64_be(0xcc);
+    assert_eq!(evm.poststate[&callee].balance, U256::from(5));
+    assert_eq!(
+        evm.poststate[&H160::from_low_u64_be(0xbb)].balance,
+        U256::from(10 - 5)
+    );
+    // the callee wasn't in the prestate
+    assert_eq!(evm.prestate[&callee], EthAccountState::default());
+
+    let call = &evm.call_tree.unwrap().calls[0];
+    assert_eq!(call.call_type, "CALL");
+    assert_eq!(call.callee, callee);
+    assert_eq!(call.value, U256::from(5));
+}
+
+#[test]
+fn create_and_create2() {
+    // init code returning the single byte 0x2a:
+    // PUSH1 0x2a PUSH1 0x00 MSTORE8 PUSH1 0x01 PUSH1 0x00 RETURN
+    let init_code = [0x60, 0x2a, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xf3];
+    // PUSH10 <init code> PUSH1 0x00 MSTORE
+    let mut code = vec![0x69];
+    code.extend(init_code);
+    code.extend([0x60, 0x00, 0x52]);
+    // CREATE(0, 22, 10) PUSH1 0x00 SSTORE
+    code.extend([0x60, 0x0a, 0x60, 0x16, 0x60, 0x00, 0xf0, 0x60, 0x00, 0x55]);
+    // CREATE2(0, 22, 10, 1) PUSH1 0x01 SSTORE STOP
+    code.extend([
+        0x60, 0x01, 0x60, 0x0a, 0x60, 0x16, 0x60, 0x00, 0xf5, 0x60, 0x01, 0x55, 0x00,
+    ]);
+    let evm = EvmSource::execute(&input_with_code(code)).unwrap();
+    assert!(!evm.summary.failed);
+
+    let contract = H160::from_low_u64_be(0xbb);
+    let created = get_contract_address(contract, 1);
+    let created2 =
+        get_create2_address_from_hash(contract, slot(1).0.to_vec(), keccak256(init_code));
+    let storage = &evm.poststate[&contract].storage;
+    assert_eq!(storage[&slot(0)], H256::from(created));
+    assert_eq!(storage[&slot(1)], H256::from(created2));
+    assert_eq!(evm.poststate[&contract].nonce, 3);
+    for address in [created, created2] {
+        let account = &evm.poststate[&address];
+        assert_eq!(account.nonce, 1);
+        assert_eq!(account.code.to_vec(), vec![0x2a]);
+    }
+
+    let calls = &evm.call_tree.unwrap().calls;
+    assert_eq!(calls[0].call_type, "CREATE");
+    assert_eq!(calls[0].callee, created);
+    assert_eq!(calls[0].input.to_vec(), init_code.to_vec());
+    assert_eq!(calls[1].call_type, "CREATE2");
+    assert_eq!(calls[1].callee, created2);
+}
+
+#[test]
+fn selfdestruct_moves_the_balance() {
+    // PUSH1 0xcc SELFDESTRUCT
+    let mut input = input_with_code(vec![0x60, 0xcc, 0xff]);
+    let contract = H160::from_low_u64_be(0xbb);
+    input.prestate.get_mut(&contract).unwrap().balance = 50.into();
+    let evm = EvmSource::execute(&input).unwrap();
+    assert!(!evm.summary.failed);
+    assert!(!evm.poststate.contains_key(&contract));
+    // the balance and the value of the transaction
+    assert_eq!(
+        evm.poststate[&H160::from_low_u64_be(0xcc)].balance,
+        U256::from(60)
+    );
+}
+
+#[test]
+fn later_fork_opcodes_are_unsupported() {
+    // PUSH0 STOP
+    let error = EvmSource::execute(&input_with_code(vec![0x5f, 0x00]))
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "the PUSH0 opcode is not supported by the embedded EVM"
+    );
+}
+
+#[test]
+fn gas_fee_overflow() {
+    let mut input = input();
+    input.transaction.gas_price = U256::MAX;
+    let error = EvmSource::execute(&input).err().unwrap();
+    assert!(error.to_string().contains("overflow"), "{}", error);
+}
@@ -0,0 +1,58 @@
+use cid::multihash::Code;
+use cid::Cid;
+use fevm_test_vectors::evm_state::{State, StateVersion, Tombstone};
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_encoding::CborStore;
+use fvm_shared::{IDENTITY_HASH, IPLD_RAW};
+
+#[test]
+fn evm_state_layouts_round_trip() {
+    let store = MemoryBlockstore::new();
+    let cid = Cid::new_v1(
+        IPLD_RAW,
+        multihash::Multihash::wrap(IDENTITY_HASH, b"bytecode").unwrap(),
+    );
+    for version in StateVersion::ALL {
+        let mut state = State::new(*version, cid, [7; 32], cid, 3);
+        if *version != StateVersion::V10 {
+            state.tombstone = Some(Tombstone {
+                origin: 100,
+                nonce: 1,
+            });
+        }
+        let head = state.store(&store).unwrap();
+        let loaded = State::load(&store, &head).unwrap().unwrap();
+        assert_eq!(loaded, state, "{} layout", version);
+    }
+}
+
+#[test]
+fn evm_state_load_errors() {
+    let store = MemoryBlockstore::new();
+    let cid = Cid::new_v1(
+        IPLD_RAW,
+        multihash::Multihash::wrap(IDENTITY_HASH, b"bytecode").unwrap(),
+    );
+    // another actor's state, e.g. an account
+    let account = store.put_cbor(&(cid,), Code::Blake2b256).unwrap();
+    assert_eq!(State::load(&store, &account).unwrap(), None);
+
+    // a v10 state whose bytecode hash isn't a keccak256 digest
+    let short_hash = multihash::Multihash::wrap(IDENTITY_HASH, &[7; 20]).unwrap();
+    let head = store
+        .put_cbor(&(cid, short_hash, cid, 3u64), Code::Blake2b256)
+        .unwrap();
+    let error = State::load(&store, &head).unwrap_err();
+    assert!(error.to_string().contains("bytecode hash"), "{}", error);
+
+    // a state with the fields of a layout but not their types
+    let head = store
+        .put_cbor(&(1u64, 2u64, 3u64, 4u64, 5u64), Code::Blake2b256)
+        .unwrap();
+    let error = State::load(&store, &head).unwrap_err();
+    assert!(
+        error.to_string().contains("invalid v11 EVM state"),
+        "{}",
+        error
+    );
+}
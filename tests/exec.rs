@@ -1,10 +1,19 @@
 use std::path::Path;
 
-use fevm_test_vectors::extractor::types::EthTransactionTestVector;
-use fevm_test_vectors::util::{compute_address_create, hex_to_eth_address};
+use ethers::types::{Bytes, H160, H256};
+use ethers::utils::keccak256;
+use fevm_test_vectors::evm_state::StateVersion;
+use fevm_test_vectors::extractor::evm::{synthesize_test_vector, EvmInput, EvmTransaction};
+use fevm_test_vectors::extractor::types::{
+    EthAccountState, EthPrecompileCall, EthState, EthTransactionTestVector,
+};
+use fevm_test_vectors::mock::MockConfig;
+use fevm_test_vectors::util::{
+    compute_address_create, compute_address_create2, hex_to_eth_address,
+};
 use fevm_test_vectors::{export_test_vector_file, init_log, load_evm_contract_input};
 use fil_actor_eam::EthAddress;
-use fil_actor_evm::DelegateCallParams;
+use fil_actor_evm::{DelegateCallParams, EVM_CONTRACT_REVERTED};
 use fvm_ipld_encoding::{from_slice, strict_bytes, BytesDe, Cbor, RawBytes};
 use serde::{Deserialize, Serialize};
 use serde_tuple::*;
@@ -17,6 +26,15 @@ fn evm_create_test() {
     assert_eq!(result.0[..], expected.0[..]);
 }
 
+#[test]
+fn evm_create2_test() {
+    // example 1 of EIP-1014
+    let from = hex_to_eth_address("0x0000000000000000000000000000000000000000");
+    let expected = hex_to_eth_address("0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38");
+    let result = compute_address_create2(&EthAddress(from.0), &[0; 32], &keccak256([0x00]));
+    assert_eq!(result.0[..], expected.0[..]);
+}
+
 #[test]
 fn from_slice_test() {
     let input: Vec<u8> = Vec::from([
@@ -76,7 +94,136 @@ async fn exec_export() {
         "contracts/0x26c9c5e5e4f35e7eebcefec434b986b13fa5d7768c1e89a793c41be58f977195.json"
     ))
     .unwrap();
-    export_test_vector_file(input, Path::new("test_vector.json").to_path_buf())
+    export_test_vector_file(
+        input,
+        Path::new("test_vector.json").to_path_buf(),
+        &MockConfig::default(),
+    )
+    .await
+    .unwrap();
+}
+
+#[async_std::test]
+async fn exec_export_precompile_comment() {
+    let mut input: EthTransactionTestVector = serde_json::from_str(include_str!(
+        "contracts/0x26c9c5e5e4f35e7eebcefec434b986b13fa5d7768c1e89a793c41be58f977195.json"
+    ))
+    .unwrap();
+    let call = EthPrecompileCall {
+        address: H160::from_low_u64_be(0x04),
+        name: String::from("identity"),
+        call_type: String::from("DELEGATECALL"),
+        success: true,
+        ..Default::default()
+    };
+    // the same difference is noted once
+    input.precompile_calls = vec![call.clone(), call];
+    input.precompile_calls.push(EthPrecompileCall {
+        address: H160::from_low_u64_be(0x02),
+        name: String::from("sha256"),
+        call_type: String::from("CALL"),
+        value: 1.into(),
+        success: true,
+        ..Default::default()
+    });
+
+    let path = std::env::temp_dir().join(format!("fevm-precompile-{}.json", std::process::id()));
+    export_test_vector_file(input, path.clone(), &MockConfig::default())
+        .await
+        .unwrap();
+    let vector: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        vector["_meta"]["comment"],
+        "identity precompile called by DELEGATECALL; value transferred to sha256 precompile"
+    );
+}
+
+#[async_std::test]
+async fn exec_export_rejects_state_version() {
+    let input: EthTransactionTestVector = serde_json::from_str(include_str!(
+        "contracts/0x26c9c5e5e4f35e7eebcefec434b986b13fa5d7768c1e89a793c41be58f977195.json"
+    ))
+    .unwrap();
+    let config = MockConfig {
+        evm_state_version: StateVersion::V16,
+        ..Default::default()
+    };
+    let path = std::env::temp_dir().join(format!("fevm-state-version-{}.json", std::process::id()));
+    let error = export_test_vector_file(input, path.clone(), &config)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("EVM state version v16"));
+    assert!(!path.exists());
+}
+
+#[async_std::test]
+async fn exec_export_reverted_receipt() {
+    let sender = H160::from_low_u64_be(0xaa);
+    let contract = H160::from_low_u64_be(0xbb);
+    let mut prestate = EthState::new();
+    prestate.insert(
+        sender,
+        EthAccountState {
+            nonce: 1,
+            balance: 1_000_000.into(),
+            ..Default::default()
+        },
+    );
+    prestate.insert(
+        contract,
+        EthAccountState {
+            nonce: 1,
+            // PUSH1 0x2a PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 REVERT
+            code: Bytes::from(vec![
+                0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xfd,
+            ]),
+            ..Default::default()
+        },
+    );
+    let input = EvmInput {
+        prestate,
+        transaction: EvmTransaction {
+            from: sender,
+            to: Some(contract),
+            gas: 100_000.into(),
+            gas_price: 1.into(),
+            ..Default::default()
+        },
+        block: Default::default(),
+    };
+    let input = synthesize_test_vector(&input).await.unwrap();
+    assert_eq!(input.status, 0);
+
+    let path = std::env::temp_dir().join(format!("fevm-reverted-{}.json", std::process::id()));
+    export_test_vector_file(input, path.clone(), &MockConfig::default())
         .await
         .unwrap();
+    let vector: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let receipt = &vector["postconditions"]["receipts"][0];
+    assert_eq!(receipt["exit_code"], EVM_CONTRACT_REVERTED.value());
+    // the revert data, CBOR encoded
+    let return_data = RawBytes::serialize(BytesDe(H256::from_low_u64_be(0x2a).0.to_vec())).unwrap();
+    assert_eq!(receipt["return"], base64::encode(return_data.bytes()));
+}
+
+#[async_std::test]
+async fn exec_export_failed_receipt() {
+    let mut input: EthTransactionTestVector = serde_json::from_str(include_str!(
+        "contracts/0x26c9c5e5e4f35e7eebcefec434b986b13fa5d7768c1e89a793c41be58f977195.json"
+    ))
+    .unwrap();
+    // as selected by --failed-only
+    input.status = 0;
+    let path = std::env::temp_dir().join(format!("fevm-failed-{}.json", std::process::id()));
+    export_test_vector_file(input, path.clone(), &MockConfig::default())
+        .await
+        .unwrap();
+    let vector: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        vector["postconditions"]["receipts"][0]["exit_code"],
+        EVM_CONTRACT_REVERTED.value()
+    );
 }
@@ -0,0 +1,93 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use common::TempDir;
+use fevm_test_vectors::generate::{generate_in_parallel, process_in_parallel};
+use fevm_test_vectors::mock::MockConfig;
+
+#[test]
+fn generate_reports_failed_inputs() {
+    let dir = TempDir::new("generate");
+    let invalid = dir.join("invalid.json");
+    std::fs::write(&invalid, "{}").unwrap();
+    let valid = Path::new(env!("CARGO_MANIFEST_DIR")).join(
+        "tests/contracts/0x26c9c5e5e4f35e7eebcefec434b986b13fa5d7768c1e89a793c41be58f977195.json",
+    );
+    let out_dir = dir.join("out");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let results = generate_in_parallel(
+        &[invalid.clone(), valid.clone()],
+        &out_dir,
+        &MockConfig::default(),
+        2,
+    );
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].input, invalid);
+    assert!(!results[0].success);
+    assert!(results[0]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("Serialization failed"));
+    assert_eq!(results[0].output, None);
+
+    assert_eq!(results[1].input, valid);
+    assert!(results[1].success, "{:?}", results[1].error);
+    assert_eq!(
+        results[1].output,
+        Some(out_dir.join(valid.file_name().unwrap()))
+    );
+    assert!(results[1].output_size.unwrap() > 0);
+}
+
+#[test]
+fn process_in_parallel_catches_panics() {
+    let dir = TempDir::new("parallel");
+    let files: Vec<PathBuf> = (0..8).map(|i| dir.join(format!("{}.json", i))).collect();
+
+    let results = process_in_parallel(&files, 4, |input| {
+        match input.file_stem().unwrap().to_str().unwrap() {
+            "3" => panic!("boom"),
+            "5" => bail!("failed"),
+            _ => {
+                std::fs::write(input, "{}")?;
+                Ok(input.to_path_buf())
+            }
+        }
+    });
+
+    // the results are in input order, a panic only fails its input
+    let inputs: Vec<_> = results.iter().map(|r| r.input.clone()).collect();
+    assert_eq!(inputs, files);
+    for (i, result) in results.iter().enumerate() {
+        match i {
+            3 => assert_eq!(result.error.as_deref(), Some("panicked: boom")),
+            5 => assert_eq!(result.error.as_deref(), Some("failed")),
+            _ => {
+                assert!(result.success);
+                assert_eq!(result.output_size, Some(2));
+            }
+        }
+    }
+}
+
+#[test]
+fn process_in_parallel_keeps_input_order() {
+    let dir = TempDir::new("order");
+    let files: Vec<PathBuf> = (0..6).map(|i| dir.join(format!("{}.json", i))).collect();
+    for jobs in [0, 1, 3, 16] {
+        let results = process_in_parallel(&files, jobs, |input| {
+            // the first inputs finish last
+            let i: u64 = input.file_stem().unwrap().to_str().unwrap().parse()?;
+            std::thread::sleep(std::time::Duration::from_millis((6 - i) * 5));
+            std::fs::write(input, i.to_string())?;
+            Ok(input.to_path_buf())
+        });
+        let outputs: Vec<_> = results.iter().map(|r| r.output.clone().unwrap()).collect();
+        assert_eq!(outputs, files, "{} jobs", jobs);
+    }
+}
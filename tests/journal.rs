@@ -0,0 +1,38 @@
+use ethers::types::{H160, H256, U256};
+use fevm_test_vectors::extractor::journal::JournaledState;
+use fevm_test_vectors::extractor::types::{EthAccountState, EthState};
+
+#[test]
+fn revert_undoes_changes_since_checkpoint() {
+    let address = H160::from_low_u64_be(1);
+    let key = H256::from_low_u64_be(1);
+    let mut state = EthState::new();
+    state.insert(
+        address,
+        EthAccountState {
+            balance: 10.into(),
+            ..Default::default()
+        },
+    );
+    let mut state = JournaledState::new(state);
+
+    state.checkpoint();
+    state.sub_balance(&address, 4.into());
+    state.checkpoint();
+    state.increment_nonce(&address);
+    state.set_storage(&address, key, H256::from_low_u64_be(7));
+    state.revert();
+    let account = state.get(&address).unwrap();
+    assert_eq!(account.balance, U256::from(6));
+    assert_eq!(account.nonce, 0);
+    assert!(account.storage.is_empty());
+
+    // the changes of a returned frame are undone by a revert of its caller
+    state.checkpoint();
+    state.set_storage(&address, key, H256::from_low_u64_be(7));
+    state.discard_checkpoints(1);
+    state.revert();
+    let state = state.into_state();
+    assert_eq!(state[&address].balance, U256::from(10));
+    assert!(state[&address].storage.is_empty());
+}
@@ -0,0 +1,284 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use cid::multihash::Code;
+use cid::Cid;
+use common::{Reply, Requests};
+use ethers::providers::{Http, Provider};
+use ethers::types::H256;
+use fevm_test_vectors::extractor::lotus::{
+    dag_links, export_lotus_test_vector_file, read_dag, Precursors,
+};
+use fevm_test_vectors::mock::actor;
+use fevm_test_vectors::tracing_blockstore::TracingBlockStore;
+use fil_actor_init::{ExecReturn, Method as InitMethod, State as InitState};
+use fil_actors_runtime::{
+    EAM_ACTOR_ID, FIRST_NON_SINGLETON_ADDR, INIT_ACTOR_ADDR, SYSTEM_ACTOR_ADDR,
+};
+use fvm_ipld_blockstore::{Block, Blockstore, MemoryBlockstore};
+use fvm_ipld_encoding::{CborStore, RawBytes};
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::state::{StateRoot, StateTreeVersion};
+use fvm_shared::{HAMT_BIT_WIDTH, IPLD_RAW};
+use serde_json::{json, Value};
+
+#[test]
+fn read_dag_reads_linked_blocks_only() {
+    let base = MemoryBlockstore::new();
+    let leaf = base
+        .put(
+            Code::Blake2b256,
+            &Block::new(IPLD_RAW, b"bytecode".to_vec()),
+        )
+        .unwrap();
+    let node = base
+        .put_cbor(&(leaf, 7u64, "node"), Code::Blake2b256)
+        .unwrap();
+    let root = base
+        .put_cbor(&(vec![node, leaf], Some(node)), Code::Blake2b256)
+        .unwrap();
+    let unrelated = base.put_cbor(&(leaf, 8u64), Code::Blake2b256).unwrap();
+
+    let root_block = base.get(&root).unwrap().unwrap();
+    assert_eq!(dag_links(&root_block).unwrap(), vec![node, leaf, node]);
+
+    let store = TracingBlockStore::new(base);
+    let mut seen = HashSet::new();
+    read_dag(&store, root, &mut seen).unwrap();
+    let traced = store.traced.borrow();
+    assert_eq!(*traced, HashSet::from([root, node, leaf]));
+    assert!(!traced.contains(&unrelated));
+}
+
+#[test]
+fn dag_links_rejects_truncated_blocks() {
+    assert!(dag_links(&[0x82, 0x01]).is_err());
+}
+
+/// State with the Init actor and the System actor, whose manifest links to the actor
+/// code, and the EVM actor `created` if given, with its robust and delegated addresses.
+/// Returns the state root, the code, the root of the Init address map and the blocks by
+/// CID.
+fn lotus_state(created: Option<(Address, Address)>) -> (Cid, Cid, Cid, HashMap<String, Vec<u8>>) {
+    let store = TracingBlockStore::new(MemoryBlockstore::new());
+    let code = store
+        .put(Code::Blake2b256, &Block::new(IPLD_RAW, b"wasm".to_vec()))
+        .unwrap();
+    let manifest = store
+        .put_cbor(&vec![("evm".to_owned(), code)], Code::Blake2b256)
+        .unwrap();
+    let system = store.put_cbor(&(manifest,), Code::Blake2b256).unwrap();
+    let mut init = InitState::new(&store, "test".to_owned()).unwrap();
+    let mut states = vec![(SYSTEM_ACTOR_ADDR, system, None)];
+    if let Some((robust, delegated)) = created {
+        let (id, _) = init
+            .map_addresses_to_id(&store, &robust, Some(&delegated))
+            .unwrap();
+        let head = store.put_cbor(&(), Code::Blake2b256).unwrap();
+        states.push((Address::new_id(id), head, Some(delegated)));
+    }
+    let address_map = init.address_map;
+    let init = store.put_cbor(&init, Code::Blake2b256).unwrap();
+    states.push((INIT_ACTOR_ADDR, init, None));
+
+    let mut actors = Hamt::<_, _>::new_with_bit_width(&store, HAMT_BIT_WIDTH);
+    for (addr, head, delegated) in states {
+        let state = actor(code, head, 0, TokenAmount::from_atto(0), delegated);
+        actors.set(addr.to_bytes().into(), state).unwrap();
+    }
+    let actors = actors.flush().unwrap();
+    let info = store.put_cbor(&(), Code::Blake2b256).unwrap();
+    let root = store
+        .put_cbor(
+            &StateRoot {
+                version: StateTreeVersion::V5,
+                actors,
+                info,
+            },
+            Code::Blake2b256,
+        )
+        .unwrap();
+
+    let blocks = store
+        .traced
+        .borrow()
+        .iter()
+        .map(|cid| (cid.to_string(), store.base.get(cid).unwrap().unwrap()))
+        .collect();
+    (root, code, address_map, blocks)
+}
+
+/// Fake Lotus node serving `blocks` and a message from f0100 to f0101 which turns the
+/// state `pre` into `post` with `execution_trace`. The node computes the message with
+/// more gas than on chain.
+async fn lotus_node(
+    pre: Cid,
+    post: Cid,
+    mut blocks: HashMap<String, Vec<u8>>,
+    execution_trace: Value,
+) -> (Provider<Http>, Requests) {
+    let message = Message {
+        version: 0,
+        from: Address::new_id(100),
+        to: Address::new_id(101),
+        sequence: 0,
+        value: TokenAmount::from_atto(0),
+        method_num: 0,
+        params: RawBytes::default(),
+        gas_limit: 1000,
+        gas_fee_cap: TokenAmount::from_atto(0),
+        gas_premium: TokenAmount::from_atto(0),
+    };
+    let message_store = MemoryBlockstore::new();
+    let msg_cid = message_store.put_cbor(&message, Code::Blake2b256).unwrap();
+    blocks.insert(
+        msg_cid.to_string(),
+        message_store.get(&msg_cid).unwrap().unwrap(),
+    );
+
+    // every tipset is the same
+    let msg = json!({"/": msg_cid.to_string()});
+    let tipset = json!([{"/": pre.to_string()}]);
+    let (url, requests) = common::fake_node(Duration::ZERO, move |_, request| {
+        let params = &request["params"];
+        Reply::Result(match request["method"].as_str().unwrap() {
+            "Filecoin.EthGetMessageCidByTransactionHash" => msg.clone(),
+            "Filecoin.StateSearchMsg" => json!({
+                "Message": msg,
+                "Receipt": {"ExitCode": 0, "GasUsed": 10},
+                "TipSet": tipset,
+            }),
+            "Filecoin.ChainGetTipSet" => json!({
+                "Cids": tipset,
+                "Blocks": [{"Parents": tipset, "ParentBaseFee": "100", "Timestamp": 1000}],
+                "Height": 10,
+            }),
+            "Filecoin.ChainGetParentMessages" => json!([{
+                "Cid": msg,
+                "Message": {"From": "f0100", "To": "f0101"},
+            }]),
+            // the pre-state is computed without the message
+            "Filecoin.StateCompute" => {
+                let root = match params[1].as_array().unwrap().len() {
+                    0 => pre,
+                    _ => post,
+                };
+                json!({
+                    "Root": {"/": root.to_string()},
+                    "Trace": [{
+                        "MsgRct": {"ExitCode": 0, "GasUsed": 12},
+                        "ExecutionTrace": execution_trace,
+                    }],
+                })
+            }
+            "Filecoin.ChainReadObj" => {
+                json!(base64::encode(&blocks[params[0]["/"].as_str().unwrap()]))
+            }
+            "Filecoin.StateGetRandomnessFromBeacon" => json!(base64::encode(b"randomness")),
+            "Filecoin.StateNetworkVersion" => json!(18),
+            "eth_chainId" => json!("0x13a"),
+            _ => Value::Null,
+        })
+    })
+    .await;
+    (Provider::<Http>::try_from(url).unwrap(), requests)
+}
+
+/// CIDs of the blocks read through `Filecoin.ChainReadObj`.
+fn read_objects(requests: &Requests) -> Vec<String> {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|request| request["method"] == "Filecoin.ChainReadObj")
+        .map(|request| request["params"][0]["/"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_lotus_test_vector() {
+    let (root, code, _, blocks) = lotus_state(None);
+    let (provider, requests) = lotus_node(
+        root,
+        root,
+        blocks,
+        json!({"Msg": {"From": "f0100", "To": "f0101"}}),
+    )
+    .await;
+    let dir = std::env::temp_dir().join(format!("fevm-lotus-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("vector.json");
+    let tx_hash = H256::from_low_u64_be(1);
+
+    // the receipts only match when every precursor is applied
+    let error = export_lotus_test_vector_file(&provider, tx_hash, &path, Precursors::All)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("on chain"), "{}", error);
+
+    export_lotus_test_vector_file(&provider, tx_hash, &path, Precursors::Sender)
+        .await
+        .unwrap();
+    let vector: Value = serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let root = json!({"/": root.to_string()});
+    assert_eq!(vector["preconditions"]["state_tree"]["root_cid"], root);
+    assert_eq!(vector["postconditions"]["state_tree"]["root_cid"], root);
+    assert_eq!(vector["postconditions"]["receipts"][0]["gas_used"], 12);
+    assert_eq!(vector["chain_id"], 0x13a);
+
+    // the actor code linked by the System actor's manifest isn't read
+    let read = read_objects(&requests);
+    assert!(!read.is_empty());
+    assert!(!read.contains(&code.to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_lotus_creation() {
+    let robust = Address::new_actor(b"created");
+    let delegated = Address::new_delegated(EAM_ACTOR_ID, &[0xcc; 20]).unwrap();
+    let (pre, _, pre_address_map, mut blocks) = lotus_state(None);
+    let (post, _, _, post_blocks) = lotus_state(Some((robust, delegated)));
+    blocks.extend(post_blocks);
+    let created = Address::new_id(FIRST_NON_SINGLETON_ADDR);
+    let exec_return = RawBytes::serialize(ExecReturn {
+        id_address: created,
+        robust_address: robust,
+    })
+    .unwrap();
+    // f0101 has the EAM create the contract through Init, the trace only has ID addresses
+    let execution_trace = json!({
+        "Msg": {"From": "f0100", "To": "f0101"},
+        "Subcalls": [{
+            "Msg": {"From": "f0101", "To": "f01", "Method": InitMethod::Exec4 as u64},
+            "MsgRct": {
+                "ExitCode": 0,
+                "Return": base64::encode(exec_return.bytes()),
+                "GasUsed": 0,
+            },
+            "Subcalls": [{"Msg": {"From": "f01", "To": created.to_string(), "Method": 1}}],
+        }],
+    });
+    let (provider, requests) = lotus_node(pre, post, blocks, execution_trace).await;
+    let dir = std::env::temp_dir().join(format!("fevm-lotus-creation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("vector.json");
+    export_lotus_test_vector_file(
+        &provider,
+        H256::from_low_u64_be(1),
+        &path,
+        Precursors::Sender,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // the robust and delegated addresses are looked up in the pre-state address map, as
+    // the message does when Init assigns them
+    assert!(read_objects(&requests).contains(&pre_address_map.to_string()));
+}
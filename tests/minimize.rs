@@ -0,0 +1,131 @@
+use ethers::types::{Bytes, H160, H256, U256};
+use fevm_test_vectors::extractor::evm::{synthesize_test_vector, EvmInput, EvmTransaction};
+use fevm_test_vectors::extractor::types::{
+    EthAccesses, EthAccountState, EthState, EthTransactionTestVector,
+};
+use fevm_test_vectors::minimize::{minimize_test_vector, verify_minimized, EvmExecutor};
+
+#[test]
+fn minimize_drops_unused_slots_and_accounts() {
+    let from = H160::from_low_u64_be(0x1000);
+    let contract = H160::from_low_u64_be(0x2000);
+    let untouched = H160::from_low_u64_be(0x3000);
+    let (read, written, unused) = (
+        H256::from_low_u64_be(1),
+        H256::from_low_u64_be(2),
+        H256::from_low_u64_be(3),
+    );
+
+    let mut input = EthTransactionTestVector {
+        from,
+        to: contract,
+        ..Default::default()
+    };
+    let mut contract_state = EthAccountState::default();
+    for key in [read, written, unused] {
+        contract_state.storage.insert(key, H256::from_low_u64_be(7));
+    }
+    input.prestate.insert(from, EthAccountState::default());
+    input.prestate.insert(contract, contract_state.clone());
+    input.prestate.insert(untouched, EthAccountState::default());
+    input.poststate = input.prestate.clone();
+    input
+        .poststate
+        .get_mut(&contract)
+        .unwrap()
+        .storage
+        .insert(written, H256::from_low_u64_be(8));
+
+    let mut accesses = EthAccesses::default();
+    accesses.accounts.insert(from);
+    accesses.accounts.insert(contract);
+    accesses
+        .storage_reads
+        .entry(contract)
+        .or_default()
+        .insert(read);
+    accesses
+        .storage_writes
+        .entry(contract)
+        .or_default()
+        .insert(written);
+    input.accesses = Some(accesses);
+
+    let (output, report) = minimize_test_vector(&input).unwrap();
+    assert_eq!(report.dropped_accounts, vec![untouched]);
+    assert!(!output.prestate.contains_key(&untouched));
+    let pre = &output.prestate[&contract];
+    assert_eq!(pre.storage.keys().collect::<Vec<_>>(), vec![&read]);
+    let post = &output.poststate[&contract];
+    assert_eq!(
+        post.storage.keys().collect::<Vec<_>>(),
+        vec![&read, &written]
+    );
+}
+
+#[tokio::test]
+async fn verify_minimized_with_embedded_evm() {
+    let from = H160::from_low_u64_be(0x1000);
+    let contract = H160::from_low_u64_be(0x2000);
+    let untouched = H160::from_low_u64_be(0x3000);
+    let mut prestate = EthState::new();
+    prestate.insert(
+        from,
+        EthAccountState {
+            balance: 1_000_000.into(),
+            ..Default::default()
+        },
+    );
+    let mut contract_state = EthAccountState {
+        // PUSH1 0x01 SLOAD PUSH1 0x01 ADD PUSH1 0x02 SSTORE STOP
+        code: Bytes::from(vec![
+            0x60, 0x01, 0x54, 0x60, 0x01, 0x01, 0x60, 0x02, 0x55, 0x00,
+        ]),
+        ..Default::default()
+    };
+    for key in 1..=3 {
+        contract_state
+            .storage
+            .insert(H256::from_low_u64_be(key), H256::from_low_u64_be(7));
+    }
+    prestate.insert(contract, contract_state);
+    prestate.insert(untouched, EthAccountState::default());
+    let input = synthesize_test_vector(&EvmInput {
+        prestate,
+        transaction: EvmTransaction {
+            from,
+            to: Some(contract),
+            gas: 100_000.into(),
+            gas_price: 1.into(),
+            ..Default::default()
+        },
+        block: Default::default(),
+    })
+    .await
+    .unwrap();
+
+    let (mut output, report) = minimize_test_vector(&input).unwrap();
+    assert_eq!(report.dropped_accounts, vec![untouched]);
+    verify_minimized(&input, &output, &EvmExecutor).unwrap();
+
+    // a poststate the execution doesn't yield is rejected
+    output
+        .poststate
+        .get_mut(&contract)
+        .unwrap()
+        .storage
+        .insert(H256::from_low_u64_be(2), H256::from_low_u64_be(9));
+    let error = verify_minimized(&input, &output, &EvmExecutor).unwrap_err();
+    assert!(
+        error.to_string().contains("changes the execution"),
+        "{}",
+        error
+    );
+
+    // nothing is verified if the execution differs from the original transaction, e.g.
+    // the coinbase is warm from Shanghai on, so it used less gas
+    let mut original = input.clone();
+    original.poststate.get_mut(&from).unwrap().balance += U256::from(2500);
+    let error = verify_minimized(&original, &output, &EvmExecutor).unwrap_err();
+    assert!(error.to_string().contains("can't be verified"), "{}", error);
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, HashMap};
+
+use cid::Cid;
+use ethers::types::{Bytes, H160};
+use ethers::utils::get_contract_address;
+use fevm_test_vectors::evm_state::State as EvmState;
+use fevm_test_vectors::extractor::types::{
+    EthAccountState, EthCallFrame, EthTransactionTestVector,
+};
+use fevm_test_vectors::load_evm_contract_input;
+use fevm_test_vectors::mock::{default_robust_address, Actor, Mock, MockConfig, MockError};
+use fevm_test_vectors::util::{compute_robust_address, get_code_cid_map};
+use fil_actor_init::State as InitState;
+use fil_actors_runtime::runtime::builtins::Type;
+use fil_actors_runtime::{EAM_ACTOR_ID, FIRST_NON_SINGLETON_ADDR, INIT_ACTOR_ADDR};
+use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
+use fvm_ipld_encoding::CborStore;
+use fvm_ipld_hamt::Hamt;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::HAMT_BIT_WIDTH;
+
+#[test]
+fn mock_reports_missing_actor_code() {
+    let store = MemoryBlockstore::new();
+    let mut mock = Mock::new(&store, BTreeMap::new());
+    match mock.mock_builtin_actor() {
+        Err(MockError::MissingActorCode(actor_type)) => assert_eq!(actor_type, Type::System),
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert!(matches!(
+        mock.get_actor(Address::new_id(1000)),
+        Err(MockError::ActorNotFound(_))
+    ));
+}
+
+fn delegated(byte: u8) -> Address {
+    Address::new_delegated(EAM_ACTOR_ID, &[byte; 20]).unwrap()
+}
+
+#[test]
+fn mock_skips_reserved_ids() {
+    let store = MemoryBlockstore::new();
+    let mut mock = Mock::new(&store, get_code_cid_map().unwrap());
+    mock.mock_builtin_actor().unwrap();
+    // the next ID of the Init actor and a later one are referenced as masked IDs
+    let (first, second) = (FIRST_NON_SINGLETON_ADDR, FIRST_NON_SINGLETON_ADDR + 2);
+    mock.reserve_actor_ids([first, second]).unwrap();
+    for id in [first, second] {
+        mock.mock_evm_actor_with_id(id, TokenAmount::zero())
+            .unwrap();
+    }
+
+    let mut ids = Vec::new();
+    for byte in [0xaa, 0xbb] {
+        let addr = delegated(byte);
+        mock.mock_evm_actor(addr, default_robust_address(&addr), TokenAmount::zero())
+            .unwrap();
+        ids.push(mock.normalize_address(&addr).unwrap());
+        // the robust address resolves to the same actor
+        assert_eq!(
+            mock.normalize_address(&default_robust_address(&addr))
+                .unwrap(),
+            *ids.last().unwrap()
+        );
+    }
+    assert_eq!(
+        ids,
+        vec![
+            Address::new_id(FIRST_NON_SINGLETON_ADDR + 1),
+            Address::new_id(FIRST_NON_SINGLETON_ADDR + 3)
+        ]
+    );
+
+    let addr = delegated(0xaa);
+    assert!(matches!(
+        mock.mock_evm_actor(addr, default_robust_address(&addr), TokenAmount::zero()),
+        Err(MockError::AddressInUse(_))
+    ));
+}
+
+#[test]
+fn mock_next_id_skips_reserved_ids() {
+    let store = MemoryBlockstore::new();
+    let mut mock = Mock::new(&store, get_code_cid_map().unwrap());
+    mock.mock_builtin_actor().unwrap();
+    // the last allocation lands right before a reserved ID
+    let reserved = FIRST_NON_SINGLETON_ADDR + 1;
+    mock.reserve_actor_ids([reserved]).unwrap();
+    mock.mock_evm_actor_with_id(reserved, TokenAmount::zero())
+        .unwrap();
+    let addr = delegated(0xaa);
+    mock.mock_evm_actor(addr, default_robust_address(&addr), TokenAmount::zero())
+        .unwrap();
+    assert_eq!(
+        mock.normalize_address(&addr).unwrap(),
+        Address::new_id(FIRST_NON_SINGLETON_ADDR)
+    );
+    mock.get_actors().unwrap();
+
+    // the snapshot has FVM allocate the ID after the reserved one
+    let init: InitState = mock.get_state(INIT_ACTOR_ADDR).unwrap();
+    assert_eq!(init.next_id, reserved + 1);
+}
+
+#[test]
+fn mock_masked_id_evm_state() {
+    let store = MemoryBlockstore::new();
+    let mut mock = Mock::new(&store, get_code_cid_map().unwrap());
+    mock.mock_builtin_actor().unwrap();
+    let id = FIRST_NON_SINGLETON_ADDR;
+    mock.reserve_actor_ids([id]).unwrap();
+    mock.mock_evm_actor_with_id(id, TokenAmount::zero())
+        .unwrap();
+    let storage = HashMap::from([(1.into(), 2.into())]);
+    mock.mock_evm_actor_state(&Address::new_id(id), storage, Some(vec![0x00]), Some(3))
+        .unwrap();
+    mock.get_actors().unwrap();
+
+    let actor = mock.get_actor(Address::new_id(id)).unwrap();
+    assert_eq!(actor.code, get_code_cid_map().unwrap()[&Type::EVM]);
+    assert_eq!(actor.predictable_address, None);
+    let state = EvmState::load(&store, &actor.head).unwrap().unwrap();
+    assert_eq!(state.nonce, 3);
+}
+
+/// The ID and EVM state of the actor `addr` resolves to in the `actors` HAMT.
+fn evm_actor(store: &MemoryBlockstore, actors: &Cid, addr: &Address) -> (Address, EvmState) {
+    let actors = Hamt::<_, Actor>::load_with_bit_width(actors, store, HAMT_BIT_WIDTH).unwrap();
+    let init = actors.get(&INIT_ACTOR_ADDR.to_bytes()).unwrap().unwrap();
+    let init_state: InitState = store.get_cbor(&init.head).unwrap().unwrap();
+    let id = init_state.resolve_address(store, addr).unwrap().unwrap();
+    let actor = actors.get(&id.to_bytes()).unwrap().unwrap();
+    (id, EvmState::load(store, &actor.head).unwrap().unwrap())
+}
+
+#[test]
+fn robust_address_derivation() {
+    let origin = delegated(0xaa);
+    // CBOR byte string of the origin, then the message sequence and the actor index
+    let mut bytes = vec![0x40 | origin.to_bytes().len() as u8];
+    bytes.extend(origin.to_bytes());
+    bytes.extend(7u64.to_be_bytes());
+    bytes.extend(1u64.to_be_bytes());
+    assert_eq!(
+        compute_robust_address(&origin, 7, 1),
+        Address::new_actor(&bytes)
+    );
+    assert_ne!(
+        compute_robust_address(&origin, 7, 1),
+        compute_robust_address(&origin, 7, 0)
+    );
+    assert_ne!(
+        compute_robust_address(&origin, 7, 1),
+        compute_robust_address(&origin, 8, 1)
+    );
+}
+
+#[test]
+fn mock_created_contracts() {
+    let from = H160::repeat_byte(0xaa);
+    let contract = H160::repeat_byte(0xbb);
+    // the first creation reverts, the second one deploys a contract
+    let reverted = get_contract_address(contract, 1);
+    let created = get_contract_address(contract, 2);
+    let code = Bytes::from(vec![0x00]);
+    let account = |nonce: u64, code: &Bytes| EthAccountState {
+        nonce,
+        code: code.clone(),
+        ..Default::default()
+    };
+    let create = |callee: H160, reverted: bool| EthCallFrame {
+        call_type: String::from("CREATE"),
+        caller: contract,
+        callee,
+        reverted,
+        ..Default::default()
+    };
+    let input = EthTransactionTestVector {
+        nonce: 7,
+        from,
+        to: contract,
+        prestate: BTreeMap::from([
+            (from, account(7, &Bytes::default())),
+            (contract, account(1, &code)),
+        ]),
+        poststate: BTreeMap::from([
+            (from, account(8, &Bytes::default())),
+            (contract, account(3, &code)),
+            (created, account(1, &code)),
+        ]),
+        call_tree: Some(EthCallFrame {
+            call_type: String::from("CALL"),
+            caller: from,
+            callee: contract,
+            calls: vec![create(reverted, true), create(created, false)],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let store = MemoryBlockstore::new();
+    let (pre_actors, post_actors, _) = load_evm_contract_input(
+        &store,
+        get_code_cid_map().unwrap(),
+        &input,
+        &MockConfig::default(),
+    )
+    .unwrap();
+
+    // the EVM nonce is carried into the EVM states
+    let contract_addr = Address::new_delegated(EAM_ACTOR_ID, &contract.0).unwrap();
+    assert_eq!(evm_actor(&store, &pre_actors, &contract_addr).1.nonce, 1);
+    assert_eq!(evm_actor(&store, &post_actors, &contract_addr).1.nonce, 3);
+
+    // the reverted creation still takes an index of the message
+    let from_addr = Address::new_delegated(EAM_ACTOR_ID, &from.0).unwrap();
+    let created_addr = Address::new_delegated(EAM_ACTOR_ID, &created.0).unwrap();
+    let (id, state) = evm_actor(&store, &post_actors, &created_addr);
+    assert_eq!(state.nonce, 1);
+    let robust_address = compute_robust_address(&from_addr, 7, 1);
+    assert_eq!(evm_actor(&store, &post_actors, &robust_address).0, id);
+}
+
+#[test]
+fn mock_empty_evm_state() {
+    let store = MemoryBlockstore::new();
+    let mut mock = Mock::new(&store, get_code_cid_map().unwrap());
+    mock.mock_builtin_actor().unwrap();
+    let addr = delegated(0xaa);
+    mock.mock_evm_actor(addr, default_robust_address(&addr), TokenAmount::zero())
+        .unwrap();
+    // nonce 1, no code and no storage are the values of a new EVM state
+    mock.mock_evm_actor_state(&addr, HashMap::new(), Some(Vec::new()), Some(1))
+        .unwrap();
+    let actors = mock.get_actors().unwrap();
+
+    let (_, state) = evm_actor(&store, &actors, &addr);
+    assert_eq!(state.nonce, 1);
+    assert_eq!(store.get(&state.bytecode).unwrap(), Some(Vec::new()));
+}
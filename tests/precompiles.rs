@@ -0,0 +1,65 @@
+use ethers::types::H160;
+use fevm_test_vectors::extractor::precompiles::{fevm_difference, precompile, PrecompileKind};
+use fevm_test_vectors::extractor::types::EthPrecompileCall;
+
+fn fevm_precompile(id: u64) -> H160 {
+    let mut address = H160::from_low_u64_be(id);
+    address.0[0] = 0xfe;
+    address
+}
+
+#[test]
+fn detect_precompiles() {
+    assert_eq!(
+        precompile(&H160::from_low_u64_be(0x01)),
+        Some((PrecompileKind::Ethereum, "ecrecover"))
+    );
+    assert_eq!(
+        precompile(&fevm_precompile(0x03)),
+        Some((PrecompileKind::Fevm, "call_actor"))
+    );
+    assert_eq!(precompile(&H160::zero()), None);
+    // point evaluation is Cancun
+    assert_eq!(precompile(&H160::from_low_u64_be(0x0a)), None);
+    assert_eq!(precompile(&H160::from_low_u64_be(0x0b)), None);
+    assert_eq!(precompile(&fevm_precompile(0x06)), None);
+
+    // only the first byte may be non zero
+    let mut address = H160::from_low_u64_be(0x01);
+    address.0[5] = 1;
+    assert_eq!(precompile(&address), None);
+}
+
+#[test]
+fn fevm_differences() {
+    let call = |address: H160, call_type: &str, value: u64| EthPrecompileCall {
+        address,
+        call_type: call_type.to_owned(),
+        value: value.into(),
+        ..Default::default()
+    };
+    let identity = H160::from_low_u64_be(0x04);
+    assert_eq!(fevm_difference(&call(identity, "STATICCALL", 0)), None);
+    assert_eq!(fevm_difference(&call(identity, "CALL", 0)), None);
+    assert_eq!(
+        fevm_difference(&call(identity, "CALL", 1)).unwrap(),
+        "value transferred to identity precompile"
+    );
+    assert_eq!(
+        fevm_difference(&call(identity, "DELEGATECALL", 0)).unwrap(),
+        "identity precompile called by DELEGATECALL"
+    );
+    assert_eq!(
+        fevm_difference(&call(identity, "CALLCODE", 0)).unwrap(),
+        "identity precompile called by CALLCODE"
+    );
+    assert_eq!(
+        fevm_difference(&call(fevm_precompile(0x01), "STATICCALL", 0)).unwrap(),
+        "resolve_address is a FEVM precompile, Ethereum executed it as an empty account"
+    );
+    // not a precompile
+    assert_eq!(
+        fevm_difference(&call(H160::from_low_u64_be(0xbb), "CALL", 1)),
+        None
+    );
+}
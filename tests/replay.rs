@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+
+use ethers::types::{Block, Bytes, Transaction, H160, H256, U256, U64};
+use fevm_test_vectors::extractor::replay::extract_test_vector;
+use fevm_test_vectors::extractor::source::{FixtureSource, TraceFixture};
+use fevm_test_vectors::extractor::types::{EthAccountState, EthState};
+
+fn fixture() -> TraceFixture {
+    let sender = H160::from_low_u64_be(0xaa);
+    let contract = H160::from_low_u64_be(0xbb);
+    let transaction = Transaction {
+        hash: H256::from_low_u64_be(1),
+        nonce: 3.into(),
+        block_hash: Some(H256::from_low_u64_be(2)),
+        block_number: Some(U64::from(100)),
+        from: sender,
+        to: Some(contract),
+        value: 10.into(),
+        gas_price: Some(1.into()),
+        gas: 100_000.into(),
+        chain_id: Some(1.into()),
+        ..Default::default()
+    };
+    let block = Block {
+        hash: Some(H256::from_low_u64_be(2)),
+        number: Some(U64::from(100)),
+        author: Some(H160::from_low_u64_be(0xcc)),
+        difficulty: 1.into(),
+        transactions: vec![transaction.clone()],
+        ..Default::default()
+    };
+    let mut prestate = EthState::new();
+    prestate.insert(
+        sender,
+        EthAccountState {
+            nonce: 3,
+            balance: 1_000_000.into(),
+            ..Default::default()
+        },
+    );
+    prestate.insert(
+        contract,
+        EthAccountState {
+            // PUSH1 0x2a PUSH1 0x01 SSTORE STOP
+            code: Bytes::from(vec![0x60, 0x2a, 0x60, 0x01, 0x55, 0x00]),
+            ..Default::default()
+        },
+    );
+    let trace = serde_json::json!({
+        "gas": 43000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "PUSH1", "gas": 79000, "gasCost": 3, "depth": 1, "stack": []},
+            {"pc": 2, "op": "PUSH1", "gas": 78997, "gasCost": 3, "depth": 1, "stack": ["0x2a"]},
+            {"pc": 4, "op": "SSTORE", "gas": 78994, "gasCost": 22100, "depth": 1, "stack": ["0x2a", "0x1"]},
+            {"pc": 5, "op": "STOP", "gas": 56894, "gasCost": 0, "depth": 1, "stack": []}
+        ]
+    });
+    TraceFixture {
+        transaction,
+        block,
+        prestate,
+        trace,
+        calls: None,
+        codes: BTreeMap::new(),
+    }
+}
+
+#[tokio::test]
+async fn replay_recorded_fixture() {
+    let path = std::env::temp_dir().join(format!("fevm-trace-fixture-{}.json", std::process::id()));
+    std::fs::write(&path, serde_json::to_vec(&fixture()).unwrap()).unwrap();
+    let source = FixtureSource::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let vector = extract_test_vector(&source, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+    assert_eq!(vector.status, 1);
+    assert_eq!(vector.gas_used, U256::from(43000));
+    assert_eq!(vector.block_hashes[&100], H256::from_low_u64_be(2));
+
+    // gas fee and value are taken from the sender, the slot is written
+    let sender = &vector.poststate[&H160::from_low_u64_be(0xaa)];
+    assert_eq!(sender.nonce, 4);
+    assert_eq!(sender.balance, U256::from(1_000_000 - 43000 - 10));
+    let contract = &vector.poststate[&H160::from_low_u64_be(0xbb)];
+    assert_eq!(contract.balance, U256::from(10));
+    assert_eq!(
+        contract.storage.get(&H256::from_low_u64_be(1)),
+        Some(&H256::from_low_u64_be(0x2a))
+    );
+
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.call_type, "CALL");
+    assert!(!call_tree.reverted);
+    assert_eq!(call_tree.storage_writes.len(), 1);
+}
+
+/// The contract creates a contract whose creation code writes a slot then reverts,
+/// `created` is the address the trace reports.
+fn create_fixture(created: &str) -> TraceFixture {
+    let mut fixture = fixture();
+    fixture.trace = serde_json::json!({
+        "gas": 50000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "CREATE", "gas": 79000, "gasCost": 32000, "depth": 1, "stack": ["0x0", "0x0", "0x0"]},
+            {"pc": 0, "op": "SSTORE", "gas": 46000, "gasCost": 22100, "depth": 2, "stack": ["0x1", "0x1"]},
+            {"pc": 1, "op": "REVERT", "gas": 23900, "gasCost": 0, "depth": 2, "stack": ["0x0", "0x0"]},
+            {"pc": 1, "op": "STOP", "gas": 40000, "gasCost": 0, "depth": 1, "stack": [created]}
+        ]
+    });
+    fixture
+}
+
+#[tokio::test]
+async fn replay_failed_create() {
+    let source = FixtureSource {
+        fixture: create_fixture("0x0"),
+    };
+    let vector = extract_test_vector(&source, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+
+    let contract = H160::from_low_u64_be(0xbb);
+    let created = ethers::utils::get_contract_address(contract, 0);
+    // the nonce is increased, but the failed creation leaves no account behind
+    assert_eq!(vector.poststate[&contract].nonce, 1);
+    assert!(!vector.poststate.contains_key(&H160::zero()));
+    assert!(!vector.poststate.contains_key(&created));
+
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.calls.len(), 1);
+    assert_eq!(call_tree.calls[0].call_type, "CREATE");
+    assert_eq!(call_tree.calls[0].callee, created);
+    assert!(call_tree.calls[0].reverted);
+}
+
+#[tokio::test]
+async fn replay_create_address_mismatch() {
+    let source = FixtureSource {
+        fixture: create_fixture("0xcc"),
+    };
+    let error = extract_test_vector(&source, H256::from_low_u64_be(1))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("CREATE at pc 0 created"));
+}
+
+/// The contract runs CREATE2 with salt 7 on the 2 bytes of creation code at the start of
+/// its memory, which the trace doesn't capture. The callTracer reports `created` running
+/// `init_code`, which reverts if `reverts`.
+fn create2_fixture(reverts: bool, created: H160, init_code: &[u8]) -> TraceFixture {
+    let mut fixture = fixture();
+    let (creation, result) = if reverts {
+        (
+            serde_json::json!({"pc": 0, "op": "REVERT", "gas": 46000, "gasCost": 0, "depth": 2, "stack": ["0x0", "0x0"]}),
+            U256::zero(),
+        )
+    } else {
+        (
+            serde_json::json!({"pc": 0, "op": "STOP", "gas": 46000, "gasCost": 0, "depth": 2, "stack": []}),
+            U256::from_big_endian(created.as_bytes()),
+        )
+    };
+    fixture.trace = serde_json::json!({
+        "gas": 50000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "CREATE2", "gas": 79000, "gasCost": 32006, "depth": 1, "stack": ["0x7", "0x2", "0x0", "0x0"]},
+            creation,
+            {"pc": 1, "op": "STOP", "gas": 40000, "gasCost": 0, "depth": 1, "stack": [result]}
+        ]
+    });
+    fixture.calls = Some(
+        serde_json::from_value(serde_json::json!({
+            "type": "CALL",
+            "calls": [{
+                "type": "CREATE2",
+                "from": H160::from_low_u64_be(0xbb),
+                "to": created,
+                "input": Bytes::from(init_code.to_vec())
+            }]
+        }))
+        .unwrap(),
+    );
+    fixture.codes.insert(created, Bytes::from(vec![0x00]));
+    fixture
+}
+
+#[tokio::test]
+async fn replay_failed_create2_without_memory() {
+    let contract = H160::from_low_u64_be(0xbb);
+    // Parity reports failed creations at the zero address
+    for created in [H160::from_low_u64_be(0xdd), H160::zero()] {
+        let source = FixtureSource {
+            fixture: create2_fixture(true, created, &[0x60, 0x00]),
+        };
+        let vector = extract_test_vector(&source, H256::from_low_u64_be(1))
+            .await
+            .unwrap();
+
+        assert_eq!(vector.poststate[&contract].nonce, 1);
+        assert!(!vector.poststate.contains_key(&H160::zero()));
+        assert!(!vector.poststate.contains_key(&created));
+
+        let call = &vector.call_tree.unwrap().calls[0];
+        assert_eq!(call.call_type, "CREATE2");
+        assert_eq!(call.callee, created);
+        assert_eq!(call.salt, Some(H256::from_low_u64_be(7)));
+        assert!(call.reverted);
+    }
+}
+
+#[tokio::test]
+async fn replay_create2_checks_traced_init_code() {
+    let contract = H160::from_low_u64_be(0xbb);
+    let init_code = [0x60, 0x00];
+    let created = ethers::utils::get_create2_address_from_hash(
+        contract,
+        H256::from_low_u64_be(7).as_bytes().to_vec(),
+        ethers::utils::keccak256(init_code),
+    );
+    let source = FixtureSource {
+        fixture: create2_fixture(false, created, &init_code),
+    };
+    let vector = extract_test_vector(&source, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+    assert_eq!(vector.poststate[&created].nonce, 1);
+    assert_eq!(vector.poststate[&created].code.to_vec(), vec![0x00]);
+    let call = &vector.call_tree.unwrap().calls[0];
+    assert_eq!(call.callee, created);
+    assert_eq!(call.input.to_vec(), init_code.to_vec());
+
+    // the address the trace reports doesn't come from the traced creation code
+    let source = FixtureSource {
+        fixture: create2_fixture(false, created, &[0x60, 0x01]),
+    };
+    let error = extract_test_vector(&source, H256::from_low_u64_be(1))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("CREATE2 created"), "{}", error);
+}
+
+/// The contract calls 0xdd, which writes a slot, delegates to 0xee writing another slot
+/// of 0xdd, then reverts. Back in the contract a slot is written.
+fn nested_fixture() -> TraceFixture {
+    let mut fixture = fixture();
+    for address in [0xdd, 0xee] {
+        fixture
+            .prestate
+            .insert(H160::from_low_u64_be(address), EthAccountState::default());
+    }
+    fixture.trace = serde_json::json!({
+        "gas": 60000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "CALL", "gas": 79000, "gasCost": 2600, "depth": 1, "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xdd", "0x4000"]},
+            {"pc": 0, "op": "SSTORE", "gas": 16384, "gasCost": 22100, "depth": 2, "stack": ["0x5", "0x1"]},
+            {"pc": 1, "op": "DELEGATECALL", "gas": 14000, "gasCost": 2600, "depth": 2, "stack": ["0x0", "0x0", "0x0", "0x0", "0xee", "0x2000"]},
+            {"pc": 0, "op": "SSTORE", "gas": 8192, "gasCost": 22100, "depth": 3, "stack": ["0x6", "0x2"]},
+            {"pc": 1, "op": "STOP", "gas": 6000, "gasCost": 0, "depth": 3, "stack": []},
+            {"pc": 2, "op": "POP", "gas": 9000, "gasCost": 2, "depth": 2, "stack": ["0x1"]},
+            {"pc": 3, "op": "REVERT", "gas": 8998, "gasCost": 0, "depth": 2, "stack": ["0x0", "0x0"]},
+            {"pc": 1, "op": "POP", "gas": 70000, "gasCost": 2, "depth": 1, "stack": ["0x0"]},
+            {"pc": 2, "op": "SSTORE", "gas": 69998, "gasCost": 22100, "depth": 1, "stack": ["0x7", "0x3"]},
+            {"pc": 3, "op": "STOP", "gas": 47898, "gasCost": 0, "depth": 1, "stack": []}
+        ]
+    });
+    fixture
+}
+
+#[tokio::test]
+async fn replay_nested_revert() {
+    let mut fixture = nested_fixture();
+    fixture.calls = Some(
+        serde_json::from_value(serde_json::json!({
+            "type": "CALL",
+            "input": "0x01",
+            "calls": [{
+                "type": "CALL",
+                "input": "0x02",
+                "output": "0x08c379a0",
+                "calls": [{"type": "DELEGATECALL", "input": "0x03", "gasUsed": "0x5654"}]
+            }]
+        }))
+        .unwrap(),
+    );
+    let vector = extract_test_vector(&FixtureSource { fixture }, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+
+    // the writes of the reverted call and of its delegate call are dropped
+    assert!(vector.poststate[&H160::from_low_u64_be(0xdd)]
+        .storage
+        .is_empty());
+    let contract = &vector.poststate[&H160::from_low_u64_be(0xbb)];
+    assert_eq!(
+        contract.storage,
+        BTreeMap::from([(H256::from_low_u64_be(3), H256::from_low_u64_be(7))])
+    );
+
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.input.to_vec(), vec![0x01]);
+    assert!(!call_tree.reverted);
+    assert_eq!(call_tree.calls.len(), 1);
+    let call = &call_tree.calls[0];
+    assert_eq!(call.call_type, "CALL");
+    assert_eq!(call.callee, H160::from_low_u64_be(0xdd));
+    assert_eq!(call.gas, 16384);
+    assert!(call.reverted);
+    assert_eq!(call.input.to_vec(), vec![0x02]);
+    assert_eq!(call.output.to_vec(), vec![0x08, 0xc3, 0x79, 0xa0]);
+    assert_eq!(call.storage_writes.len(), 1);
+    assert_eq!(call.calls.len(), 1);
+    let delegate = &call.calls[0];
+    assert_eq!(delegate.call_type, "DELEGATECALL");
+    assert_eq!(delegate.caller, H160::from_low_u64_be(0xdd));
+    assert_eq!(delegate.callee, H160::from_low_u64_be(0xee));
+    assert!(!delegate.reverted);
+    assert_eq!(delegate.input.to_vec(), vec![0x03]);
+    assert_eq!(delegate.gas_used, 0x5654);
+    assert_eq!(
+        delegate.storage_writes.get(&H256::from_low_u64_be(2)),
+        Some(&H256::from_low_u64_be(6))
+    );
+}
+
+#[tokio::test]
+async fn replay_faulted_call() {
+    let mut fixture = fixture();
+    fixture.prestate.insert(
+        H160::from_low_u64_be(0xdd),
+        EthAccountState {
+            balance: 100.into(),
+            ..Default::default()
+        },
+    );
+    // 0xdd writes a slot then runs out of gas on a CALL sending value to 0xee
+    fixture.trace = serde_json::json!({
+        "gas": 60000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "CALL", "gas": 79000, "gasCost": 2600, "depth": 1, "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xdd", "0x8000"]},
+            {"pc": 0, "op": "SSTORE", "gas": 32768, "gasCost": 22100, "depth": 2, "stack": ["0x5", "0x1"]},
+            {"pc": 1, "op": "CALL", "gas": 10668, "gasCost": 34300, "depth": 2, "stack": ["0x0", "0x0", "0x0", "0x0", "0x5", "0xee", "0xffff"], "error": "out of gas"},
+            {"pc": 1, "op": "STOP", "gas": 46000, "gasCost": 0, "depth": 1, "stack": ["0x0"]}
+        ]
+    });
+    let vector = extract_test_vector(&FixtureSource { fixture }, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+
+    // the faulted call never starts, the frame running it is reverted
+    let callee = &vector.poststate[&H160::from_low_u64_be(0xdd)];
+    assert!(callee.storage.is_empty());
+    assert_eq!(callee.balance, U256::from(100));
+    assert!(!vector.poststate.contains_key(&H160::from_low_u64_be(0xee)));
+
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.calls.len(), 1);
+    assert!(call_tree.calls[0].reverted);
+    assert!(call_tree.calls[0].calls.is_empty());
+}
+
+#[tokio::test]
+async fn replay_call_trace_mismatch() {
+    let mut fixture = nested_fixture();
+    // the callTracer disagrees on the type of the inner call
+    fixture.calls = Some(
+        serde_json::from_value(serde_json::json!({
+            "type": "CALL",
+            "input": "0x01",
+            "calls": [{"type": "STATICCALL", "input": "0x02"}]
+        }))
+        .unwrap(),
+    );
+    let vector = extract_test_vector(&FixtureSource { fixture }, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+
+    // the matching frames are merged, the mismatching subtree is kept as replayed
+    let call_tree = vector.call_tree.unwrap();
+    assert_eq!(call_tree.input.to_vec(), vec![0x01]);
+    let call = &call_tree.calls[0];
+    assert_eq!(call.call_type, "CALL");
+    assert!(call.input.is_empty());
+    assert_eq!(call.calls[0].call_type, "DELEGATECALL");
+}
+
+#[tokio::test]
+async fn replay_precompile_calls() {
+    let mut fixture = fixture();
+    let sha256 = H160::from_low_u64_be(0x02);
+    let identity = H160::from_low_u64_be(0x04);
+    fixture.prestate.insert(sha256, EthAccountState::default());
+    // precompiles run without logs of their own
+    fixture.trace = serde_json::json!({
+        "gas": 30000,
+        "failed": false,
+        "returnValue": "",
+        "structLogs": [
+            {"pc": 0, "op": "STATICCALL", "gas": 79000, "gasCost": 2600, "depth": 1, "stack": ["0x0", "0x0", "0x0", "0x0", "0x4", "0x1000"]},
+            {"pc": 1, "op": "POP", "gas": 76000, "gasCost": 2, "depth": 1, "stack": ["0x1"]},
+            {"pc": 2, "op": "CALL", "gas": 75998, "gasCost": 9000, "depth": 1, "stack": ["0x0", "0x0", "0x0", "0x0", "0x5", "0x2", "0x1000"]},
+            {"pc": 3, "op": "STOP", "gas": 60000, "gasCost": 0, "depth": 1, "stack": ["0x1"]}
+        ]
+    });
+    let vector = extract_test_vector(&FixtureSource { fixture }, H256::from_low_u64_be(1))
+        .await
+        .unwrap();
+
+    let calls: Vec<_> = vector
+        .precompile_calls
+        .iter()
+        .map(|call| {
+            (
+                call.address,
+                call.name.as_str(),
+                call.call_type.as_str(),
+                call.value,
+            )
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        vec![
+            (identity, "identity", "STATICCALL", U256::zero()),
+            (sha256, "sha256", "CALL", U256::from(5)),
+        ]
+    );
+    assert!(vector.precompile_calls.iter().all(|call| call.success));
+    // precompiles are not accounts on FEVM
+    assert!(!vector.prestate.contains_key(&sha256));
+    assert!(!vector.poststate.contains_key(&sha256));
+    assert_eq!(
+        vector.poststate[&H160::from_low_u64_be(0xbb)].balance,
+        U256::from(5)
+    );
+}
@@ -0,0 +1,63 @@
+use ethers::types::H256;
+use fevm_test_vectors::extractor::cache::RpcCache;
+
+#[test]
+fn rpc_cache_shares_and_evicts_responses() {
+    let dir = std::env::temp_dir().join(format!("fevm-rpc-cache-{}", std::process::id()));
+    let cache = RpcCache::open(&dir, None).unwrap();
+    cache.clear().unwrap();
+    let tx1 = H256::from_low_u64_be(1);
+    let tx2 = H256::from_low_u64_be(2);
+
+    assert_eq!(cache.get(&tx1, "eth_chainId[]").unwrap(), None);
+    cache.put(&tx1, "eth_chainId[]", b"\"0x1\"").unwrap();
+    cache.put(&tx2, "eth_chainId[]", b"\"0x1\"").unwrap();
+    cache.put(&tx2, "eth_getCode[]", b"\"0x6000\"").unwrap();
+    assert_eq!(
+        cache.get(&tx1, "eth_chainId[]").unwrap(),
+        Some(b"\"0x1\"".to_vec())
+    );
+    let info = cache.info().unwrap();
+    assert_eq!(info.transactions, 2);
+    // identical responses are stored once
+    assert_eq!(info.blobs, 2);
+
+    assert!(cache.invalidate(&tx1).unwrap());
+    assert!(!cache.invalidate(&tx1).unwrap());
+    assert_eq!(cache.get(&tx1, "eth_chainId[]").unwrap(), None);
+    assert_eq!(cache.info().unwrap().blobs, 2);
+
+    cache.prune(0).unwrap();
+    let info = cache.info().unwrap();
+    assert_eq!((info.transactions, info.blobs, info.size), (0, 0, 0));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rpc_cache_keeps_size_bound() {
+    let dir = std::env::temp_dir().join(format!("fevm-rpc-cache-bound-{}", std::process::id()));
+    let cache = RpcCache::open(&dir, Some(15)).unwrap();
+    cache.clear().unwrap();
+    let txs: Vec<H256> = (1..=3).map(H256::from_low_u64_be).collect();
+
+    // 10 bytes per transaction, the oldest one is evicted when the next one is added
+    for (i, tx) in txs.iter().enumerate() {
+        cache
+            .put(tx, "eth_getCode[]", format!("\"0x{:06}\"", i).as_bytes())
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let info = cache.info().unwrap();
+    assert_eq!((info.transactions, info.blobs, info.size), (1, 1, 10));
+    assert_eq!(cache.get(&txs[1], "eth_getCode[]").unwrap(), None);
+    assert!(cache.get(&txs[2], "eth_getCode[]").unwrap().is_some());
+
+    // the same response again takes no space
+    cache
+        .put(&txs[0], "eth_getCode[]", b"\"0x000002\"")
+        .unwrap();
+    assert_eq!(cache.info().unwrap().transactions, 2);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use common::{Reply, Requests};
+use ethers::providers::{Http, Middleware, Provider};
+use fevm_test_vectors::extractor::policy::{PolicyClient, RpcPolicy};
+use serde_json::json;
+
+/// Fake JSON-RPC node answering every request with block number 0x10, after `delay`.
+/// The first `failures` requests get a 503 error page instead.
+async fn fake_node(failures: usize, delay: Duration) -> (String, Requests) {
+    common::fake_node(delay, move |n, _| {
+        if n < failures {
+            Reply::Page(
+                "503 Service Unavailable",
+                "<html>overloaded</html>".to_owned(),
+            )
+        } else {
+            Reply::Result(json!("0x10"))
+        }
+    })
+    .await
+}
+
+fn provider(url: &str, policy: RpcPolicy) -> Provider<PolicyClient<Http>> {
+    Provider::new(PolicyClient::new(Http::from_str(url).unwrap(), policy))
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let (url, requests) = fake_node(2, Duration::ZERO).await;
+    let policy = RpcPolicy {
+        retries: 3,
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let block = provider(&url, policy).get_block_number().await.unwrap();
+    assert_eq!(block.as_u64(), 0x10);
+    assert_eq!(requests.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn retries_are_bounded() {
+    let (url, requests) = fake_node(5, Duration::ZERO).await;
+    let policy = RpcPolicy {
+        retries: 1,
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    assert!(provider(&url, policy).get_block_number().await.is_err());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn malformed_answers_are_not_retried() {
+    let (url, requests) = common::fake_node(Duration::ZERO, |_, _| {
+        Reply::Page("200 OK", json!({"unexpected": true}).to_string())
+    })
+    .await;
+    let policy = RpcPolicy {
+        retries: 3,
+        backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    assert!(provider(&url, policy).get_block_number().await.is_err());
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn slow_requests_time_out() {
+    let (url, _) = fake_node(0, Duration::from_millis(500)).await;
+    let policy = RpcPolicy {
+        retries: 0,
+        timeout: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let err = provider(&url, policy).get_block_number().await.unwrap_err();
+    assert!(err.to_string().contains("timed out"), "{}", err);
+}
+
+#[tokio::test]
+async fn rate_limit_is_shared_by_concurrent_requests() {
+    let (url, requests) = fake_node(0, Duration::ZERO).await;
+    let policy = RpcPolicy {
+        requests_per_second: Some(20.0),
+        ..Default::default()
+    };
+    let provider = provider(&url, policy);
+    let started = Instant::now();
+    let blocks = futures::future::join_all((0..5).map(|_| provider.get_block_number())).await;
+    assert!(blocks.iter().all(|block| block.is_ok()));
+    assert_eq!(requests.lock().unwrap().len(), 5);
+    // 5 requests 50ms apart
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
@@ -0,0 +1,152 @@
+use ethers::types::{H160, H256, U256};
+use ethers::utils::keccak256;
+use fevm_test_vectors::export_test_vector_file;
+use fevm_test_vectors::mock::MockConfig;
+use fevm_test_vectors::scenario::{compile_scenario, load_scenario, Scenario};
+use fil_actor_evm::EVM_CONTRACT_REVERTED;
+
+const TRANSFER: &str = r#"
+labels:
+  balances: 0
+accounts:
+  alice: { address: "0x00000000000000000000000000000000000000aa", balance: 1 ether, nonce: 1 }
+  bob: { address: "0x00000000000000000000000000000000000000bb" }
+  token:
+    address: create(@alice, 0)
+    code: "0x00"
+    storage:
+      mapping($balances, @alice): 100
+transaction:
+  from: "@alice"
+  to: "@token"
+  gas: 100000
+  gasPrice: 1 gwei
+  call: { function: "transfer(address,uint256)", args: ["@bob", 10] }
+expect:
+  gasUsed: 51000
+  poststate:
+    alice: { nonce: 2, balance: 0.999949 ether }
+    token:
+      storage:
+        mapping($balances, @alice): 90
+        mapping($balances, @bob): 10
+"#;
+
+fn balance_slot(holder: u64) -> H256 {
+    let mut preimage = [0; 64];
+    preimage[12..32].copy_from_slice(H160::from_low_u64_be(holder).as_bytes());
+    H256(keccak256(preimage))
+}
+
+#[test]
+fn compile_transfer() {
+    let scenario: Scenario = serde_yaml::from_str(TRANSFER).unwrap();
+    let vector = compile_scenario(&scenario).unwrap();
+
+    let alice = H160::from_low_u64_be(0xaa);
+    let token = ethers::utils::get_contract_address(alice, 0);
+    assert_eq!(vector.from, alice);
+    assert_eq!(vector.to, token);
+    assert_eq!(vector.nonce, 1);
+    assert_eq!(vector.gas_price, U256::exp10(9));
+    assert_eq!(vector.status, 1);
+
+    // transfer(0xbb, 10)
+    let mut input = hex::decode("a9059cbb").unwrap();
+    input.extend(H256::from_low_u64_be(0xbb).as_bytes());
+    input.extend(H256::from_low_u64_be(10).as_bytes());
+    assert_eq!(vector.input.to_vec(), input);
+
+    assert_eq!(
+        vector.prestate[&token].storage[&balance_slot(0xaa)],
+        H256::from_low_u64_be(100)
+    );
+    let post = &vector.poststate[&token];
+    assert_eq!(post.storage[&balance_slot(0xaa)], H256::from_low_u64_be(90));
+    assert_eq!(post.storage[&balance_slot(0xbb)], H256::from_low_u64_be(10));
+    assert_eq!(post.code.to_vec(), vec![0x00]);
+    assert_eq!(vector.poststate[&alice].nonce, 2);
+    assert_eq!(
+        vector.poststate[&alice].balance,
+        U256::exp10(18) - U256::from(51000) * U256::exp10(9)
+    );
+}
+
+#[test]
+fn load_toml_scenario() {
+    let path = std::env::temp_dir().join(format!("fevm-scenario-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+[accounts.alice]
+balance = "1000000"
+
+[transaction]
+from = "@alice"
+to = "@bob"
+value = 10
+gas = 21000
+
+[expect]
+gasUsed = 21000
+
+[expect.poststate.alice]
+nonce = 1
+balance = "999990"
+
+[expect.poststate.bob]
+address = "0x00000000000000000000000000000000000000bb"
+balance = 10
+"#,
+    )
+    .unwrap();
+    let scenario = load_scenario(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let vector = compile_scenario(&scenario).unwrap();
+    let bob = H160::from_low_u64_be(0xbb);
+    assert_eq!(vector.to, bob);
+    assert!(!vector.prestate.contains_key(&bob));
+    assert_eq!(vector.poststate[&bob].balance, U256::from(10));
+    assert_eq!(vector.poststate[&vector.from].balance, U256::from(999_990));
+}
+
+const REVERT: &str = r#"
+accounts:
+  alice: { address: "0x00000000000000000000000000000000000000aa", balance: 1 ether, nonce: 1 }
+  vault: { address: "0x00000000000000000000000000000000000000bb", code: "0x60006000fd" }
+transaction:
+  from: "@alice"
+  to: "@vault"
+  value: 10
+  gas: 100000
+  gasPrice: 1 gwei
+expect:
+  success: false
+  gasUsed: 21006
+  poststate:
+    alice: { nonce: 2, balance: 0.999978994 ether }
+"#;
+
+#[async_std::test]
+async fn export_reverted_scenario() {
+    let scenario: Scenario = serde_yaml::from_str(REVERT).unwrap();
+    let vector = compile_scenario(&scenario).unwrap();
+    assert_eq!(vector.status, 0);
+    // the value stays with the sender
+    assert_eq!(
+        vector.poststate[&H160::from_low_u64_be(0xbb)].balance,
+        U256::zero()
+    );
+
+    let path = std::env::temp_dir().join(format!("fevm-scenario-{}.json", std::process::id()));
+    export_test_vector_file(vector, path.clone(), &MockConfig::default())
+        .await
+        .unwrap();
+    let vector: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        vector["postconditions"]["receipts"][0]["exit_code"],
+        EVM_CONTRACT_REVERTED.value()
+    );
+}
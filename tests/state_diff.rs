@@ -0,0 +1,263 @@
+mod common;
+
+use std::time::Duration;
+
+use common::Reply;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Bytes, Transaction, TransactionReceipt, H160, H256, U256, U64};
+use fevm_test_vectors::extractor::backend::Backend;
+use fevm_test_vectors::extractor::state_diff::{
+    call_tree, struct_logs, Diff, ReplayTrace, VmAccesses,
+};
+use fevm_test_vectors::extractor::transaction::extract_eth_transaction_test_vector;
+use serde_json::{json, Value};
+
+const REPLAY: &str = r#"{
+    "output": "0x",
+    "stateDiff": {
+        "0x00000000000000000000000000000000000000aa": {
+            "balance": "=",
+            "nonce": {"*": {"from": "0x1", "to": "0x2"}},
+            "code": "=",
+            "storage": {
+                "0x0000000000000000000000000000000000000000000000000000000000000002": {
+                    "+": "0x000000000000000000000000000000000000000000000000000000000000002a"
+                }
+            }
+        }
+    },
+    "trace": [
+        {
+            "type": "call",
+            "action": {"callType": "call", "from": "0x00000000000000000000000000000000000000aa", "to": "0x00000000000000000000000000000000000000bb", "value": "0x0", "gas": "0x100", "input": "0x"},
+            "result": {"gasUsed": "0x10", "output": "0x"},
+            "traceAddress": []
+        },
+        {
+            "type": "create",
+            "action": {"from": "0x00000000000000000000000000000000000000bb", "value": "0x0", "gas": "0x80", "init": "0x6000"},
+            "result": {"gasUsed": "0x8", "code": "0x00", "address": "0x00000000000000000000000000000000000000cc"},
+            "traceAddress": [0]
+        },
+        {
+            "type": "call",
+            "action": {"callType": "staticcall", "from": "0x00000000000000000000000000000000000000bb", "to": "0x00000000000000000000000000000000000000cc", "value": "0x0", "gas": "0x40", "input": "0x"},
+            "result": null,
+            "error": "Reverted",
+            "traceAddress": [1]
+        }
+    ],
+    "vmTrace": {
+        "code": "0x600154600255",
+        "ops": [
+            {"pc": 0, "cost": 3, "ex": {"push": ["0x1"], "mem": null, "store": null, "used": 97}, "sub": null},
+            {"pc": 2, "cost": 2100, "ex": {"push": ["0x2a"], "mem": null, "store": null, "used": 95}, "sub": null},
+            {"pc": 3, "cost": 3, "ex": {"push": ["0x2"], "mem": null, "store": null, "used": 92}, "sub": null},
+            {"pc": 5, "cost": 20000, "ex": {"push": [], "mem": null, "store": {"key": "0x2", "val": "0x2a"}, "used": 72}, "sub": null}
+        ]
+    }
+}"#;
+
+#[test]
+fn state_diff_values() {
+    let replay: ReplayTrace = serde_json::from_str(REPLAY).unwrap();
+    let state_diff = replay.state_diff.unwrap();
+    let diff = &state_diff[&H160::from_low_u64_be(0xaa)];
+    assert_eq!(diff.balance, Diff::Same);
+    assert_eq!(diff.nonce.values(), Some((U256::from(1), U256::from(2))));
+    assert_eq!(
+        diff.storage[&H256::from_low_u64_be(2)].values(),
+        Some((H256::zero(), H256::from_low_u64_be(0x2a)))
+    );
+}
+
+#[test]
+fn vm_trace_reads_and_writes() {
+    let replay: ReplayTrace = serde_json::from_str(REPLAY).unwrap();
+    let context = H160::from_low_u64_be(0xbb);
+    let mut vm = VmAccesses::default();
+    vm.record(&replay.vm_trace.unwrap(), context);
+
+    // the slot read is recorded with the value SLOAD pushed
+    assert_eq!(
+        vm.reads[&context].get(&H256::from_low_u64_be(1)),
+        Some(&H256::from_low_u64_be(0x2a))
+    );
+    assert!(vm.accesses.storage_writes[&context].contains(&H256::from_low_u64_be(2)));
+    assert!(!vm.reads[&context].contains_key(&H256::from_low_u64_be(2)));
+}
+
+#[test]
+fn call_tree_from_flat_trace() {
+    let replay: ReplayTrace = serde_json::from_str(REPLAY).unwrap();
+    let root = call_tree(&replay.trace).unwrap();
+    assert_eq!(root.call_type, "CALL");
+    assert_eq!(root.gas_used, 0x10);
+    assert_eq!(root.calls.len(), 2);
+    assert_eq!(root.calls[0].call_type, "CREATE");
+    assert_eq!(root.calls[0].callee, H160::from_low_u64_be(0xcc));
+    assert_eq!(root.calls[0].output.to_vec(), vec![0x00]);
+    assert_eq!(root.calls[1].call_type, "STATICCALL");
+    assert!(root.calls[1].reverted);
+}
+
+#[test]
+fn vm_trace_as_struct_logs() {
+    let replay: ReplayTrace = serde_json::from_str(REPLAY).unwrap();
+    let mut logs = Vec::new();
+    assert!(struct_logs(&replay.vm_trace.unwrap(), 1, &mut |log| {
+        logs.push(log);
+        true
+    }));
+    let ops: Vec<&str> = logs.iter().map(|log| log.op.as_str()).collect();
+    assert_eq!(ops, vec!["PUSH1", "SLOAD", "PUSH1", "SSTORE"]);
+
+    // the stack before SSTORE holds the value loaded and the key pushed
+    let sstore = &logs[3];
+    assert_eq!(sstore.stack, Some(vec![U256::from(0x2a), U256::from(2)]));
+    assert_eq!(sstore.gas, 20072);
+    assert_eq!(sstore.gas_cost, 20000);
+    assert!(logs.iter().all(|log| log.depth == 1 && log.error.is_none()));
+}
+
+#[tokio::test]
+async fn state_diff_after_earlier_transactions() {
+    let tx_hash = H256::from_low_u64_be(2);
+    let block_hash = H256::from_low_u64_be(10);
+    let (sender, contract) = (H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    // the second transaction of the block, the first one paid the sender and deployed
+    // the contract
+    let transaction = Transaction {
+        hash: tx_hash,
+        from: sender,
+        to: Some(contract),
+        block_hash: Some(block_hash),
+        block_number: Some(U64::from(10)),
+        transaction_index: Some(U64::from(1)),
+        gas_price: Some(U256::from(1)),
+        chain_id: Some(U256::from(1)),
+        ..Default::default()
+    };
+    let block: Block<Transaction> = Block {
+        hash: Some(block_hash),
+        number: Some(U64::from(10)),
+        author: Some(H160::from_low_u64_be(0xcc)),
+        mix_hash: Some(H256::zero()),
+        ..Default::default()
+    };
+    let receipt = TransactionReceipt {
+        transaction_hash: tx_hash,
+        status: Some(U64::from(1)),
+        gas_used: Some(U256::from(0x10)),
+        ..Default::default()
+    };
+    let block_replay = json!([
+        {
+            "transactionHash": H256::from_low_u64_be(1),
+            "stateDiff": {
+                format!("{:?}", sender): {"balance": {"*": {"from": "0x1", "to": "0x5"}}, "nonce": "=", "code": "=", "storage": {}},
+                format!("{:?}", contract): {"balance": "=", "nonce": {"+": "0x1"}, "code": {"+": "0x6001"}, "storage": {}}
+            }
+        },
+        {"transactionHash": tx_hash, "stateDiff": {}}
+    ]);
+    let (url, _) = common::fake_node(Duration::ZERO, move |_, request| {
+        Reply::Result(match request["method"].as_str().unwrap() {
+            "eth_getTransactionByHash" => serde_json::to_value(&transaction).unwrap(),
+            "eth_getBlockByHash" => serde_json::to_value(&block).unwrap(),
+            "eth_getTransactionReceipt" => serde_json::to_value(&receipt).unwrap(),
+            "trace_replayTransaction" => serde_json::from_str(REPLAY).unwrap(),
+            "trace_replayBlockTransactions" => block_replay.clone(),
+            // values at the end of the previous block
+            "eth_getBalance" => json!("0x7"),
+            "eth_getTransactionCount" => json!("0x0"),
+            "eth_getCode" => json!("0x"),
+            _ => Value::Null,
+        })
+    })
+    .await;
+    let provider = Provider::<Http>::try_from(url).unwrap();
+    let vector = extract_eth_transaction_test_vector(&provider, tx_hash, None, Backend::Parity)
+        .await
+        .unwrap();
+
+    assert_eq!(vector.prestate[&sender].balance, U256::from(5));
+    assert_eq!(vector.prestate[&sender].nonce, 1);
+    assert_eq!(vector.poststate[&sender].nonce, 2);
+    assert_eq!(vector.prestate[&contract].nonce, 1);
+    assert_eq!(
+        vector.prestate[&contract].code,
+        Bytes::from(vec![0x60, 0x01])
+    );
+    // untouched by the first transaction
+    assert_eq!(vector.prestate[&contract].balance, U256::from(7));
+}
+
+#[tokio::test]
+async fn vm_trace_replayed_once() {
+    let tx_hash = H256::from_low_u64_be(1);
+    let block_hash = H256::from_low_u64_be(10);
+    let (sender, contract) = (H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let transaction = Transaction {
+        hash: tx_hash,
+        from: sender,
+        to: Some(contract),
+        block_hash: Some(block_hash),
+        block_number: Some(U64::from(10)),
+        transaction_index: Some(U64::from(0)),
+        gas: U256::from(0x100),
+        gas_price: Some(U256::from(1)),
+        chain_id: Some(U256::from(1)),
+        ..Default::default()
+    };
+    let block: Block<Transaction> = Block {
+        hash: Some(block_hash),
+        number: Some(U64::from(10)),
+        author: Some(H160::from_low_u64_be(0xcc)),
+        mix_hash: Some(H256::zero()),
+        transactions: vec![transaction.clone()],
+        ..Default::default()
+    };
+    let receipt = TransactionReceipt {
+        transaction_hash: tx_hash,
+        status: Some(U64::from(1)),
+        gas_used: Some(U256::from(0x10)),
+        ..Default::default()
+    };
+    let (url, requests) = common::fake_node(Duration::ZERO, move |_, request| {
+        Reply::Result(match request["method"].as_str().unwrap() {
+            "eth_getTransactionByHash" => serde_json::to_value(&transaction).unwrap(),
+            "eth_getBlockByHash" => serde_json::to_value(&block).unwrap(),
+            "eth_getTransactionReceipt" => serde_json::to_value(&receipt).unwrap(),
+            "trace_replayTransaction" => serde_json::from_str(REPLAY).unwrap(),
+            "eth_getBalance" => json!("0x10000"),
+            "eth_getTransactionCount" => json!("0x1"),
+            "eth_getCode" => json!("0x"),
+            _ => Value::Null,
+        })
+    })
+    .await;
+    let provider = Provider::<Http>::try_from(url).unwrap();
+    let vector = extract_eth_transaction_test_vector(&provider, tx_hash, None, Backend::Erigon)
+        .await
+        .unwrap();
+
+    // the slot read is in the prestate, the one written by the replayed vmTrace after
+    assert_eq!(
+        vector.prestate[&contract].storage[&H256::from_low_u64_be(1)],
+        H256::from_low_u64_be(0x2a)
+    );
+    assert_eq!(
+        vector.poststate[&contract].storage[&H256::from_low_u64_be(2)],
+        H256::from_low_u64_be(0x2a)
+    );
+    let methods = common::methods(&requests);
+    for method in ["eth_getTransactionByHash", "trace_replayTransaction"] {
+        assert_eq!(
+            methods.iter().filter(|m| *m == method).count(),
+            1,
+            "{}",
+            method
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use std::collections::BTreeMap;
+
+use ethers::types::H256;
+use fevm_test_vectors::mock::KAMT_CONFIG;
+use fevm_test_vectors::storage_stats::kamt_stats;
+
+#[test]
+fn kamt_stats_grow_with_storage() {
+    let mut storage = BTreeMap::new();
+    let empty = kamt_stats(&storage, &KAMT_CONFIG).unwrap();
+    assert_eq!(empty.entries, 0);
+    assert_eq!(empty.depth, 0);
+
+    for slot in 0..256 {
+        storage.insert(H256::from_low_u64_be(slot), H256::from_low_u64_be(slot + 1));
+    }
+    let stats = kamt_stats(&storage, &KAMT_CONFIG).unwrap();
+    assert_eq!(stats.entries, 256);
+    assert!(stats.depth > 1);
+    assert!(stats.nodes >= stats.depth);
+    assert!(stats.size > 0);
+}
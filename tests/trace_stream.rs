@@ -0,0 +1,42 @@
+use std::io::Cursor;
+
+use fevm_test_vectors::extractor::trace_stream::StructLogStream;
+
+const TRACE: &str = r#"{
+    "gas": 21500,
+    "failed": false,
+    "returnValue": "00ff",
+    "structLogs": [
+        {"pc": 0, "op": "PUSH1", "gas": 100, "gasCost": 3, "depth": 1, "stack": []},
+        {"pc": 2, "op": "CREATE", "gas": 97, "gasCost": 32000, "depth": 1, "stack": ["0x0", "0x0", "0x0"]},
+        {"pc": 0, "op": "STOP", "gas": 50, "gasCost": 0, "depth": 2, "stack": []},
+        {"pc": 3, "op": "POP", "gas": 40, "gasCost": 2, "depth": 1, "stack": ["0xabcd"]}
+    ]
+}"#;
+
+#[tokio::test]
+async fn struct_logs_are_streamed_in_order() {
+    let mut logs = StructLogStream::new(Cursor::new(TRACE.as_bytes().to_vec()));
+    assert_eq!(logs.next().await.unwrap().op, "PUSH1");
+    assert_eq!(logs.next().await.unwrap().op, "CREATE");
+
+    // looking ahead doesn't consume the logs in between
+    let back = logs.find_ahead(|log| log.depth == 1).await.unwrap();
+    assert_eq!(back.op, "POP");
+    assert_eq!(logs.peek().await.unwrap().op, "STOP");
+    assert_eq!(logs.next().await.unwrap().op, "STOP");
+    assert_eq!(logs.next().await.unwrap().op, "POP");
+    assert!(logs.next().await.is_none());
+
+    let summary = logs.finish().unwrap();
+    assert_eq!(summary.gas, 21500);
+    assert!(!summary.failed);
+    assert_eq!(summary.return_value.to_vec(), vec![0x00, 0xff]);
+}
+
+#[tokio::test]
+async fn malformed_trace_fails_on_finish() {
+    let mut logs = StructLogStream::new(Cursor::new(b"{\"structLogs\": [{]}".to_vec()));
+    assert!(logs.next().await.is_none());
+    assert!(logs.finish().is_err());
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{methods, Reply, TempDir};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Transaction, H256, U64};
+use fevm_test_vectors::extractor::backend::Backend;
+use fevm_test_vectors::extractor::batch::TxFilter;
+use fevm_test_vectors::extractor::types::EthTransactionTestVector;
+use fevm_test_vectors::extractor::watch::{
+    VectorBlock, WatchState, Watcher, MAX_ATTEMPTS, REORG_DEPTH,
+};
+use serde_json::{json, Value};
+
+#[test]
+fn reorged_blocks_discard_their_vectors() {
+    let mut state = WatchState::default();
+    for number in 1..=3 {
+        let hash = H256::from_low_u64_be(number);
+        state.push_block(number, hash);
+        state.vectors.insert(
+            H256::from_low_u64_be(100 + number),
+            VectorBlock { number, hash },
+        );
+    }
+
+    let discarded = state.discard_from(2);
+    assert_eq!(
+        discarded,
+        vec![H256::from_low_u64_be(102), H256::from_low_u64_be(103)]
+    );
+    assert_eq!(state.blocks.keys().copied().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(state.vectors.len(), 1);
+
+    // blocks too old to be reorged are forgotten along with their vectors
+    state.push_block(2 + REORG_DEPTH, H256::zero());
+    assert_eq!(state.blocks.len(), 1);
+    assert!(state.vectors.is_empty());
+}
+
+/// Blocks of the fake chain by number, the head is the last one.
+type Chain = Arc<Mutex<BTreeMap<u64, Block<Transaction>>>>;
+
+fn block(hash: u64, parent: u64, transactions: Vec<Transaction>) -> Block<Transaction> {
+    Block {
+        hash: Some(H256::from_low_u64_be(hash)),
+        parent_hash: H256::from_low_u64_be(parent),
+        transactions,
+        ..Default::default()
+    }
+}
+
+/// Fake node serving `chain`, it knows no transaction.
+async fn chain_node(chain: Chain) -> (Provider<Http>, common::Requests) {
+    let (url, requests) = common::fake_node(Duration::ZERO, move |_, request| {
+        let chain = chain.lock().unwrap();
+        Reply::Result(match request["method"].as_str().unwrap() {
+            "eth_blockNumber" => json!(U64::from(*chain.keys().next_back().unwrap())),
+            "eth_getBlockByNumber" => {
+                let number: U64 = serde_json::from_value(request["params"][0].clone()).unwrap();
+                serde_json::to_value(&chain[&number.as_u64()]).unwrap()
+            }
+            _ => Value::Null,
+        })
+    })
+    .await;
+    (Provider::<Http>::try_from(url).unwrap(), requests)
+}
+
+fn watcher<'a>(
+    provider: &'a Provider<Http>,
+    out_dir: &'a Path,
+) -> Watcher<'a, Http, impl Fn(&Path, EthTransactionTestVector) -> anyhow::Result<()>> {
+    Watcher {
+        provider,
+        out_dir,
+        filter: TxFilter::default(),
+        cache: None,
+        backend: Backend::Geth,
+        write: |_: &Path, _: EthTransactionTestVector| Ok(()),
+        state: WatchState::default(),
+    }
+}
+
+#[tokio::test]
+async fn watch_retries_failed_blocks() {
+    let tx = Transaction {
+        hash: H256::from_low_u64_be(0x100),
+        ..Default::default()
+    };
+    let chain: Chain = Arc::new(Mutex::new(BTreeMap::from([(1, block(1, 0, vec![tx]))])));
+    let (provider, requests) = chain_node(chain.clone()).await;
+    let dir = TempDir::new("watch-retry");
+    let mut watcher = watcher(&provider, &dir);
+
+    // the transaction isn't found, so the block isn't processed
+    watcher.poll(Some(1)).await.unwrap();
+    assert!(watcher.state.blocks.is_empty());
+    assert_eq!(watcher.state.retry, Some(1));
+
+    // the failed block is processed again even though the head moved on
+    chain.lock().unwrap().insert(2, block(2, 1, vec![]));
+    watcher.poll(None).await.unwrap();
+    let saved = WatchState::load(&dir).unwrap();
+    assert_eq!(saved.retry, Some(1));
+    let tx_requests = methods(&requests)
+        .iter()
+        .filter(|method| *method == "eth_getTransactionByHash")
+        .count();
+    assert_eq!(tx_requests, 2);
+}
+
+#[tokio::test]
+async fn watch_gives_up_on_failing_transactions() {
+    let tx = Transaction {
+        hash: H256::from_low_u64_be(0x100),
+        ..Default::default()
+    };
+    let chain: Chain = Arc::new(Mutex::new(BTreeMap::from([
+        (1, block(1, 0, vec![tx.clone()])),
+        (2, block(2, 1, vec![])),
+    ])));
+    let (provider, _) = chain_node(chain).await;
+    let dir = TempDir::new("watch-give-up");
+    let mut watcher = watcher(&provider, &dir);
+
+    for _ in 1..MAX_ATTEMPTS {
+        watcher.poll(Some(1)).await.unwrap();
+        assert_eq!(watcher.state.retry, Some(1));
+    }
+    // the last attempt records the transaction as failed and moves on
+    watcher.poll(None).await.unwrap();
+    let saved = WatchState::load(&dir).unwrap();
+    assert_eq!(saved.retry, None);
+    assert!(saved.attempts.is_empty());
+    assert!(saved.failed.contains_key(&tx.hash));
+    assert_eq!(saved.blocks.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[tokio::test]
+async fn watch_rewinds_reorged_blocks() {
+    let chain: Chain = Arc::new(Mutex::new(BTreeMap::from([
+        (1, block(1, 0, vec![])),
+        (2, block(2, 1, vec![])),
+    ])));
+    let (provider, _) = chain_node(chain.clone()).await;
+    let dir = TempDir::new("watch-reorg");
+    let mut watcher = watcher(&provider, &dir);
+    watcher.poll(Some(1)).await.unwrap();
+    assert_eq!(watcher.state.blocks.len(), 2);
+
+    // a vector extracted from block 2
+    let tx_hash = H256::from_low_u64_be(0x200);
+    let vector = dir.join(format!("{:?}.json", tx_hash));
+    std::fs::write(&vector, "{}").unwrap();
+    watcher.state.vectors.insert(
+        tx_hash,
+        VectorBlock {
+            number: 2,
+            hash: H256::from_low_u64_be(2),
+        },
+    );
+
+    // block 2 is replaced, the new block 3 builds on it
+    chain.lock().unwrap().insert(2, block(0x22, 1, vec![]));
+    chain.lock().unwrap().insert(3, block(3, 0x22, vec![]));
+    watcher.poll(None).await.unwrap();
+    assert!(!vector.exists());
+    assert!(watcher.state.vectors.is_empty());
+    assert_eq!(
+        watcher.state.blocks,
+        BTreeMap::from([
+            (1, H256::from_low_u64_be(1)),
+            (2, H256::from_low_u64_be(0x22)),
+            (3, H256::from_low_u64_be(3)),
+        ])
+    );
+}